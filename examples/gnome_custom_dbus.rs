@@ -2,23 +2,40 @@
 use {
     std::time::Duration,
     tracing::Level,
-    whatawhat_lib::{WindowManager as _, gnome::GnomeWindowWatcher, config::{WatcherConfig, GnomeDbusConfig}},
+    whatawhat_lib::{
+        WindowManager as _,
+        config::{GnomeDbusConfig, GnomeWindowSource, WatcherConfig},
+        gnome::GnomeWindowWatcher,
+    },
 };
 
 #[cfg(feature = "gnome")]
 fn main() {
     // Create custom GNOME DBus configuration
     let custom_gnome_config = GnomeDbusConfig {
+        // Read the focused window from the extension (the default)
+        window_source: GnomeWindowSource::Extension,
         // Custom window data DBus settings (these are the defaults)
         window_service: "org.gnome.Shell".to_string(),
         window_path: "/org/gnome/shell/extensions/WhatawhatFocusedWindow".to_string(),
         window_interface: "org.gnome.shell.extensions.WhatawhatFocusedWindow".to_string(),
         window_method: "Get".to_string(),
+        // Poll the method instead of listening for a focus change signal
+        window_signal: None,
         // Custom idle time DBus settings (these are the defaults)
         idle_service: "org.gnome.Shell".to_string(),
         idle_path: "/org/gnome/Mutter/IdleMonitor/Core".to_string(),
         idle_interface: "org.gnome.Mutter.IdleMonitor".to_string(),
         idle_method: "GetIdletime".to_string(),
+        // Custom screen lock DBus settings (these are the defaults)
+        screensaver_service: "org.gnome.ScreenSaver".to_string(),
+        screensaver_path: "/org/gnome/ScreenSaver".to_string(),
+        screensaver_interface: "org.gnome.ScreenSaver".to_string(),
+        screensaver_method: "GetActive".to_string(),
+        // Custom monitor DBus settings (these are the defaults)
+        display_config_service: "org.gnome.Mutter.DisplayConfig".to_string(),
+        display_config_path: "/org/gnome/Mutter/DisplayConfig".to_string(),
+        display_config_interface: "org.gnome.Mutter.DisplayConfig".to_string(),
     };
 
     let config = WatcherConfig {
@@ -27,15 +44,21 @@ fn main() {
         ..Default::default()
     };
 
-    let mut window_manager = GnomeWindowWatcher::new(config).unwrap();
+    let mut window_manager = GnomeWindowWatcher::from_config(config).unwrap();
 
     tracing_subscriber::fmt()
         .with_max_level(Level::DEBUG)
         .init();
 
     println!("Using custom GNOME DBus configuration:");
-    println!("Window service: {}", window_manager.gnome_dbus_config.window_service);
-    println!("Idle service: {}", window_manager.gnome_dbus_config.idle_service);
+    println!(
+        "Window service: {}",
+        window_manager.gnome_dbus_config.window_service
+    );
+    println!(
+        "Idle service: {}",
+        window_manager.gnome_dbus_config.idle_service
+    );
 
     loop {
         let active_window = window_manager.get_active_window_data().unwrap();
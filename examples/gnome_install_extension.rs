@@ -1,14 +1,9 @@
-use std::{path::PathBuf, str::FromStr};
-
-use whatawhat_lib::gnome_install;
+#[cfg(feature = "gnome")]
+fn main() {
+    whatawhat_lib::gnome_install::install_bundled_gnome_extension().unwrap();
+}
 
+#[cfg(not(feature = "gnome"))]
 fn main() {
-    let _path = PathBuf::from_str("./focused-window-dbus@whatawhat.anoromi.com.shell-extension.zip")
-        .unwrap();
-    gnome_install::install_gnome_extension(
-        PathBuf::from_str("./focused-window-dbus@whatawhat.anoromi.com.shell-extension.zip")
-            .unwrap()
-            .as_path(),
-    )
-    .unwrap();
+    println!("Not supported");
 }
@@ -0,0 +1,38 @@
+#[cfg(all(feature = "async", feature = "gnome"))]
+use {
+    std::time::Duration,
+    tracing::Level,
+    whatawhat_lib::{
+        async_manager::AsyncWindowManager as _, config::WatcherConfig, gnome::GnomeWindowWatcher,
+    },
+};
+
+#[cfg(all(feature = "async", feature = "gnome"))]
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_max_level(Level::DEBUG)
+        .init();
+
+    let config = WatcherConfig {
+        idle_timeout: Duration::from_secs(10),
+        ..Default::default()
+    };
+    let mut window_manager = tokio::task::spawn_blocking(|| GnomeWindowWatcher::from_config(config))
+        .await
+        .unwrap()
+        .unwrap();
+
+    loop {
+        let active_window = window_manager.get_active_window_data().await.unwrap();
+        println!("Active window: {:?}", active_window);
+        let idle_time = window_manager.is_idle().await.unwrap();
+        println!("Idle time: {:?}", idle_time);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+#[cfg(not(all(feature = "async", feature = "gnome")))]
+fn main() {
+    println!("Not supported");
+}
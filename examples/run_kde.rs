@@ -11,7 +11,7 @@ fn main() {
         idle_timeout: Duration::from_secs(10),
         ..Default::default()
     };
-    let mut window_manager = KdeWindowManager::new(config).unwrap();
+    let mut window_manager = KdeWindowManager::from_config(config).unwrap();
 
     tracing_subscriber::fmt()
         // all spans/events with a level higher than TRACE (e.g, info, warn, etc.)
@@ -0,0 +1,82 @@
+//! Async counterpart of `WindowManager` for applications running on tokio.
+//!
+//! GNOME and KDE implement it directly, making their D-Bus calls through the async connection
+//! and the remaining blocking work, like desktop entry lookups and Wayland roundtrips, on
+//! tokio's blocking thread pool. Other backends are wrapped in `BlockingWindowManager`, which
+//! runs all of their calls there.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Result;
+
+use crate::{ActiveWindowData, WindowManager, lock_shared};
+
+pub trait AsyncWindowManager {
+    fn get_active_window_data(&mut self) -> impl Future<Output = Result<ActiveWindowData>> + Send;
+
+    /// Whether the user has been inactive for longer than the idle timeout.
+    fn is_idle(&mut self) -> impl Future<Output = Result<bool>> + Send;
+
+    /// Time since the last input while the user is idle, zero while they are active. See
+    /// `WindowManager::idle_duration`.
+    fn idle_duration(&mut self) -> impl Future<Output = Result<Duration>> + Send;
+
+    /// Whether the screen is currently locked. Backends that can't detect it report `false`.
+    fn is_screen_locked(&mut self) -> impl Future<Output = Result<bool>> + Send {
+        async { Ok(false) }
+    }
+}
+
+/// Runs the calls of a blocking `WindowManager` through `tokio::task::spawn_blocking`.
+pub struct BlockingWindowManager<W> {
+    inner: Arc<Mutex<W>>,
+}
+
+impl<W> BlockingWindowManager<W>
+where
+    W: WindowManager + Send + 'static,
+{
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(inner)),
+        }
+    }
+
+    /// Creates the manager on the blocking thread pool, since constructors may retry and sleep.
+    pub async fn create(create: impl FnOnce() -> Result<W> + Send + 'static) -> Result<Self> {
+        let inner = tokio::task::spawn_blocking(create).await??;
+        Ok(Self::new(inner))
+    }
+
+    async fn run<T>(&self, call: impl FnOnce(&mut W) -> Result<T> + Send + 'static) -> Result<T>
+    where
+        T: Send + 'static,
+    {
+        let inner = self.inner.clone();
+        tokio::task::spawn_blocking(move || call(&mut *lock_shared(&inner)?)).await?
+    }
+}
+
+impl<W> AsyncWindowManager for BlockingWindowManager<W>
+where
+    W: WindowManager + Send + 'static,
+{
+    async fn get_active_window_data(&mut self) -> Result<ActiveWindowData> {
+        self.run(|inner| inner.get_active_window_data()).await
+    }
+
+    async fn is_idle(&mut self) -> Result<bool> {
+        self.run(|inner| inner.is_idle()).await
+    }
+
+    async fn idle_duration(&mut self) -> Result<Duration> {
+        self.run(|inner| inner.idle_duration()).await
+    }
+
+    async fn is_screen_locked(&mut self) -> Result<bool> {
+        self.run(|inner| inner.is_screen_locked()).await
+    }
+}
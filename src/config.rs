@@ -2,15 +2,47 @@ use std::time::Duration;
 
 use derive_builder::Builder;
 
-use crate::simple_cache::CacheConfig;
+use crate::{rules::WindowRule, simple_cache::CacheConfig};
 
 const DEFAULT_CACHE_CONFIG: CacheConfig = CacheConfig {
     ttl: Duration::from_secs(60 * 10),
     max_size: 100,
+    negative_ttl: None,
+    stats_log_interval: None,
 };
 
+/// `cache_config` of `WatcherConfig::recommended`, which config files start from.
+#[cfg(feature = "config-file")]
+pub(crate) fn watcher_cache_config() -> CacheConfig {
+    DEFAULT_CACHE_CONFIG
+}
+
+/// Where the GNOME watcher reads the focused window from.
+#[cfg_attr(
+    feature = "config-file",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GnomeWindowSource {
+    /// The bundled extension, through the `window_*` settings.
+    #[default]
+    Extension,
+    /// `GetWindows` of org.gnome.Shell.Introspect, which needs no extension but is only answered
+    /// when GNOME Shell allows it, for example with `org.gnome.shell introspect` set. Falls back
+    /// to the extension once it's refused.
+    PreferIntrospect,
+}
+
+#[cfg_attr(
+    feature = "config-file",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
 #[derive(Clone)]
 pub struct GnomeDbusConfig {
+    /// Where the focused window is read from
+    pub window_source: GnomeWindowSource,
     /// The DBus service name for window data calls
     pub window_service: String,
     /// The DBus path for window data calls
@@ -19,6 +51,10 @@ pub struct GnomeDbusConfig {
     pub window_interface: String,
     /// The DBus method name for window data calls
     pub window_method: String,
+    /// Signal emitted by the window service on focus changes, with the same JSON payload as
+    /// `window_method`. If set, the focused window is taken from the latest signal and
+    /// `window_method` is only called until the first one arrives.
+    pub window_signal: Option<String>,
     /// The DBus service name for idle time calls
     pub idle_service: String,
     /// The DBus path for idle time calls
@@ -27,27 +63,273 @@ pub struct GnomeDbusConfig {
     pub idle_interface: String,
     /// The DBus method name for idle time calls
     pub idle_method: String,
+    /// The DBus service name for screen lock calls
+    pub screensaver_service: String,
+    /// The DBus path for screen lock calls
+    pub screensaver_path: String,
+    /// The DBus interface for screen lock calls
+    pub screensaver_interface: String,
+    /// The DBus method name for screen lock calls
+    pub screensaver_method: String,
+    /// The DBus service name for monitor calls
+    pub display_config_service: String,
+    /// The DBus path for monitor calls
+    pub display_config_path: String,
+    /// The DBus interface for monitor calls, which has to provide Mutter's `GetCurrentState`
+    pub display_config_interface: String,
 }
 
 impl Default for GnomeDbusConfig {
     fn default() -> Self {
         Self {
+            window_source: GnomeWindowSource::Extension,
             window_service: "org.gnome.Shell".to_string(),
             window_path: "/org/gnome/shell/extensions/WhatawhatFocusedWindow".to_string(),
             window_interface: "org.gnome.shell.extensions.WhatawhatFocusedWindow".to_string(),
             window_method: "Get".to_string(),
+            window_signal: None,
             idle_service: "org.gnome.Shell".to_string(),
             idle_path: "/org/gnome/Mutter/IdleMonitor/Core".to_string(),
             idle_interface: "org.gnome.Mutter.IdleMonitor".to_string(),
             idle_method: "GetIdletime".to_string(),
+            screensaver_service: "org.gnome.ScreenSaver".to_string(),
+            screensaver_path: "/org/gnome/ScreenSaver".to_string(),
+            screensaver_interface: "org.gnome.ScreenSaver".to_string(),
+            screensaver_method: "GetActive".to_string(),
+            display_config_service: "org.gnome.Mutter.DisplayConfig".to_string(),
+            display_config_path: "/org/gnome/Mutter/DisplayConfig".to_string(),
+            display_config_interface: "org.gnome.Mutter.DisplayConfig".to_string(),
+        }
+    }
+}
+
+/// Service of the GNOME desktop portal backend.
+const GNOME_PORTAL_SERVICE: &str = "org.freedesktop.impl.portal.desktop.gnome";
+/// Path the desktop portal objects are exported on.
+const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+
+impl GnomeDbusConfig {
+    /// Calls the GNOME portal backend instead of GNOME Shell, for sessions where
+    /// `org.gnome.Shell` can't be reached, like Flatpak sandboxes. Interfaces and methods stay
+    /// the same, so the portal side has to expose them.
+    pub fn xdg_portal() -> Self {
+        Self {
+            window_service: GNOME_PORTAL_SERVICE.to_string(),
+            window_path: PORTAL_PATH.to_string(),
+            idle_service: GNOME_PORTAL_SERVICE.to_string(),
+            idle_path: PORTAL_PATH.to_string(),
+            screensaver_service: GNOME_PORTAL_SERVICE.to_string(),
+            screensaver_path: PORTAL_PATH.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// The default config with the window service and path overridden by the
+    /// `WHATAWHAT_GNOME_SERVICE` and `WHATAWHAT_GNOME_PATH` environment variables, if set.
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Ok(service) = std::env::var("WHATAWHAT_GNOME_SERVICE") {
+            config.window_service = service;
+        }
+        if let Ok(path) = std::env::var("WHATAWHAT_GNOME_PATH") {
+            config.window_path = path;
+        }
+        config
+    }
+}
+
+#[cfg_attr(
+    feature = "config-file",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+#[derive(Clone, Debug)]
+pub struct MacosConfig {
+    /// If true, the idle time is read from the IOHIDSystem registry entry, which accounts for all
+    /// HID devices. `CGEventSource` is used if it's false or IOKit fails.
+    pub use_iokit_idle: bool,
+}
+
+impl Default for MacosConfig {
+    fn default() -> Self {
+        Self {
+            use_iokit_idle: true,
+        }
+    }
+}
+
+#[cfg_attr(
+    feature = "config-file",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+#[derive(Clone, Debug)]
+pub struct WindowsConfig {
+    /// Most windows `get_window_list` reports. Windows further down the Z-order are left out.
+    pub window_list_limit: usize,
+}
+
+impl Default for WindowsConfig {
+    fn default() -> Self {
+        Self {
+            window_list_limit: 64,
+        }
+    }
+}
+
+/// What `GenericWindowManager::get_active_window_data` reports while the screen is locked.
+#[cfg_attr(
+    feature = "config-file",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LockedBehavior {
+    /// Report the active window as usual.
+    #[default]
+    Ignore,
+    /// Return an error.
+    Error,
+    /// Return `ActiveWindowData::locked()`.
+    Sentinel,
+}
+
+/// What `GenericWindowManager` reports for the windows of excluded apps.
+#[cfg_attr(
+    feature = "config-file",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExcludedAppMode {
+    /// The window with an empty title, so the time is still attributed to the app.
+    #[default]
+    RedactTitle,
+    /// A window with an empty title and no other fields, so not even the app is known.
+    RedactAll,
+}
+
+/// How `GenericWindowManager` reports window titles.
+#[cfg_attr(
+    feature = "config-file",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TitleMode {
+    /// The title as the window has it.
+    #[default]
+    Full,
+    /// The lowercase hex SHA-256 of the title, so equal titles can still be grouped.
+    Hashed,
+    /// An empty title.
+    Empty,
+}
+
+/// How `GenericWindowManager` retries failed `get_active_window_data`, `sample` and `is_idle`
+/// calls. `NoActiveWindow` isn't retried, and the last error is returned once the attempts run
+/// out.
+#[cfg_attr(
+    feature = "config-file",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case", deny_unknown_fields)
+)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RetryPolicy {
+    /// Errors are returned right away.
+    #[default]
+    None,
+    /// Retries up to this many times without waiting.
+    Immediate(u32),
+    /// Waits `base_delay` before the first retry and doubles the delay for every further one, up
+    /// to `max_delay`.
+    ExponentialBackoff {
+        /// Most attempts, including the first one.
+        max_attempts: u32,
+        #[cfg_attr(feature = "config-file", serde(with = "crate::config_file"))]
+        base_delay: Duration,
+        #[cfg_attr(feature = "config-file", serde(with = "crate::config_file"))]
+        max_delay: Duration,
+    },
+}
+
+impl RetryPolicy {
+    /// How long to wait before the next attempt after `failed_attempts` failed ones. `None` if
+    /// no attempts are left.
+    pub fn delay(self, failed_attempts: u32) -> Option<Duration> {
+        match self {
+            Self::None => None,
+            Self::Immediate(retries) => (failed_attempts <= retries).then_some(Duration::ZERO),
+            Self::ExponentialBackoff {
+                max_attempts,
+                base_delay,
+                max_delay,
+            } => (failed_attempts < max_attempts).then(|| {
+                base_delay
+                    .saturating_mul(2u32.saturating_pow(failed_attempts.saturating_sub(1)))
+                    .min(max_delay)
+            }),
+        }
+    }
+}
+
+/// Where the X11 watcher reads the idle time from.
+#[cfg_attr(
+    feature = "config-file",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum X11IdleSource {
+    /// The MIT-SCREEN-SAVER extension. Falls back to `Sync` if the reported idle time stays
+    /// constant, which happens on some setups with screensaver inhibitors.
+    #[default]
+    ScreenSaver,
+    /// The `IDLETIME` system counter of the SYNC extension.
+    Sync,
+}
+
+/// How the backends that poll the idle time compare it with `WatcherConfig::idle_timeout`.
+#[cfg_attr(
+    feature = "config-file",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IdleSensitivity {
+    /// Idle as soon as the time since the last input exceeds the timeout.
+    #[default]
+    Exact,
+    /// The time since the last input has to exceed the timeout by this much, so readings that
+    /// jitter around the threshold don't flip between idle and active.
+    Coarse(#[cfg_attr(feature = "config-file", serde(with = "crate::config_file"))] Duration),
+}
+
+impl IdleSensitivity {
+    pub fn is_idle(self, since_input: Duration, idle_timeout: Duration) -> bool {
+        since_input > self.threshold(idle_timeout)
+    }
+
+    /// Time since the last input beyond which the user is idle.
+    pub fn threshold(self, idle_timeout: Duration) -> Duration {
+        match self {
+            Self::Exact => idle_timeout,
+            Self::Coarse(tolerance) => idle_timeout.saturating_add(tolerance),
         }
     }
 }
 
-#[derive(Clone, Default, Builder)]
+#[cfg_attr(
+    feature = "config-file",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default = "WatcherConfig::recommended", deny_unknown_fields)
+)]
+#[derive(Clone, Builder)]
+#[builder(build_fn(name = "build_unchecked"))]
 pub struct WatcherConfig {
     /// The timeout for the idle watcher.
     #[builder(default = Duration::from_secs(1))]
+    #[cfg_attr(feature = "config-file", serde(with = "crate::config_file"))]
     pub idle_timeout: Duration,
     /// The cache used for extra information like application names.
     #[builder(default = DEFAULT_CACHE_CONFIG)]
@@ -56,10 +338,434 @@ pub struct WatcherConfig {
     /// Only relevant for macOS, because if not, the watcher will spawn an osascript process.
     #[builder(default = true)]
     pub am_on_main_thread: bool,
+    /// How the idle time is compared with `idle_timeout` on X11 and macOS. Wayland compositors
+    /// apply the timeout themselves.
+    #[builder(default)]
+    pub idle_sensitivity: IdleSensitivity,
     /// The interval for the idle watcher.
     #[builder(default = Duration::from_secs(1))]
+    #[cfg_attr(feature = "config-file", serde(with = "crate::config_file"))]
     pub idle_check_interval: Duration,
     /// Configuration for GNOME DBus calls
     #[builder(default)]
     pub gnome_dbus_config: GnomeDbusConfig,
+    /// How many more times the GNOME watcher tries to reach the extension if the first attempt
+    /// fails. Zero means the extension is tried exactly once.
+    #[builder(default = 2)]
+    pub gnome_startup_retries: u32,
+    /// Delay between the attempts to reach the GNOME extension.
+    #[builder(default = Duration::from_secs(3))]
+    #[cfg_attr(feature = "config-file", serde(with = "crate::config_file"))]
+    pub gnome_startup_retry_delay: Duration,
+    /// How many times in a row the GNOME watcher tries to reconnect after the extension stops
+    /// answering, for example when GNOME Shell restarts, before it reports the error. Until then
+    /// the failures are reported as `WatcherError::Transient`.
+    #[builder(default = 5)]
+    pub gnome_recovery_attempts: u32,
+    /// If true, the GNOME watcher also enables the extension when it reconnects.
+    #[builder(default = false)]
+    pub gnome_reactivate_extension: bool,
+    /// How long the GNOME watcher waits for a D-Bus reply before failing the call with
+    /// `WatcherError::Transient`.
+    #[builder(default = Duration::from_secs(3))]
+    #[cfg_attr(feature = "config-file", serde(with = "crate::config_file"))]
+    pub gnome_dbus_timeout: Duration,
+    /// If true, the GNOME watcher reports the monitor of the window, read from Mutter's
+    /// DisplayConfig. The monitor is left out if that fails.
+    #[builder(default = false)]
+    pub include_monitor_info: bool,
+    /// Configuration for macOS
+    #[builder(default)]
+    pub macos_config: MacosConfig,
+    /// Configuration for Windows
+    #[builder(default)]
+    pub windows_config: WindowsConfig,
+    /// What to report as the active window while the screen is locked.
+    #[builder(default)]
+    pub behavior_when_locked: LockedBehavior,
+    /// How `GenericWindowManager` retries failed calls.
+    #[builder(default)]
+    pub retry_policy: RetryPolicy,
+    /// If true, a locked screen is reported as idle regardless of the time since the last input.
+    /// Currently only relevant for macOS.
+    #[builder(default = false)]
+    pub treat_lock_as_idle: bool,
+    /// The source of the idle time on X11.
+    #[builder(default)]
+    pub x11_idle_source: X11IdleSource,
+    /// If false, application names and paths aren't resolved from desktop entries or executable
+    /// metadata, which avoids loading them at startup.
+    #[builder(default = true)]
+    pub resolve_app_metadata: bool,
+    /// How often a failed lookup may check the application dirs for changes. The desktop entries
+    /// are reloaded if they changed. Only relevant for Linux.
+    #[builder(default = Duration::from_secs(30))]
+    #[cfg_attr(feature = "config-file", serde(with = "crate::config_file"))]
+    pub desktop_entries_refresh_interval: Duration,
+    /// Locales of the app names read from desktop entries, most preferred first, like `de_DE` or
+    /// `de`. Names fall back to the unlocalized one. If empty, the locales are derived from
+    /// `LC_MESSAGES` and `LANG`. Only relevant for Linux.
+    #[builder(default)]
+    pub locales: Vec<String>,
+    /// Size in pixels of the icon files `get_app_icon` looks for. The closest size is used if the
+    /// theme has no exact match. Only relevant for Linux.
+    #[builder(default = 48)]
+    pub icon_size: u32,
+    /// Icon theme `get_app_icon` looks in before its parents and hicolor. If `None`, only hicolor
+    /// is used. Only relevant for Linux.
+    #[builder(default)]
+    pub icon_theme: Option<String>,
+    /// If true, Windows idle time is tracked through WinEvent notifications instead of polling
+    /// `GetLastInputInfo`.
+    #[builder(default = false)]
+    pub windows_event_idle: bool,
+    /// Name of the Wayland seat to track idle time on. If `None`, every seat is tracked and the
+    /// user is idle only when all of them are.
+    #[builder(default)]
+    pub wayland_seat_name: Option<String>,
+    /// If not empty, `GenericWindowManager` only reports windows matching one of the rules.
+    #[builder(default)]
+    pub allow_list: Vec<WindowRule>,
+    /// Windows matching any of the rules are never reported by `GenericWindowManager`.
+    #[builder(default)]
+    pub deny_list: Vec<WindowRule>,
+    /// Apps whose windows `GenericWindowManager` redacts according to `excluded_app_mode`. An
+    /// entry matches the app identifier, the process path or the file name of the process path.
+    #[builder(default)]
+    pub excluded_apps: Vec<String>,
+    /// If set, the windows of all other apps are redacted like the ones of `excluded_apps`.
+    #[builder(default)]
+    pub included_apps: Option<Vec<String>>,
+    /// How the windows of excluded apps are redacted.
+    #[builder(default)]
+    pub excluded_app_mode: ExcludedAppMode,
+    /// How the titles of all reported windows are transformed.
+    #[builder(default)]
+    pub title_mode: TitleMode,
+}
+
+/// The fields the config had when `Default` was derived keep their zero values, so
+/// `..Default::default()` doesn't change behavior. That includes a zero `idle_timeout`, which
+/// `validate` rejects. `WatcherConfigBuilder` starts from the recommended values instead.
+impl Default for WatcherConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout: Duration::ZERO,
+            cache_config: CacheConfig::default(),
+            am_on_main_thread: false,
+            idle_check_interval: Duration::ZERO,
+            ..Self::recommended()
+        }
+    }
+}
+
+impl WatcherConfig {
+    /// The builder defaults, which config files start from.
+    pub(crate) fn recommended() -> Self {
+        WatcherConfigBuilder::default()
+            .build()
+            .expect("All fields have valid defaults")
+    }
+}
+
+/// Shortest `idle_timeout` `validate` accepts.
+pub const MIN_IDLE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Why a `WatcherConfig` is rejected.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// `idle_timeout` is shorter than `MIN_IDLE_TIMEOUT`.
+    IdleTimeoutTooShort(Duration),
+    IdleCheckIntervalZero,
+    /// `idle_check_interval` is longer than `idle_timeout`, so idle would be noticed late.
+    IdleCheckIntervalTooLong {
+        idle_check_interval: Duration,
+        idle_timeout: Duration,
+    },
+    /// `cache_config.ttl` is zero while `max_size` isn't, so every cached entry is expired.
+    CacheTtlZero,
+    /// A D-Bus name of `gnome_dbus_config` is empty.
+    EmptyGnomeDbusField(&'static str),
+    Builder(WatcherConfigBuilderError),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IdleTimeoutTooShort(timeout) => write!(
+                f,
+                "idle_timeout of {timeout:?} is shorter than {MIN_IDLE_TIMEOUT:?}"
+            ),
+            Self::IdleCheckIntervalZero => write!(f, "idle_check_interval is zero"),
+            Self::IdleCheckIntervalTooLong {
+                idle_check_interval,
+                idle_timeout,
+            } => write!(
+                f,
+                "idle_check_interval of {idle_check_interval:?} is longer than idle_timeout of \
+                 {idle_timeout:?}"
+            ),
+            Self::CacheTtlZero => write!(f, "cache_config.ttl is zero but max_size is not"),
+            Self::EmptyGnomeDbusField(field) => write!(f, "gnome_dbus_config.{field} is empty"),
+            Self::Builder(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<WatcherConfigBuilderError> for ConfigError {
+    fn from(e: WatcherConfigBuilderError) -> Self {
+        Self::Builder(e)
+    }
+}
+
+impl WatcherConfigBuilder {
+    /// Builds the config and checks it with `WatcherConfig::validate`. Use `build_unchecked` to
+    /// skip the checks.
+    pub fn build(&self) -> Result<WatcherConfig, ConfigError> {
+        let config = self.build_unchecked()?;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+impl WatcherConfig {
+    /// Rejects values that make the watchers misbehave rather than fail, like a zero
+    /// `idle_timeout`, which makes idle notifications fire constantly.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.idle_timeout < MIN_IDLE_TIMEOUT {
+            return Err(ConfigError::IdleTimeoutTooShort(self.idle_timeout));
+        }
+        if self.idle_check_interval.is_zero() {
+            return Err(ConfigError::IdleCheckIntervalZero);
+        }
+        if self.idle_check_interval > self.idle_timeout {
+            return Err(ConfigError::IdleCheckIntervalTooLong {
+                idle_check_interval: self.idle_check_interval,
+                idle_timeout: self.idle_timeout,
+            });
+        }
+        if self.cache_config.ttl.is_zero() && self.cache_config.max_size != 0 {
+            return Err(ConfigError::CacheTtlZero);
+        }
+        let dbus = &self.gnome_dbus_config;
+        let fields = [
+            ("window_service", dbus.window_service.as_str()),
+            ("window_path", &dbus.window_path),
+            ("window_interface", &dbus.window_interface),
+            ("window_method", &dbus.window_method),
+            ("idle_service", &dbus.idle_service),
+            ("idle_path", &dbus.idle_path),
+            ("idle_interface", &dbus.idle_interface),
+            ("idle_method", &dbus.idle_method),
+            ("screensaver_service", &dbus.screensaver_service),
+            ("screensaver_path", &dbus.screensaver_path),
+            ("screensaver_interface", &dbus.screensaver_interface),
+            ("screensaver_method", &dbus.screensaver_method),
+            ("display_config_service", &dbus.display_config_service),
+            ("display_config_path", &dbus.display_config_path),
+            ("display_config_interface", &dbus.display_config_interface),
+        ];
+        if let Some((field, _)) = fields.iter().find(|(_, value)| value.is_empty()) {
+            return Err(ConfigError::EmptyGnomeDbusField(field));
+        }
+        // Unlike the other names, the signal is optional
+        if dbus.window_signal.as_deref() == Some("") {
+            return Err(ConfigError::EmptyGnomeDbusField("window_signal"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(configure: impl FnOnce(&mut WatcherConfig)) -> Result<(), ConfigError> {
+        let mut config = WatcherConfig::recommended();
+        configure(&mut config);
+        config.validate()
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        assert!(validate(|_| ()).is_ok());
+    }
+
+    #[test]
+    fn default_keeps_the_derived_values() {
+        let config = WatcherConfig::default();
+        assert_eq!(config.idle_timeout, Duration::ZERO);
+        assert_eq!(config.idle_check_interval, Duration::ZERO);
+        assert_eq!(config.cache_config.ttl, Duration::ZERO);
+        assert_eq!(config.cache_config.max_size, 0);
+        assert!(!config.am_on_main_thread);
+        // Fields added since then start from the builder defaults
+        assert!(config.resolve_app_metadata);
+        assert_eq!(config.gnome_dbus_timeout, Duration::from_secs(3));
+    }
+
+    #[test]
+    fn short_idle_timeout_is_rejected() {
+        let result = validate(|config| {
+            config.idle_timeout = MIN_IDLE_TIMEOUT - Duration::from_millis(1);
+            config.idle_check_interval = Duration::from_millis(10);
+        });
+        assert!(matches!(result, Err(ConfigError::IdleTimeoutTooShort(_))));
+
+        let result = validate(|config| {
+            config.idle_timeout = MIN_IDLE_TIMEOUT;
+            config.idle_check_interval = MIN_IDLE_TIMEOUT;
+        });
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn zero_idle_check_interval_is_rejected() {
+        let result = validate(|config| config.idle_check_interval = Duration::ZERO);
+        assert!(matches!(result, Err(ConfigError::IdleCheckIntervalZero)));
+    }
+
+    #[test]
+    fn idle_check_interval_longer_than_idle_timeout_is_rejected() {
+        let result = validate(|config| {
+            config.idle_timeout = Duration::from_secs(1);
+            config.idle_check_interval = Duration::from_secs(2);
+        });
+        assert!(matches!(
+            result,
+            Err(ConfigError::IdleCheckIntervalTooLong { .. })
+        ));
+    }
+
+    #[test]
+    fn zero_cache_ttl_is_rejected_unless_the_cache_is_disabled() {
+        let result = validate(|config| config.cache_config.ttl = Duration::ZERO);
+        assert!(matches!(result, Err(ConfigError::CacheTtlZero)));
+
+        let result = validate(|config| {
+            config.cache_config.ttl = Duration::ZERO;
+            config.cache_config.max_size = 0;
+        });
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn empty_gnome_dbus_field_is_rejected() {
+        let result = validate(|config| config.gnome_dbus_config.idle_path.clear());
+        assert!(matches!(
+            result,
+            Err(ConfigError::EmptyGnomeDbusField("idle_path"))
+        ));
+    }
+
+    #[test]
+    fn empty_window_signal_is_rejected_but_a_missing_one_is_not() {
+        let result = validate(|config| config.gnome_dbus_config.window_signal = Some("".into()));
+        assert!(matches!(
+            result,
+            Err(ConfigError::EmptyGnomeDbusField("window_signal"))
+        ));
+
+        let result = validate(|config| config.gnome_dbus_config.window_signal = None);
+        assert!(result.is_ok());
+
+        let result = validate(|config| {
+            config.gnome_dbus_config.window_signal = Some("WindowChanged".into())
+        });
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn build_validates() {
+        let result = WatcherConfigBuilder::default()
+            .idle_timeout(Duration::ZERO)
+            .build();
+        assert!(matches!(result, Err(ConfigError::IdleTimeoutTooShort(_))));
+
+        let config = WatcherConfigBuilder::default()
+            .idle_timeout(Duration::ZERO)
+            .build_unchecked();
+        assert!(config.is_ok());
+    }
+
+    #[test]
+    fn retry_delays() {
+        assert_eq!(RetryPolicy::None.delay(1), None);
+
+        let immediate = RetryPolicy::Immediate(2);
+        assert_eq!(immediate.delay(1), Some(Duration::ZERO));
+        assert_eq!(immediate.delay(2), Some(Duration::ZERO));
+        assert_eq!(immediate.delay(3), None);
+
+        let backoff = RetryPolicy::ExponentialBackoff {
+            max_attempts: 6,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+        };
+        let delays: Vec<_> = (1..=6).map(|failed| backoff.delay(failed)).collect();
+        assert_eq!(
+            delays,
+            [
+                Some(Duration::from_millis(100)),
+                Some(Duration::from_millis(200)),
+                Some(Duration::from_millis(400)),
+                // Capped at max_delay
+                Some(Duration::from_millis(500)),
+                Some(Duration::from_millis(500)),
+                // The sixth attempt was the last one
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn backoff_delay_doesnt_overflow() {
+        let backoff = RetryPolicy::ExponentialBackoff {
+            max_attempts: u32::MAX,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+        };
+        assert_eq!(backoff.delay(40), Some(Duration::from_secs(60)));
+        assert_eq!(backoff.delay(u32::MAX - 1), Some(Duration::from_secs(60)));
+        assert_eq!(backoff.delay(u32::MAX), None);
+    }
+
+    #[test]
+    fn exact_sensitivity_is_idle_only_past_the_timeout() {
+        let timeout = Duration::from_secs(60);
+        let exact = IdleSensitivity::Exact;
+        assert_eq!(exact.threshold(timeout), timeout);
+        assert!(!exact.is_idle(timeout - Duration::from_millis(1), timeout));
+        assert!(!exact.is_idle(timeout, timeout));
+        assert!(exact.is_idle(timeout + Duration::from_millis(1), timeout));
+    }
+
+    #[test]
+    fn coarse_sensitivity_adds_the_tolerance() {
+        let timeout = Duration::from_secs(60);
+        let tolerance = Duration::from_secs(5);
+        let coarse = IdleSensitivity::Coarse(tolerance);
+        assert_eq!(coarse.threshold(timeout), timeout + tolerance);
+        // Idle for Exact, still active for Coarse.
+        assert!(!coarse.is_idle(timeout + Duration::from_millis(1), timeout));
+        assert!(!coarse.is_idle(timeout + tolerance, timeout));
+        assert!(coarse.is_idle(timeout + tolerance + Duration::from_millis(1), timeout));
+        assert_eq!(
+            IdleSensitivity::Coarse(Duration::ZERO).threshold(timeout),
+            IdleSensitivity::Exact.threshold(timeout)
+        );
+    }
+
+    #[test]
+    fn coarse_threshold_saturates() {
+        let coarse = IdleSensitivity::Coarse(Duration::MAX);
+        assert_eq!(coarse.threshold(Duration::from_secs(60)), Duration::MAX);
+        assert!(!coarse.is_idle(Duration::MAX, Duration::from_secs(60)));
+        assert_eq!(
+            IdleSensitivity::Coarse(Duration::from_secs(1)).threshold(Duration::MAX),
+            Duration::MAX
+        );
+    }
 }
@@ -0,0 +1,252 @@
+//! Reading and writing `WatcherConfig` as TOML or JSON. Fields left out of a file keep the
+//! values of `WatcherConfigBuilder`, unknown fields are rejected. Durations are written like
+//! `"500ms"` or `"10s"`.
+
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Deserializer, Serializer};
+
+use crate::config::WatcherConfig;
+
+impl WatcherConfig {
+    /// The config is checked with `validate`.
+    pub fn from_toml_str(toml: &str) -> Result<Self> {
+        let config: Self = toml::from_str(toml).context("Invalid watcher config")?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn to_toml_string(&self) -> Result<String> {
+        toml::to_string(self).context("Failed to serialize the watcher config")
+    }
+
+    /// The config is checked with `validate`.
+    pub fn from_json_str(json: &str) -> Result<Self> {
+        let config: Self = serde_json::from_str(json).context("Invalid watcher config")?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn to_json_string(&self) -> Result<String> {
+        serde_json::to_string(self).context("Failed to serialize the watcher config")
+    }
+}
+
+/// Units a duration can be written in, largest first.
+const UNITS: &[(&str, Duration)] = &[
+    ("h", Duration::from_secs(60 * 60)),
+    ("m", Duration::from_secs(60)),
+    ("s", Duration::from_secs(1)),
+    ("ms", Duration::from_millis(1)),
+    ("us", Duration::from_micros(1)),
+    ("ns", Duration::from_nanos(1)),
+];
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// Writes the duration in the largest unit that represents it exactly.
+fn format_duration(duration: Duration) -> String {
+    let nanos = duration.as_nanos();
+    let (unit, size) = UNITS
+        .iter()
+        .map(|(unit, size)| (unit, size.as_nanos()))
+        .find(|(_, size)| nanos.is_multiple_of(*size))
+        .expect("Every duration is a whole number of nanoseconds");
+    format!("{}{unit}", nanos / size)
+}
+
+/// Parses a whole number followed by one of `UNITS`, like `"500ms"`.
+fn parse_duration(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| anyhow!("Duration {value:?} has no unit"))?;
+    let (amount, unit) = value.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .with_context(|| format!("Duration {value:?} doesn't start with a number"))?;
+    let (_, size) = UNITS
+        .iter()
+        .find(|(name, _)| *name == unit.trim())
+        .ok_or_else(|| anyhow!("Duration {value:?} has an unknown unit"))?;
+    size.as_nanos()
+        .checked_mul(u128::from(amount))
+        .and_then(|nanos| {
+            let secs = u64::try_from(nanos / NANOS_PER_SEC).ok()?;
+            Some(Duration::new(secs, (nanos % NANOS_PER_SEC) as u32))
+        })
+        .ok_or_else(|| anyhow!("Duration {value:?} is too long"))
+}
+
+pub(crate) fn serialize<S: Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format_duration(*duration))
+}
+
+pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Duration, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    parse_duration(&value).map_err(serde::de::Error::custom)
+}
+
+/// The same format for optional durations.
+pub(crate) mod option {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(crate) fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => super::serialize(duration, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|value| super::parse_duration(&value).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_round_trip() {
+        let defaults = WatcherConfig::recommended();
+        let toml = defaults.to_toml_string().unwrap();
+        let from_toml = WatcherConfig::from_toml_str(&toml).unwrap();
+        assert_eq!(from_toml.to_toml_string().unwrap(), toml);
+
+        let json = defaults.to_json_string().unwrap();
+        let from_json = WatcherConfig::from_json_str(&json).unwrap();
+        assert_eq!(from_json.to_json_string().unwrap(), json);
+
+        // Left out fields keep their defaults
+        let empty = WatcherConfig::from_toml_str("").unwrap();
+        assert_eq!(empty.to_toml_string().unwrap(), toml);
+        let empty = WatcherConfig::from_json_str("{}").unwrap();
+        assert_eq!(empty.to_json_string().unwrap(), json);
+    }
+
+    #[test]
+    fn overrides_are_read() {
+        let config = WatcherConfig::from_toml_str(
+            r#"
+            idle_timeout = "10s"
+            idle_check_interval = "500ms"
+            gnome_startup_retries = 4
+            locales = ["de_DE", "de"]
+
+            [gnome_dbus_config]
+            window_signal = "FocusChanged"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.idle_timeout, Duration::from_secs(10));
+        assert_eq!(config.idle_check_interval, Duration::from_millis(500));
+        assert_eq!(config.gnome_startup_retries, 4);
+        assert_eq!(config.locales, ["de_DE", "de"]);
+        assert_eq!(
+            config.gnome_dbus_config.window_signal.as_deref(),
+            Some("FocusChanged")
+        );
+        let defaults = WatcherConfig::recommended();
+        assert_eq!(
+            config.gnome_dbus_config.window_method,
+            defaults.gnome_dbus_config.window_method
+        );
+        assert_eq!(config.gnome_dbus_timeout, defaults.gnome_dbus_timeout);
+
+        let toml = config.to_toml_string().unwrap();
+        assert!(toml.contains(r#"idle_timeout = "10s""#), "{toml}");
+        assert!(toml.contains(r#"idle_check_interval = "500ms""#), "{toml}");
+        let json = config.to_json_string().unwrap();
+        let from_json = WatcherConfig::from_json_str(&json).unwrap();
+        assert_eq!(from_json.idle_check_interval, Duration::from_millis(500));
+        assert_eq!(from_json.to_toml_string().unwrap(), toml);
+    }
+
+    #[test]
+    fn durations_use_the_largest_exact_unit() {
+        let cases = [
+            ("500ms", Duration::from_millis(500)),
+            ("10s", Duration::from_secs(10)),
+            ("1500ms", Duration::from_millis(1500)),
+            ("2m", Duration::from_secs(120)),
+            ("1h", Duration::from_secs(60 * 60)),
+            ("250us", Duration::from_micros(250)),
+            ("7ns", Duration::from_nanos(7)),
+        ];
+        for (text, duration) in cases {
+            assert_eq!(parse_duration(text).unwrap(), duration, "{text}");
+            assert_eq!(format_duration(duration), text);
+        }
+        assert_eq!(parse_duration(" 60s ").unwrap(), Duration::from_secs(60));
+        assert_eq!(format_duration(Duration::from_secs(60)), "1m");
+    }
+
+    #[test]
+    fn long_durations_round_trip() {
+        let cases = [
+            ("1000000001ns", Duration::new(1, 1)),
+            ("5000000000s", Duration::from_secs(5_000_000_000)),
+            (
+                "4294967297ms",
+                Duration::from_millis(u64::from(u32::MAX) + 2),
+            ),
+            ("18446744073709551615s", Duration::from_secs(u64::MAX)),
+        ];
+        for (text, duration) in cases {
+            assert_eq!(parse_duration(text).unwrap(), duration, "{text}");
+            assert_eq!(format_duration(duration), text);
+        }
+    }
+
+    #[test]
+    fn durations_beyond_duration_max_are_rejected() {
+        for text in [
+            "18446744073709551615m",
+            "18446744073709551615h",
+            "18446744073709551616s",
+        ] {
+            assert!(parse_duration(text).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn invalid_durations_are_rejected() {
+        for text in ["10", "ms", "1.5s", "10 days", "-1s", ""] {
+            assert!(parse_duration(text).is_err(), "{text}");
+        }
+        assert!(WatcherConfig::from_toml_str(r#"idle_timeout = "10""#).is_err());
+        assert!(WatcherConfig::from_toml_str("idle_timeout = 10").is_err());
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        assert!(WatcherConfig::from_toml_str(r#"idle_timout = "1s""#).is_err());
+        assert!(
+            WatcherConfig::from_toml_str("[gnome_dbus_config]\nwindow_sginal = \"x\"").is_err()
+        );
+        assert!(WatcherConfig::from_json_str(r#"{"idle_timout": "1s"}"#).is_err());
+    }
+
+    #[test]
+    fn invalid_configs_are_rejected() {
+        // Parses, but fails `validate`
+        assert!(WatcherConfig::from_toml_str(r#"idle_timeout = "10ms""#).is_err());
+        assert!(WatcherConfig::from_json_str(r#"{"idle_check_interval": "0s"}"#).is_err());
+    }
+}
@@ -0,0 +1,110 @@
+//! Scripted `WindowManager` for testing code that consumes the watchers.
+
+use anyhow::{Result, anyhow};
+
+use crate::{ActiveWindowData, WindowManager};
+
+/// Steps through a script of windows and idle states. Every `get_active_window_data` call moves
+/// to the next entry and returns its window, starting over after the last one. `is_idle` reports
+/// the entry returned last, or the first one before any window was requested.
+pub struct FakeWindowManager {
+    script: Vec<(ActiveWindowData, bool)>,
+    /// Index of the entry `get_active_window_data` returns next.
+    next: usize,
+    current: usize,
+}
+
+impl FakeWindowManager {
+    pub fn new(script: Vec<(ActiveWindowData, bool)>) -> Result<Self> {
+        if script.is_empty() {
+            return Err(anyhow!("The script has no entries"));
+        }
+        Ok(Self {
+            script,
+            next: 0,
+            current: 0,
+        })
+    }
+}
+
+impl WindowManager for FakeWindowManager {
+    fn get_active_window_data(&mut self) -> Result<ActiveWindowData> {
+        self.current = self.next;
+        self.next = (self.next + 1) % self.script.len();
+        Ok(self.script[self.current].0.clone())
+    }
+
+    fn is_idle(&mut self) -> Result<bool> {
+        Ok(self.script[self.current].1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(title: &str) -> ActiveWindowData {
+        ActiveWindowData {
+            window_title: title.into(),
+            ..ActiveWindowData::locked()
+        }
+    }
+
+    fn next_title(manager: &mut FakeWindowManager) -> String {
+        manager
+            .get_active_window_data()
+            .unwrap()
+            .window_title
+            .to_string()
+    }
+
+    #[test]
+    fn script_is_returned_in_order_and_starts_over() {
+        let mut manager = FakeWindowManager::new(vec![
+            (window("first"), false),
+            (window("second"), false),
+            (window("third"), false),
+        ])
+        .unwrap();
+        let titles: Vec<_> = (0..7).map(|_| next_title(&mut manager)).collect();
+        assert_eq!(
+            titles,
+            [
+                "first", "second", "third", "first", "second", "third", "first"
+            ]
+        );
+    }
+
+    #[test]
+    fn single_entry_script_repeats() {
+        let mut manager = FakeWindowManager::new(vec![(window("only"), true)]).unwrap();
+        for _ in 0..3 {
+            assert_eq!(next_title(&mut manager), "only");
+            assert!(manager.is_idle().unwrap());
+        }
+    }
+
+    #[test]
+    fn is_idle_follows_the_entry_returned_last() {
+        let mut manager =
+            FakeWindowManager::new(vec![(window("active"), false), (window("idle"), true)])
+                .unwrap();
+        // Before any window was requested it reports the first entry.
+        assert!(!manager.is_idle().unwrap());
+        assert!(!manager.is_idle().unwrap());
+
+        assert_eq!(next_title(&mut manager), "active");
+        assert!(!manager.is_idle().unwrap());
+        assert_eq!(next_title(&mut manager), "idle");
+        assert!(manager.is_idle().unwrap());
+        // Asking again doesn't advance the script.
+        assert!(manager.is_idle().unwrap());
+        assert_eq!(next_title(&mut manager), "active");
+        assert!(!manager.is_idle().unwrap());
+    }
+
+    #[test]
+    fn empty_script_is_rejected() {
+        assert!(FakeWindowManager::new(Vec::new()).is_err());
+    }
+}
@@ -1,15 +1,45 @@
-use std::time::Duration;
+use std::{
+    collections::HashMap,
+    pin::pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    task::Poll,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
 
 use anyhow::{Context, Result, anyhow};
+use chrono::Utc;
 use serde::Deserialize;
-use tracing::{debug, trace};
-use zbus::blocking::Connection;
+use tracing::{debug, info, trace, warn};
+use zbus::{
+    MatchRule, Message,
+    blocking::{Connection, MessageIterator},
+    message,
+    zvariant::{DynamicType, OwnedValue},
+};
+
+/// Delay before the first reconnect after the extension stops answering. Doubles with every
+/// failed attempt up to `RECOVERY_MAX_DELAY`.
+const RECOVERY_INITIAL_DELAY: Duration = Duration::from_secs(1);
+const RECOVERY_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Age after which the window from the latest signal is checked with a call, so an extension
+/// that stopped sending signals isn't trusted forever.
+const SIGNAL_MAX_AGE: Duration = Duration::from_secs(10);
 
 use crate::{
-    ActiveWindowData, WindowManager,
-    config::WatcherConfig,
-    linux_desktop::{DesktopInfo, LinuxDesktopInfo},
-    simple_cache::SimpleCache,
+    ActiveWindowData, IconRef, MonitorInfo, Sample, WatcherError, WindowManager,
+    config::{GnomeWindowSource, WatcherConfig},
+    gnome_install::{
+        EXTENSION_STATE_ENABLED, EXTENSION_UUID, ExtensionState, activate_gnome_extension,
+    },
+    linux_desktop::{
+        DesktopInfo, DesktopInfoResolver, SharedDesktopInfo, get_process_path, lock_desktop_info,
+    },
+    simple_cache::SharedCache,
     utils::{is_gnome, is_x11},
 };
 
@@ -18,27 +48,420 @@ pub struct GnomeWindowWatcher {
     pub last_title: String,
     pub last_app_id: String,
     pub idle_timeout: Duration,
-    pub desktop_info_cache: SimpleCache<String, DesktopInfo>,
-    pub linux_desktop_info: LinuxDesktopInfo,
+    pub desktop_info: SharedDesktopInfo,
     pub gnome_dbus_config: crate::config::GnomeDbusConfig,
+    pub dbus_timeout: Duration,
+    include_monitor_info: bool,
+    idle_source: Option<GnomeIdleSource>,
+    /// The window from the latest `window_signal`, `None` until one arrives, after a call fails
+    /// and after the subscription ends. Cleared on reconnect.
+    signaled_window: Arc<Mutex<Option<SignaledWindow>>>,
+    /// `None` if `window_signal` isn't configured or the subscription failed.
+    window_subscription: Option<WindowSignalSubscription>,
+    /// Set once org.gnome.Shell.Introspect refuses a call, after which only the extension is used.
+    introspect_refused: AtomicBool,
+    /// Executables resolved from `/proc` for windows without a desktop entry.
+    process_path_cache: SharedCache<u32, Option<Arc<str>>>,
+    recovery_attempts: u32,
+    reactivate_extension: bool,
+    consecutive_failures: u32,
+    next_recovery: Instant,
+    /// Applied by the async calls only, the blocking ones are filtered by `GenericWindowManager`.
+    #[cfg(feature = "async")]
+    filter: crate::rules::WindowFilter,
 }
 
-#[derive(Deserialize, Default)]
+/// Whether the focused window can be received from the GNOME extension.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GnomeConnectionStatus {
+    Connected,
+    /// The extension isn't loaded, or GNOME Shell isn't running, for example while it restarts.
+    ExtensionMissing,
+    /// D-Bus itself failed, holds the error.
+    DbusFailed(String),
+}
+
+impl GnomeConnectionStatus {
+    fn from_error(e: &anyhow::Error) -> Self {
+        let message = e.to_string();
+        if message.contains("Object does not exist")
+            || message.contains("The name org.gnome.Shell was not provided")
+        {
+            Self::ExtensionMissing
+        } else {
+            Self::DbusFailed(message)
+        }
+    }
+}
+
+/// Where the GNOME watcher reads the idle time from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GnomeIdleSource {
+    /// Mutter's IdleMonitor exported by GNOME Shell, configured through `GnomeDbusConfig`.
+    MutterShell,
+    /// The same IdleMonitor under the bus name older Mutter versions export it with.
+    MutterLegacy,
+    /// `GetSessionIdleTime` of org.freedesktop.ScreenSaver, which only has a precision of seconds.
+    ScreenSaver,
+}
+
+impl GnomeIdleSource {
+    const ALL: [Self; 3] = [Self::MutterShell, Self::MutterLegacy, Self::ScreenSaver];
+}
+
+#[derive(Deserialize, Default, Clone)]
 struct WindowData {
     title: String,
     wm_class: String,
+    // Older versions of the extension only send the title and the class
+    wm_class_instance: Option<String>,
+    pid: Option<u32>,
+    /// Index of the logical monitor, negative if the window isn't on one
+    monitor: Option<i32>,
+}
+
+struct SignaledWindow {
+    data: WindowData,
+    /// When the signal arrived or a call last confirmed the window.
+    received_at: Instant,
+}
+
+/// The thread storing the windows of `window_signal`. It listens on its own connection, and
+/// dropping the subscription closes that connection, which ends the thread.
+struct WindowSignalSubscription {
+    connection: Connection,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for WindowSignalSubscription {
+    fn drop(&mut self) {
+        if let Err(e) = async_io::block_on(self.connection.inner().clone().close()) {
+            debug!("Failed to close the window signal connection: {e}");
+        }
+        if let Some(thread) = self.thread.take()
+            && thread.join().is_err()
+        {
+            warn!("The window signal thread panicked");
+        }
+    }
+}
+
+/// Mode of a physical monitor in `GetCurrentState`: id, width, height, refresh rate, preferred
+/// scale, supported scales and properties.
+type DisplayMode = (
+    String,
+    i32,
+    i32,
+    f64,
+    f64,
+    Vec<f64>,
+    HashMap<String, OwnedValue>,
+);
+/// Connector, vendor, product and serial of a physical monitor.
+type MonitorSpec = (String, String, String, String);
+/// Physical monitor in `GetCurrentState`: its spec, modes and properties.
+type PhysicalMonitor = (MonitorSpec, Vec<DisplayMode>, HashMap<String, OwnedValue>);
+/// Logical monitor in `GetCurrentState`: position, scale, transform, whether it's primary, the
+/// physical monitors it shows and properties.
+type LogicalMonitor = (
+    i32,
+    i32,
+    f64,
+    u32,
+    bool,
+    Vec<MonitorSpec>,
+    HashMap<String, OwnedValue>,
+);
+
+/// Calls `method`, failing with `WatcherError::Transient` if there is no reply within `timeout`.
+async fn call_with_timeout<B>(
+    connection: &zbus::Connection,
+    timeout: Duration,
+    (service, path, interface, method): (&str, &str, &str, &str),
+    body: &B,
+) -> Result<Message>
+where
+    B: serde::Serialize + DynamicType,
+{
+    let mut call = pin!(connection.call_method(Some(service), path, Some(interface), method, body));
+    let mut timer = pin!(async_io::Timer::after(timeout));
+    std::future::poll_fn(|cx| {
+        if let Poll::Ready(response) = call.as_mut().poll(cx) {
+            return Poll::Ready(response.map_err(anyhow::Error::from));
+        }
+        timer.as_mut().poll(cx).map(|_| {
+            Err(WatcherError::Transient(format!(
+                "{interface}.{method} of {service} did not reply within {timeout:?}"
+            ))
+            .into())
+        })
+    })
+    .await
 }
 
 impl GnomeWindowWatcher {
+    /// Blocking `call_with_timeout` limited to `dbus_timeout`.
+    fn call<B>(&self, call: (&str, &str, &str, &str), body: &B) -> Result<Message>
+    where
+        B: serde::Serialize + DynamicType,
+    {
+        async_io::block_on(call_with_timeout(
+            self.dbus_connection.inner(),
+            self.dbus_timeout,
+            call,
+            body,
+        ))
+    }
+
+    /// Service, path, interface and method to call for the focused window.
+    fn window_call(&self) -> (&str, &str, &str, &str) {
+        let config = &self.gnome_dbus_config;
+        (
+            &config.window_service,
+            &config.window_path,
+            &config.window_interface,
+            &config.window_method,
+        )
+    }
+
+    /// Service, path, interface and method to call for the screensaver state.
+    fn screensaver_call(&self) -> (&str, &str, &str, &str) {
+        let config = &self.gnome_dbus_config;
+        (
+            &config.screensaver_service,
+            &config.screensaver_path,
+            &config.screensaver_interface,
+            &config.screensaver_method,
+        )
+    }
+
     fn get_window_data(&self) -> anyhow::Result<WindowData> {
-        let call_response = self.dbus_connection.call_method(
-            Some(self.gnome_dbus_config.window_service.as_str()),
-            self.gnome_dbus_config.window_path.as_str(),
-            Some(self.gnome_dbus_config.window_interface.as_str()),
-            self.gnome_dbus_config.window_method.as_str(),
+        async_io::block_on(self.fetch_window_data())
+    }
+
+    /// Reads the window from the source selected in `GnomeDbusConfig`.
+    async fn fetch_window_data(&self) -> Result<WindowData> {
+        if self.gnome_dbus_config.window_source == GnomeWindowSource::PreferIntrospect
+            && !self.introspect_refused.load(Ordering::Relaxed)
+        {
+            match self.fetch_introspect_window_data().await {
+                Ok(data) => return Ok(data),
+                Err(e) => {
+                    debug!("org.gnome.Shell.Introspect is not usable, using the extension: {e}");
+                    self.introspect_refused.store(true, Ordering::Relaxed);
+                }
+            }
+        }
+        self.fetch_extension_window_data().await
+    }
+
+    async fn fetch_extension_window_data(&self) -> Result<WindowData> {
+        let call_response = call_with_timeout(
+            self.dbus_connection.inner(),
+            self.dbus_timeout,
+            self.window_call(),
+            &(),
+        )
+        .await;
+        Self::parse_window_data(call_response)
+    }
+
+    /// Picks the focused window from `GetWindows`. There is no pid or class instance there.
+    async fn fetch_introspect_window_data(&self) -> Result<WindowData> {
+        let windows = call_with_timeout(
+            self.dbus_connection.inner(),
+            self.dbus_timeout,
+            (
+                "org.gnome.Shell",
+                "/org/gnome/Shell/Introspect",
+                "org.gnome.Shell.Introspect",
+                "GetWindows",
+            ),
             &(),
-        );
+        )
+        .await?
+        .body()
+        .deserialize::<HashMap<u64, HashMap<String, OwnedValue>>>()
+        .with_context(|| "Failed to deserialize the windows")?;
+
+        let string = |window: &HashMap<String, OwnedValue>, key: &str| {
+            window
+                .get(key)
+                .and_then(|value| <&str>::try_from(value).ok())
+                .map(str::to_string)
+        };
+        let Some(window) = windows.values().find(|window| {
+            window
+                .get("has-focus")
+                .and_then(|value| bool::try_from(value).ok())
+                .unwrap_or(false)
+        }) else {
+            trace!("No window is active");
+            return Ok(WindowData::default());
+        };
+        Ok(WindowData {
+            title: string(window, "title").unwrap_or_default(),
+            wm_class: string(window, "wm-class")
+                .or_else(|| string(window, "app-id"))
+                .unwrap_or_default(),
+            wm_class_instance: None,
+            pid: None,
+            monitor: None,
+        })
+    }
 
+    /// The monitor of the window, if `include_monitor_info` is set and the window reports one.
+    /// Failures are logged and give `None`.
+    async fn monitor_of(&self, data: &Result<WindowData>) -> Option<MonitorInfo> {
+        if !self.include_monitor_info {
+            return None;
+        }
+        let index = u32::try_from(data.as_ref().ok()?.monitor?).ok()?;
+        self.fetch_monitor(index)
+            .await
+            .inspect_err(|e| debug!("Failed to get the monitor from DisplayConfig: {e}"))
+            .ok()
+            .flatten()
+    }
+
+    /// Reads the logical monitor at `index` from `GetCurrentState` of DisplayConfig. Mutter
+    /// numbers logical monitors in the order it reports them. The name and resolution are the
+    /// ones of the first physical monitor the logical one shows.
+    async fn fetch_monitor(&self, index: u32) -> Result<Option<MonitorInfo>> {
+        let config = &self.gnome_dbus_config;
+        let (_serial, monitors, logical_monitors, _properties) = call_with_timeout(
+            self.dbus_connection.inner(),
+            self.dbus_timeout,
+            (
+                &config.display_config_service,
+                &config.display_config_path,
+                &config.display_config_interface,
+                "GetCurrentState",
+            ),
+            &(),
+        )
+        .await?
+        .body()
+        .deserialize::<(
+            u32,
+            Vec<PhysicalMonitor>,
+            Vec<LogicalMonitor>,
+            HashMap<String, OwnedValue>,
+        )>()
+        .with_context(|| "Failed to deserialize the display state")?;
+
+        let Some((.., specs, _)) = logical_monitors.get(index as usize) else {
+            return Ok(None);
+        };
+        let Some(spec) = specs.first() else {
+            return Ok(None);
+        };
+        let resolution = monitors
+            .iter()
+            .find(|(monitor_spec, ..)| monitor_spec == spec)
+            .and_then(|(_, modes, _)| {
+                modes.iter().find(|(.., properties)| {
+                    properties
+                        .get("is-current")
+                        .and_then(|value| bool::try_from(value).ok())
+                        .unwrap_or(false)
+                })
+            })
+            .map(|(_, width, height, ..)| ((*width).max(0) as u32, (*height).max(0) as u32));
+        Ok(resolution.map(|resolution| MonitorInfo {
+            name: spec.0.as_str().into(),
+            index,
+            resolution,
+        }))
+    }
+
+    /// The window from the latest signal, unless it's older than `SIGNAL_MAX_AGE`.
+    fn fresh_signaled_window(&self) -> Option<WindowData> {
+        self.signaled_window
+            .lock()
+            .expect("Mutex poisoned")
+            .as_ref()
+            .filter(|window| window.received_at.elapsed() < SIGNAL_MAX_AGE)
+            .map(|window| window.data.clone())
+    }
+
+    /// Refreshes the signaled window with the result of a call. A failed call drops it, so
+    /// calls are made until the next signal arrives.
+    fn confirm_signaled_window(&self, result: &Result<WindowData>) {
+        let mut signaled_window = self.signaled_window.lock().expect("Mutex poisoned");
+        match result {
+            Ok(data) => {
+                if let Some(window) = signaled_window.as_mut() {
+                    window.data = data.clone();
+                    window.received_at = Instant::now();
+                }
+            }
+            Err(_) => *signaled_window = None,
+        }
+    }
+
+    /// Subscribes to `window_signal` if it's configured, ending the previous subscription.
+    fn resubscribe_to_window_signal(&mut self) {
+        self.window_subscription = None;
+        let Some(signal) = &self.gnome_dbus_config.window_signal else {
+            return;
+        };
+        match self.subscribe_to_window_signal(signal) {
+            Ok(subscription) => self.window_subscription = Some(subscription),
+            Err(e) => {
+                warn!("Failed to subscribe to {signal}, calling the extension instead: {e}");
+            }
+        }
+    }
+
+    /// Starts a thread storing the window of every `signal` in `signaled_window`.
+    fn subscribe_to_window_signal(&self, signal: &str) -> Result<WindowSignalSubscription> {
+        let config = &self.gnome_dbus_config;
+        let rule = MatchRule::builder()
+            .msg_type(message::Type::Signal)
+            .sender(config.window_service.as_str())?
+            .path(config.window_path.as_str())?
+            .interface(config.window_interface.as_str())?
+            .member(signal)?
+            .build();
+        let connection = Connection::session()?;
+        let messages = MessageIterator::for_match_rule(rule, &connection, None)?;
+        let signaled_window = Arc::clone(&self.signaled_window);
+        let thread = std::thread::spawn(move || {
+            let store = |data: Option<WindowData>| {
+                *signaled_window.lock().expect("Mutex poisoned") =
+                    data.map(|data| SignaledWindow {
+                        data,
+                        received_at: Instant::now(),
+                    });
+            };
+            for message in messages {
+                match Self::parse_window_data(message.map_err(anyhow::Error::from)) {
+                    Ok(data) => store(Some(data)),
+                    Err(e) => debug!("Ignoring window signal: {e}"),
+                }
+            }
+            debug!("Window signal subscription ended, falling back to calls");
+            store(None);
+        });
+        Ok(WindowSignalSubscription {
+            connection,
+            thread: Some(thread),
+        })
+    }
+
+    /// The window from the latest signal, or from calling the extension if there is none or
+    /// it's too old.
+    fn current_window_data(&self) -> Result<WindowData> {
+        if let Some(data) = self.fresh_signaled_window() {
+            return Ok(data);
+        }
+        let result = self.get_window_data();
+        self.confirm_signaled_window(&result);
+        result
+    }
+
+    fn parse_window_data(call_response: Result<Message>) -> anyhow::Result<WindowData> {
         match call_response {
             Ok(json) => {
                 let json: String = json
@@ -60,36 +483,181 @@ impl GnomeWindowWatcher {
         }
     }
 
-    fn get_idle_time_data(&self) -> Result<u64> {
-        let call_response = self.dbus_connection.call_method(
-            Some(self.gnome_dbus_config.idle_service.as_str()),
-            self.gnome_dbus_config.idle_path.as_str(),
-            Some(self.gnome_dbus_config.idle_interface.as_str()),
-            self.gnome_dbus_config.idle_method.as_str(),
-            &(),
-        );
-        let result = call_response
+    /// Service, path, interface and method to call for the idle time of `source`.
+    fn idle_call(&self, source: GnomeIdleSource) -> (&str, &str, &str, &str) {
+        let config = &self.gnome_dbus_config;
+        match source {
+            GnomeIdleSource::MutterShell => (
+                &config.idle_service,
+                &config.idle_path,
+                &config.idle_interface,
+                &config.idle_method,
+            ),
+            GnomeIdleSource::MutterLegacy => (
+                "org.gnome.Mutter.IdleMonitor",
+                &config.idle_path,
+                &config.idle_interface,
+                &config.idle_method,
+            ),
+            GnomeIdleSource::ScreenSaver => (
+                "org.freedesktop.ScreenSaver",
+                "/org/freedesktop/ScreenSaver",
+                "org.freedesktop.ScreenSaver",
+                "GetSessionIdleTime",
+            ),
+        }
+    }
+
+    /// Tries the sources in order until one answers, then sticks to it until it fails.
+    fn get_idle_time_data(&mut self) -> Result<u64> {
+        let sources = match self.idle_source {
+            Some(source) => vec![source],
+            None => GnomeIdleSource::ALL.to_vec(),
+        };
+        let mut last_error = None;
+        for source in sources {
+            let call_response = self.call(self.idle_call(source), &());
+            match Self::parse_idle_time(source, call_response) {
+                Ok(idle_time) => {
+                    if self.idle_source.replace(source) != Some(source) {
+                        debug!("Reading idle time from {source:?}");
+                    }
+                    return Ok(idle_time);
+                }
+                Err(e) => {
+                    trace!("Idle time is not available from {source:?}: {e}");
+                    last_error = Some(e);
+                }
+            }
+        }
+        self.idle_source = None;
+        Err(last_error.expect("At least one source is tried"))
+    }
+
+    /// Converts the reply of `source` to milliseconds.
+    fn parse_idle_time(source: GnomeIdleSource, call_response: Result<Message>) -> Result<u64> {
+        let body = call_response
             .with_context(|| "Failed to get idle time")?
+            .body();
+        let result = match source {
+            GnomeIdleSource::MutterShell | GnomeIdleSource::MutterLegacy => {
+                body.deserialize::<u64>()
+            }
+            GnomeIdleSource::ScreenSaver => body
+                .deserialize::<u32>()
+                .map(|seconds| u64::from(seconds) * 1000),
+        };
+        result.with_context(|| "Failed to deserialize idle time")
+    }
+
+    /// The source the idle time was last read from, `None` if none has answered yet.
+    pub fn idle_source(&self) -> Option<GnomeIdleSource> {
+        self.idle_source
+    }
+
+    fn get_screensaver_active(&self) -> Result<bool> {
+        Self::parse_screensaver_active(self.call(self.screensaver_call(), &()))
+    }
+
+    fn parse_screensaver_active(call_response: Result<Message>) -> Result<bool> {
+        let result = call_response
+            .with_context(|| "Failed to get screensaver state")?
             .body()
-            .deserialize::<u64>()
-            .with_context(|| "Failed to deserialize idle time")?;
+            .deserialize::<bool>()
+            .with_context(|| "Failed to deserialize screensaver state")?;
         Ok(result)
     }
 }
 
+fn is_not_installed(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<WatcherError>(),
+        Some(WatcherError::ExtensionMissing {
+            state: ExtensionState::NotInstalled
+        })
+    )
+}
+
 impl GnomeWindowWatcher {
+    /// Reports what keeps the extension from being used, or `None` if it responds.
+    pub fn extension_status(&self) -> Result<Option<ExtensionState>> {
+        let info = self
+            .call(
+                (
+                    "org.gnome.Shell.Extensions",
+                    "/org/gnome/Shell/Extensions",
+                    "org.gnome.Shell.Extensions",
+                    "GetExtensionInfo",
+                ),
+                &EXTENSION_UUID,
+            )
+            .with_context(|| "Failed to get the extension info")?
+            .body()
+            .deserialize::<HashMap<String, OwnedValue>>()
+            .with_context(|| "Failed to deserialize the extension info")?;
+        // Unknown extensions get an empty dictionary
+        let Some(state) = info.get("state") else {
+            return Ok(Some(ExtensionState::NotInstalled));
+        };
+        if f64::try_from(state).ok() != Some(EXTENSION_STATE_ENABLED) {
+            return Ok(Some(ExtensionState::Disabled));
+        }
+        Ok(async_io::block_on(self.fetch_extension_window_data())
+            .err()
+            .map(|_| ExtensionState::NotResponding))
+    }
+
+    pub fn connection_status(&self) -> GnomeConnectionStatus {
+        match self.get_window_data() {
+            Ok(_) => GnomeConnectionStatus::Connected,
+            Err(e) => GnomeConnectionStatus::from_error(&e),
+        }
+    }
+
+    pub fn from_config(config: WatcherConfig) -> Result<Self> {
+        let desktop_info = DesktopInfoResolver::shared(&config);
+        Self::with_desktop_info(config, desktop_info)
+    }
+
+    #[deprecated(note = "use `from_config`, which every backend has")]
     pub fn new(config: WatcherConfig) -> Result<Self> {
+        Self::from_config(config)
+    }
+
+    /// Like `from_config`, but looks the apps up in `desktop_info`, which other watchers may share.
+    pub fn with_desktop_info(
+        config: WatcherConfig,
+        desktop_info: SharedDesktopInfo,
+    ) -> Result<Self> {
         let loader = || -> Result<Self> {
-            let watcher = Self {
+            let mut watcher = Self {
                 dbus_connection: Connection::session()?,
                 last_app_id: String::new(),
                 last_title: String::new(),
                 idle_timeout: config.idle_timeout,
-                desktop_info_cache: SimpleCache::new(config.cache_config.clone()),
-                linux_desktop_info: LinuxDesktopInfo::new(),
+                desktop_info: Arc::clone(&desktop_info),
                 gnome_dbus_config: config.gnome_dbus_config.clone(),
+                dbus_timeout: config.gnome_dbus_timeout,
+                include_monitor_info: config.include_monitor_info,
+                idle_source: None,
+                signaled_window: Arc::new(Mutex::new(None)),
+                window_subscription: None,
+                introspect_refused: AtomicBool::new(false),
+                process_path_cache: SharedCache::new(config.cache_config.clone()),
+                recovery_attempts: config.gnome_recovery_attempts,
+                reactivate_extension: config.gnome_reactivate_extension,
+                consecutive_failures: 0,
+                next_recovery: Instant::now(),
+                #[cfg(feature = "async")]
+                filter: crate::rules::WindowFilter::from_config(&config),
             };
-            watcher.get_window_data()?;
+            if let Err(e) = watcher.get_window_data() {
+                return Err(match watcher.extension_status() {
+                    Ok(Some(state)) => WatcherError::ExtensionMissing { state }.into(),
+                    _ => e,
+                });
+            }
+            watcher.resubscribe_to_window_signal();
             Ok(watcher)
         };
 
@@ -103,29 +671,39 @@ impl GnomeWindowWatcher {
 
         debug!("Gnome Wayland detected");
 
-        let mut watcher = Err(anyhow::anyhow!(""));
-        for _ in 0..3 {
-            watcher = loader();
-            if let Err(e) = &watcher {
-                debug!("Failed to load Gnome Wayland watcher: {e}");
-                std::thread::sleep(std::time::Duration::from_secs(3));
+        // The extension may still be loading while the session starts
+        let mut retries_left = config.gnome_startup_retries;
+        loop {
+            match loader() {
+                Ok(watcher) => return Ok(watcher),
+                // Retrying won't install the extension
+                Err(e) if retries_left == 0 || is_not_installed(&e) => return Err(e),
+                Err(e) => {
+                    debug!("Failed to load Gnome Wayland watcher, retrying: {e}");
+                    retries_left -= 1;
+                    std::thread::sleep(config.gnome_startup_retry_delay);
+                }
             }
         }
-        watcher
     }
 }
 
-impl WindowManager for GnomeWindowWatcher {
-    fn get_active_window_data(&mut self) -> Result<ActiveWindowData> {
-        let data = self.get_window_data();
-        if let Err(e) = data {
-            if e.to_string().contains("Object does not exist at path") {
-                trace!("The extension seems to have stopped");
-                return Err(anyhow::anyhow!("The extension seems to have stopped"));
+impl GnomeWindowWatcher {
+    /// Shared by the blocking and the async implementations. Looking the app up reads desktop
+    /// entries and `/proc`, so it's left to the returned `AppLookup`.
+    fn process_window_data(&mut self, data: Result<WindowData>) -> Result<AppLookup> {
+        let data = match data {
+            Ok(data) => data,
+            Err(e) => {
+                return Err(match GnomeConnectionStatus::from_error(&e) {
+                    GnomeConnectionStatus::ExtensionMissing => {
+                        trace!("The extension seems to have stopped");
+                        e.context("The GNOME extension is not running")
+                    }
+                    _ => e.context("Failed to call the GNOME extension over D-Bus"),
+                });
             }
-            return Err(e);
-        }
-        let data = data?;
+        };
 
         if data.wm_class != self.last_app_id || data.title != self.last_title {
             debug!(
@@ -136,30 +714,251 @@ impl WindowManager for GnomeWindowWatcher {
             self.last_title = data.title;
         }
 
-        let (process_path, app_name) = match self.desktop_info_cache.get(&self.last_app_id) {
+        Ok(AppLookup {
+            title: self.last_title.clone(),
+            app_id: self.last_app_id.clone(),
+            wm_class_instance: data.wm_class_instance,
+            // Mutter reports 0 when the pid is unknown
+            pid: data.pid.filter(|pid| *pid != 0),
+            desktop_info: Arc::clone(&self.desktop_info),
+            process_path_cache: self.process_path_cache.clone(),
+        })
+    }
+}
+
+/// The window reported by GNOME, with what's needed to look its app up.
+struct AppLookup {
+    title: String,
+    app_id: String,
+    wm_class_instance: Option<String>,
+    pid: Option<u32>,
+    desktop_info: SharedDesktopInfo,
+    process_path_cache: SharedCache<u32, Option<Arc<str>>>,
+}
+
+impl AppLookup {
+    fn into_window_data(self, monitor: Option<MonitorInfo>) -> ActiveWindowData {
+        let desktop_info = {
+            let mut resolver = lock_desktop_info(&self.desktop_info);
+            resolver.get(&self.app_id).or_else(|| {
+                self.wm_class_instance
+                    .filter(|instance| *instance != self.app_id)
+                    .and_then(|instance| resolver.get(&instance))
+            })
+        };
+        let icon_name = desktop_info.as_ref().and_then(DesktopInfo::icon_name);
+        let (process_path, app_name) = match desktop_info {
             Some(extra_info) => (Some(extra_info.process_path), Some(extra_info.app_name)),
-            None => {
-                if let Some(extra_info) = self.linux_desktop_info.get_extra_info(&self.last_app_id)
-                {
-                    self.desktop_info_cache
-                        .set(self.last_app_id.clone(), extra_info.clone());
-                    (Some(extra_info.process_path), Some(extra_info.app_name))
-                } else {
-                    (None, None)
-                }
-            }
+            None => (
+                self.pid
+                    .and_then(|pid| get_process_path(&self.process_path_cache, pid)),
+                None,
+            ),
         };
 
-        Ok(ActiveWindowData {
-            window_title: self.last_title.clone().into(),
-            app_identifier: Some(self.last_app_id.clone().into()),
+        ActiveWindowData {
+            window_title: self.title.into(),
+            app_identifier: Some(self.app_id.into()),
+            resource_class: None,
             process_path,
             app_name,
-        })
+            workspace: None,
+            geometry: None,
+            monitor,
+            is_fullscreen: None,
+            window_type: None,
+            virtual_desktop: None,
+            icon_name,
+            publisher: None,
+            focused_at: None,
+            pid: self.pid,
+        }
+    }
+}
+
+impl GnomeWindowWatcher {
+    /// Failed reconnects since the extension last answered.
+    pub fn consecutive_failures(&self) -> u32 {
+        self.consecutive_failures
     }
 
+    /// Like `current_window_data`, but reconnects with a growing delay while the extension is
+    /// missing. The error is reported as transient until `recovery_attempts` reconnects failed.
+    fn recovering_window_data(&mut self) -> Result<WindowData> {
+        let error = match self.current_window_data() {
+            Ok(data) => {
+                self.consecutive_failures = 0;
+                return Ok(data);
+            }
+            Err(e)
+                if GnomeConnectionStatus::from_error(&e)
+                    != GnomeConnectionStatus::ExtensionMissing =>
+            {
+                return Err(e);
+            }
+            Err(e) => e,
+        };
+
+        let now = Instant::now();
+        if now >= self.next_recovery {
+            match self.reconnect() {
+                Ok(data) => {
+                    info!("Reconnected to the GNOME extension");
+                    self.consecutive_failures = 0;
+                    return Ok(data);
+                }
+                Err(e) => {
+                    self.consecutive_failures += 1;
+                    let delay = RECOVERY_INITIAL_DELAY
+                        .saturating_mul(1 << (self.consecutive_failures - 1).min(16));
+                    self.next_recovery = now + delay.min(RECOVERY_MAX_DELAY);
+                    debug!(
+                        "Failed to reconnect to the GNOME extension ({} in a row): {e}",
+                        self.consecutive_failures
+                    );
+                }
+            }
+        }
+
+        if self.consecutive_failures < self.recovery_attempts {
+            Err(
+                WatcherError::Transient(format!("Reconnecting to the GNOME extension: {error}"))
+                    .into(),
+            )
+        } else {
+            Err(error)
+        }
+    }
+
+    /// Opens a new session connection, since GNOME Shell may have been restarted, and
+    /// subscribes to the window signal on it.
+    fn reconnect(&mut self) -> Result<WindowData> {
+        self.dbus_connection = Connection::session()?;
+        // Ends the thread of the old subscription before the window it stored is cleared
+        self.window_subscription = None;
+        *self.signaled_window.lock().expect("Mutex poisoned") = None;
+        if self.reactivate_extension
+            && let Err(e) = activate_gnome_extension()
+        {
+            debug!("Failed to enable the extension: {e}");
+        }
+        let data = self.get_window_data()?;
+        self.resubscribe_to_window_signal();
+        Ok(data)
+    }
+}
+
+impl WindowManager for GnomeWindowWatcher {
+    #[tracing::instrument(name = "gnome::get_active", skip(self))]
+    fn get_active_window_data(&mut self) -> Result<ActiveWindowData> {
+        let data = self.recovering_window_data();
+        let monitor = async_io::block_on(self.monitor_of(&data));
+        Ok(self.process_window_data(data)?.into_window_data(monitor))
+    }
+
+    #[tracing::instrument(name = "gnome::is_idle", skip(self))]
     fn is_idle(&mut self) -> Result<bool> {
         let data = self.get_idle_time_data()?;
         Ok(data > self.idle_timeout.as_millis() as u64)
     }
+
+    fn idle_duration(&mut self) -> Result<Duration> {
+        let since_input = Duration::from_millis(self.get_idle_time_data()?);
+        Ok(if since_input > self.idle_timeout {
+            since_input
+        } else {
+            Duration::ZERO
+        })
+    }
+
+    fn is_screen_locked(&mut self) -> Result<bool> {
+        self.get_screensaver_active()
+    }
+
+    fn set_idle_timeout(&mut self, timeout: Duration) -> Result<()> {
+        self.idle_timeout = timeout;
+        Ok(())
+    }
+
+    fn get_app_icon(&mut self, app_id: &str) -> Result<Option<IconRef>> {
+        Ok(lock_desktop_info(&self.desktop_info).app_icon(app_id))
+    }
+
+    fn sample(&mut self) -> Result<Sample> {
+        let timestamp = Utc::now();
+        let window = self.get_active_window_data()?;
+        let since_input = Duration::from_millis(self.get_idle_time_data()?);
+        let idle = since_input > self.idle_timeout;
+        Ok(Sample {
+            window,
+            idle,
+            idle_duration: if idle { since_input } else { Duration::ZERO },
+            timestamp,
+        })
+    }
+}
+
+#[cfg(feature = "async")]
+impl GnomeWindowWatcher {
+    /// Milliseconds since the last input. Uses the source the blocking calls settled on, or
+    /// Mutter if there is none yet.
+    async fn idle_time_async(&self) -> Result<u64> {
+        let source = self.idle_source.unwrap_or(GnomeIdleSource::MutterShell);
+        let call_response = call_with_timeout(
+            self.dbus_connection.inner(),
+            self.dbus_timeout,
+            self.idle_call(source),
+            &(),
+        )
+        .await;
+        Self::parse_idle_time(source, call_response)
+    }
+}
+
+/// Calls the extension through the async connection underlying the blocking one, so no thread
+/// is blocked while GNOME Shell responds. The app is looked up on tokio's blocking thread pool,
+/// since that reads desktop entries and `/proc`. The window is filtered according to the config, like
+/// `GenericWindowManager` does for the blocking calls.
+#[cfg(feature = "async")]
+impl crate::async_manager::AsyncWindowManager for GnomeWindowWatcher {
+    async fn get_active_window_data(&mut self) -> Result<ActiveWindowData> {
+        let data = match self.fresh_signaled_window() {
+            Some(data) => Ok(data),
+            None => {
+                let result = self.fetch_window_data().await;
+                self.confirm_signaled_window(&result);
+                result
+            }
+        };
+        let monitor = self.monitor_of(&data).await;
+        let lookup = self.process_window_data(data)?;
+        let data = tokio::task::spawn_blocking(move || lookup.into_window_data(monitor)).await?;
+        self.filter
+            .apply(data)
+            .ok_or_else(|| crate::NoActiveWindow.into())
+    }
+
+    async fn is_idle(&mut self) -> Result<bool> {
+        Ok(self.idle_time_async().await? > self.idle_timeout.as_millis() as u64)
+    }
+
+    async fn idle_duration(&mut self) -> Result<Duration> {
+        let since_input = Duration::from_millis(self.idle_time_async().await?);
+        Ok(if since_input > self.idle_timeout {
+            since_input
+        } else {
+            Duration::ZERO
+        })
+    }
+
+    async fn is_screen_locked(&mut self) -> Result<bool> {
+        let call_response = call_with_timeout(
+            self.dbus_connection.inner(),
+            self.dbus_timeout,
+            self.screensaver_call(),
+            &(),
+        )
+        .await;
+        Self::parse_screensaver_active(call_response)
+    }
 }
@@ -1,25 +1,385 @@
+#[cfg(feature = "gnome")]
+use std::{
+    env::temp_dir,
+    fs::OpenOptions,
+    io::{ErrorKind, Write as _},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
 use std::{path::Path, process::Command};
 
-use anyhow::{Context as _, Result};
+use anyhow::{Context as _, Result, bail};
 
+/// Installs a packed extension with the `gnome-extensions` CLI. GNOME Shell has no D-Bus method
+/// for installing a local bundle, `InstallRemoteExtension` only downloads from extensions.gnome.org.
 pub fn install_gnome_extension(path: &Path) -> Result<()> {
-    Command::new("gnome-extensions")
+    let status = Command::new("gnome-extensions")
         .arg("install")
+        .arg("--force")
         .arg(path)
         .status()
         .with_context(|| "Failed to install gnome extension")?;
+    if !status.success() {
+        bail!("gnome-extensions install failed with {status}");
+    }
 
     Ok(())
 }
 
-const EXTENSION_UUID: &str = "focused-window-dbus@whatawhat.anoromi.com";
+pub const EXTENSION_UUID: &str = "focused-window-dbus@whatawhat.anoromi.com";
 
+/// `ExtensionState.ACTIVE` of GNOME Shell, reported as a double.
+#[cfg(feature = "gnome")]
+pub(crate) const EXTENSION_STATE_ENABLED: f64 = 1.0;
+
+/// `version` of the bundled extension's metadata.json. An installed extension with a lower
+/// version should be replaced with `install_bundled_gnome_extension`.
+pub const BUNDLED_EXTENSION_VERSION: u32 = 8;
+
+/// Why the GNOME extension can't be used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtensionState {
+    /// GNOME Shell doesn't know the extension. `install_bundled_gnome_extension` installs it.
+    NotInstalled,
+    /// Installed, but not enabled. `activate_gnome_extension` enables it.
+    Disabled,
+    /// Enabled, but its D-Bus interface doesn't answer, for example while GNOME Shell restarts.
+    NotResponding,
+}
+
+impl std::fmt::Display for ExtensionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotInstalled => write!(f, "not installed"),
+            Self::Disabled => write!(f, "installed but disabled"),
+            Self::NotResponding => write!(f, "enabled but not responding"),
+        }
+    }
+}
+
+/// Built with `make pack-gnome-extension`.
+#[cfg(feature = "gnome")]
+const BUNDLED_EXTENSION: &[u8] =
+    include_bytes!("../focused-window-dbus@whatawhat.anoromi.com.shell-extension.zip");
+
+/// The packed GNOME extension shipped with the library.
+#[cfg(feature = "gnome")]
+pub fn bundled_extension_bytes() -> &'static [u8] {
+    BUNDLED_EXTENSION
+}
+
+/// Installs the bundled extension, so the zip doesn't have to be shipped next to the binary.
+/// Replaces an already installed version.
+#[cfg(feature = "gnome")]
+pub fn install_bundled_gnome_extension() -> Result<()> {
+    let path = write_private_bundle()?;
+    let result = install_gnome_extension(&path);
+    if let Err(e) = std::fs::remove_file(&path) {
+        tracing::warn!("Failed to remove {}: {e}", path.display());
+    }
+    result
+}
+
+/// Writes the bundle to a new file only the user can access, in `XDG_RUNTIME_DIR` if it's set.
+/// The file must not exist yet, so a symlink planted by another user can't redirect the write.
+#[cfg(feature = "gnome")]
+fn write_private_bundle() -> Result<PathBuf> {
+    let dir = std::env::var_os("XDG_RUNTIME_DIR")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(temp_dir);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    let mut attempt = 0;
+    let (path, mut file) = loop {
+        let path = dir.join(format!(
+            "{EXTENSION_UUID}-{}-{nanos}-{attempt}.shell-extension.zip",
+            std::process::id()
+        ));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        match options.open(&path) {
+            Ok(file) => break (path, file),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists && attempt < 16 => attempt += 1,
+            Err(e) => {
+                return Err(e).with_context(|| "Failed to create gnome extension bundle");
+            }
+        }
+    };
+    if let Err(e) = file.write_all(BUNDLED_EXTENSION) {
+        let _ = std::fs::remove_file(&path);
+        return Err(e).with_context(|| "Failed to write gnome extension bundle");
+    }
+    Ok(path)
+}
+
+/// Enables the extension through org.gnome.Shell.Extensions, or the `gnome-extensions` CLI
+/// without the `gnome` feature.
 pub fn activate_gnome_extension() -> Result<()> {
-    Command::new("gnome-extensions")
-        .arg("enable")
-        .arg(EXTENSION_UUID)
-        .status()
-        .with_context(|| "Failed to activate gnome extension")?;
+    #[cfg(feature = "gnome")]
+    {
+        let enabled = call_extensions("EnableExtension")?
+            .body()
+            .deserialize::<bool>()
+            .with_context(|| "Failed to deserialize the EnableExtension result")?;
+        if !enabled {
+            bail!("GNOME Shell refused to enable {EXTENSION_UUID}");
+        }
+        Ok(())
+    }
+    #[cfg(not(feature = "gnome"))]
+    {
+        let status = Command::new("gnome-extensions")
+            .arg("enable")
+            .arg(EXTENSION_UUID)
+            .status()
+            .with_context(|| "Failed to activate gnome extension")?;
+        if !status.success() {
+            bail!("gnome-extensions enable failed with {status}");
+        }
+        Ok(())
+    }
+}
+
+/// Disables the extension through org.gnome.Shell.Extensions.
+#[cfg(feature = "gnome")]
+pub fn deactivate_gnome_extension() -> Result<()> {
+    let disabled = call_extensions("DisableExtension")?
+        .body()
+        .deserialize::<bool>()
+        .with_context(|| "Failed to deserialize the DisableExtension result")?;
+    if !disabled {
+        bail!("GNOME Shell refused to disable {EXTENSION_UUID}");
+    }
+    Ok(())
+}
 
+/// Removes the extension through org.gnome.Shell.Extensions.
+#[cfg(feature = "gnome")]
+pub fn uninstall_gnome_extension() -> Result<()> {
+    let uninstalled = call_extensions("UninstallExtension")?
+        .body()
+        .deserialize::<bool>()
+        .with_context(|| "Failed to deserialize the UninstallExtension result")?;
+    if !uninstalled {
+        bail!("GNOME Shell failed to uninstall {EXTENSION_UUID}");
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// What `ensure_bundled_gnome_extension` did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtensionInstall {
+    Installed,
+    Upgraded { from: u32, to: u32 },
+    AlreadyCurrent,
+}
+
+/// The user's data dir, where `gnome-extensions install` puts extensions.
+#[cfg(feature = "gnome")]
+fn data_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))
+}
+
+/// Directory of the extension in `data_dir`.
+#[cfg(feature = "gnome")]
+fn installed_extension_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join("gnome-shell/extensions").join(EXTENSION_UUID)
+}
+
+/// `version` from the metadata.json of the installed extension, `None` if it isn't installed
+/// for the current user. Unlike `extension_version` it doesn't need GNOME Shell to be running.
+#[cfg(feature = "gnome")]
+pub fn installed_extension_version() -> Result<Option<u32>> {
+    match data_dir() {
+        Some(data_dir) => installed_extension_version_in(&data_dir),
+        None => Ok(None),
+    }
+}
+
+#[cfg(feature = "gnome")]
+fn installed_extension_version_in(data_dir: &Path) -> Result<Option<u32>> {
+    let dir = installed_extension_dir(data_dir);
+    let metadata = match std::fs::read_to_string(dir.join("metadata.json")) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| "Failed to read the extension metadata"),
+    };
+    let metadata: serde_json::Value =
+        serde_json::from_str(&metadata).with_context(|| "The extension metadata is not JSON")?;
+    let version = metadata["version"]
+        .as_u64()
+        .ok_or_else(|| anyhow::anyhow!("The extension metadata has no version"))?;
+    Ok(Some(version as u32))
+}
+
+/// Installs the bundled extension, or replaces an installed one older than
+/// `BUNDLED_EXTENSION_VERSION`. An enabled extension is disabled during the swap and enabled
+/// again, so GNOME Shell loads the new code.
+#[cfg(feature = "gnome")]
+pub fn ensure_bundled_gnome_extension() -> Result<ExtensionInstall> {
+    let install = needed_install(installed_extension_version()?);
+    match install {
+        ExtensionInstall::AlreadyCurrent => {}
+        ExtensionInstall::Installed => install_bundled_gnome_extension()?,
+        ExtensionInstall::Upgraded { .. } => {
+            // A disabled extension stays disabled after the upgrade
+            let was_enabled = is_extension_enabled()
+                .inspect_err(|e| tracing::debug!("Extension state is unknown: {e}"))
+                .unwrap_or(false);
+            if was_enabled {
+                deactivate_gnome_extension()?;
+            }
+            install_bundled_gnome_extension()?;
+            if was_enabled {
+                activate_gnome_extension()?;
+            }
+        }
+    }
+    Ok(install)
+}
+
+/// What `ensure_bundled_gnome_extension` has to do with the `installed` version.
+#[cfg(feature = "gnome")]
+fn needed_install(installed: Option<u32>) -> ExtensionInstall {
+    match installed {
+        None => ExtensionInstall::Installed,
+        Some(from) if from < BUNDLED_EXTENSION_VERSION => ExtensionInstall::Upgraded {
+            from,
+            to: BUNDLED_EXTENSION_VERSION,
+        },
+        Some(_) => ExtensionInstall::AlreadyCurrent,
+    }
+}
+
+/// Whether GNOME Shell reports the extension as enabled.
+#[cfg(feature = "gnome")]
+pub fn is_extension_enabled() -> Result<bool> {
+    let info = get_extension_info()?;
+    Ok(info
+        .get("state")
+        .and_then(|state| f64::try_from(state).ok())
+        == Some(EXTENSION_STATE_ENABLED))
+}
+
+/// Whether GNOME Shell knows the extension, enabled or not.
+#[cfg(feature = "gnome")]
+pub fn is_extension_installed() -> Result<bool> {
+    // Unknown extensions get an empty dictionary
+    Ok(!get_extension_info()?.is_empty())
+}
+
+/// `version` of the installed extension, `None` if it isn't installed.
+#[cfg(feature = "gnome")]
+pub fn extension_version() -> Result<Option<u32>> {
+    let info = get_extension_info()?;
+    let Some(version) = info.get("version") else {
+        return Ok(None);
+    };
+    // metadata.json numbers are reported as doubles
+    let version = f64::try_from(version).with_context(|| "The version is not a number")?;
+    Ok(Some(version as u32))
+}
+
+#[cfg(feature = "gnome")]
+fn get_extension_info() -> Result<std::collections::HashMap<String, zbus::zvariant::OwnedValue>> {
+    call_extensions("GetExtensionInfo")?
+        .body()
+        .deserialize()
+        .with_context(|| "Failed to deserialize the extension info")
+}
+
+/// Calls `method` of org.gnome.Shell.Extensions with the uuid of the extension.
+#[cfg(feature = "gnome")]
+fn call_extensions(method: &str) -> Result<zbus::Message> {
+    zbus::blocking::Connection::session()
+        .with_context(|| "Unable to connect to the session bus")?
+        .call_method(
+            Some("org.gnome.Shell.Extensions"),
+            "/org/gnome/Shell/Extensions",
+            Some("org.gnome.Shell.Extensions"),
+            method,
+            &EXTENSION_UUID,
+        )
+        .with_context(|| format!("Failed to call {method} of org.gnome.Shell.Extensions"))
+}
+
+#[cfg(all(test, feature = "gnome"))]
+mod tests {
+    use super::*;
+
+    /// A data dir of its own for every test, removed when dropped.
+    struct TempDataDir(PathBuf);
+
+    impl TempDataDir {
+        fn new(name: &str) -> Self {
+            let dir = temp_dir().join(format!("whatawhat-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        fn install(&self, metadata: &str) {
+            let dir = installed_extension_dir(&self.0);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("metadata.json"), metadata).unwrap();
+        }
+    }
+
+    impl Drop for TempDataDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn missing_extension_has_no_version() {
+        let data_dir = TempDataDir::new("missing-extension");
+        assert_eq!(installed_extension_version_in(&data_dir.0).unwrap(), None);
+        assert_eq!(
+            needed_install(installed_extension_version_in(&data_dir.0).unwrap()),
+            ExtensionInstall::Installed
+        );
+    }
+
+    #[test]
+    fn old_extension_is_upgraded() {
+        let data_dir = TempDataDir::new("old-extension");
+        data_dir.install(r#"{"uuid": "focused-window-dbus@whatawhat.anoromi.com", "version": 3}"#);
+        let installed = installed_extension_version_in(&data_dir.0).unwrap();
+        assert_eq!(installed, Some(3));
+        assert_eq!(
+            needed_install(installed),
+            ExtensionInstall::Upgraded {
+                from: 3,
+                to: BUNDLED_EXTENSION_VERSION
+            }
+        );
+    }
+
+    #[test]
+    fn current_extension_is_kept() {
+        let data_dir = TempDataDir::new("current-extension");
+        data_dir.install(&format!(r#"{{"version": {BUNDLED_EXTENSION_VERSION}}}"#));
+        let installed = installed_extension_version_in(&data_dir.0).unwrap();
+        assert_eq!(needed_install(installed), ExtensionInstall::AlreadyCurrent);
+        assert_eq!(
+            needed_install(Some(BUNDLED_EXTENSION_VERSION + 1)),
+            ExtensionInstall::AlreadyCurrent
+        );
+    }
+
+    #[test]
+    fn metadata_without_version_is_an_error() {
+        let data_dir = TempDataDir::new("no-version");
+        data_dir.install(r#"{"uuid": "focused-window-dbus@whatawhat.anoromi.com"}"#);
+        assert!(installed_extension_version_in(&data_dir.0).is_err());
+    }
+}
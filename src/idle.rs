@@ -9,6 +9,11 @@ pub struct Tracker {
     idle_timeout: TimeDelta,
 
     idle_end: Option<DateTime<Utc>>,
+
+    /// Start of the current idle or active period.
+    period_start: DateTime<Utc>,
+    idle_total: TimeDelta,
+    active_total: TimeDelta,
 }
 
 #[derive(Debug)]
@@ -32,10 +37,23 @@ impl Tracker {
             is_changed: false,
             idle_timeout,
             idle_end: None,
+            period_start: now,
+            idle_total: TimeDelta::zero(),
+            active_total: TimeDelta::zero(),
         }
     }
 
-    fn set_idle(&mut self, is_idle: bool) {
+    /// Switches the state, adding the period that ended at `at` to the matching total.
+    fn set_idle(&mut self, is_idle: bool, at: DateTime<Utc>) {
+        if self.is_idle != is_idle {
+            let period = max(at - self.period_start, TimeDelta::zero());
+            if self.is_idle {
+                self.idle_total += period;
+            } else {
+                self.active_total += period;
+            }
+            self.period_start = at;
+        }
         self.is_idle = is_idle;
         self.is_changed = true;
     }
@@ -43,14 +61,30 @@ impl Tracker {
     pub fn mark_not_idle(&mut self, now: DateTime<Utc>) {
         debug!("No longer idle");
         self.last_input_time = now;
-        self.set_idle(false);
+        self.set_idle(false, now);
 
         self.idle_end = Some(now);
     }
 
-    pub fn mark_idle(&mut self, _: DateTime<Utc>) {
+    pub fn mark_idle(&mut self, now: DateTime<Utc>) {
         debug!("Idle again");
-        self.set_idle(true);
+        self.set_idle(true, now);
+    }
+
+    /// Total time of the finished idle periods. The current one is added once it ends.
+    pub fn cumulative_idle_duration(&self) -> TimeDelta {
+        self.idle_total
+    }
+
+    /// Total time of the finished active periods. The current one is added once it ends.
+    pub fn cumulative_active_duration(&self) -> TimeDelta {
+        self.active_total
+    }
+
+    /// Zeroes both totals. The current state and the start of the current period are kept.
+    pub fn reset_totals(&mut self) {
+        self.idle_total = TimeDelta::zero();
+        self.active_total = TimeDelta::zero();
     }
 
     // The logic is rewritten from the original Python code:
@@ -68,12 +102,13 @@ impl Tracker {
             && u64::from(seconds_since_input) < self.idle_timeout.num_seconds().try_into().unwrap()
         {
             debug!("No longer idle");
-            self.set_idle(false);
+            self.set_idle(false, self.last_input_time);
         } else if !self.is_idle
             && u64::from(seconds_since_input) >= self.idle_timeout.num_seconds().try_into().unwrap()
         {
             debug!("Idle again");
-            self.set_idle(true);
+            // The idle period started with the last input
+            self.set_idle(true, self.last_input_time);
         }
 
         Ok(self.get_status(now))
@@ -83,10 +118,10 @@ impl Tracker {
         if !self.is_idle {
             self.last_input_time = max(self.last_input_time, now - self.idle_timeout);
 
-            if let Some(idle_end) = self.idle_end {
-                if self.last_input_time < idle_end {
-                    self.last_input_time = idle_end;
-                }
+            if let Some(idle_end) = self.idle_end
+                && self.last_input_time < idle_end
+            {
+                self.last_input_time = idle_end;
             }
         }
 
@@ -124,3 +159,95 @@ impl Tracker {
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn start() -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000, 0).unwrap()
+    }
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        start() + TimeDelta::seconds(seconds)
+    }
+
+    #[test]
+    fn totals_add_up_finished_periods() {
+        let mut tracker = Tracker::new(start(), TimeDelta::seconds(60));
+        tracker.mark_idle(at(100));
+        tracker.mark_not_idle(at(130));
+        tracker.mark_idle(at(200));
+        tracker.mark_not_idle(at(250));
+
+        assert_eq!(
+            tracker.cumulative_active_duration(),
+            TimeDelta::seconds(170)
+        );
+        assert_eq!(tracker.cumulative_idle_duration(), TimeDelta::seconds(80));
+    }
+
+    #[test]
+    fn current_period_is_added_once_it_ends() {
+        let mut tracker = Tracker::new(start(), TimeDelta::seconds(60));
+        tracker.mark_idle(at(100));
+        assert_eq!(tracker.cumulative_idle_duration(), TimeDelta::zero());
+
+        tracker.mark_not_idle(at(160));
+        assert_eq!(tracker.cumulative_idle_duration(), TimeDelta::seconds(60));
+    }
+
+    #[test]
+    fn repeated_marks_dont_split_a_period() {
+        let mut tracker = Tracker::new(start(), TimeDelta::seconds(60));
+        tracker.mark_not_idle(at(10));
+        tracker.mark_not_idle(at(20));
+        tracker.mark_idle(at(100));
+        tracker.mark_idle(at(110));
+        tracker.mark_not_idle(at(150));
+
+        assert_eq!(
+            tracker.cumulative_active_duration(),
+            TimeDelta::seconds(100)
+        );
+        assert_eq!(tracker.cumulative_idle_duration(), TimeDelta::seconds(50));
+    }
+
+    #[test]
+    fn idle_from_polling_starts_at_the_last_input() {
+        let mut tracker = Tracker::new(start(), TimeDelta::seconds(60));
+        // Polled 90 seconds in, 70 seconds after the last input
+        let status = tracker.get_with_last_input(at(90), 70).unwrap();
+        assert!(matches!(status, Status::Idle { changed: true, .. }));
+
+        // Input 5 seconds before the next poll
+        let status = tracker.get_with_last_input(at(150), 5).unwrap();
+        assert!(matches!(status, Status::Active { changed: true, .. }));
+
+        assert_eq!(tracker.cumulative_active_duration(), TimeDelta::seconds(20));
+        assert_eq!(tracker.cumulative_idle_duration(), TimeDelta::seconds(125));
+    }
+
+    #[test]
+    fn reset_totals_keeps_the_current_period() {
+        let mut tracker = Tracker::new(start(), TimeDelta::seconds(60));
+        tracker.mark_idle(at(100));
+        tracker.mark_not_idle(at(130));
+        tracker.mark_idle(at(200));
+        tracker.reset_totals();
+        assert_eq!(tracker.cumulative_active_duration(), TimeDelta::zero());
+        assert_eq!(tracker.cumulative_idle_duration(), TimeDelta::zero());
+
+        // The idle period that was running at the reset counts from its start
+        tracker.mark_not_idle(at(240));
+        assert_eq!(tracker.cumulative_idle_duration(), TimeDelta::seconds(40));
+        assert_eq!(tracker.cumulative_active_duration(), TimeDelta::zero());
+    }
+
+    #[test]
+    fn transitions_out_of_order_dont_count_negative_time() {
+        let mut tracker = Tracker::new(at(100), TimeDelta::seconds(60));
+        tracker.mark_idle(at(50));
+        assert_eq!(tracker.cumulative_active_duration(), TimeDelta::zero());
+    }
+}
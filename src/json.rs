@@ -0,0 +1,251 @@
+//! Conversions between `ActiveWindowData` and `serde_json::Value`.
+
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::anyhow;
+use serde_json::{Map, Value, json};
+
+use crate::{ActiveWindowData, MonitorInfo};
+
+impl From<ActiveWindowData> for Value {
+    fn from(data: ActiveWindowData) -> Self {
+        json!({
+            "window_title": data.window_title.as_ref(),
+            "process_path": data.process_path.as_deref(),
+            "app_identifier": data.app_identifier.as_deref(),
+            "resource_class": data.resource_class.as_deref(),
+            "app_name": data.app_name.as_deref(),
+            "workspace": data.workspace.as_deref(),
+            "monitor": data.monitor.map(|monitor| json!({
+                "name": monitor.name.as_ref(),
+                "index": monitor.index,
+                "width": monitor.resolution.0,
+                "height": monitor.resolution.1,
+            })),
+            "is_fullscreen": data.is_fullscreen,
+            "pid": data.pid,
+            "window_type": data.window_type.as_deref(),
+            "virtual_desktop": data.virtual_desktop,
+            "icon_name": data.icon_name.as_deref(),
+            "publisher": data.publisher.as_deref(),
+            "focused_at": data.focused_at.map(focused_at_value),
+            "geometry": data.geometry.map(|(x, y, width, height)| json!({
+                "x": x,
+                "y": y,
+                "width": width,
+                "height": height,
+            })),
+        })
+    }
+}
+
+fn optional_field(object: &Map<String, Value>, name: &str) -> anyhow::Result<Option<Arc<str>>> {
+    match object.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(value)) => Ok(Some(value.as_str().into())),
+        Some(value) => Err(anyhow!("Field {name} is not a string: {value}")),
+    }
+}
+
+fn geometry_field(object: &Map<String, Value>) -> anyhow::Result<Option<(i32, i32, u32, u32)>> {
+    let geometry = match object.get("geometry") {
+        None | Some(Value::Null) => return Ok(None),
+        Some(Value::Object(geometry)) => geometry,
+        Some(value) => return Err(anyhow!("Field geometry is not an object: {value}")),
+    };
+    let coordinate = |name: &str| -> anyhow::Result<i64> {
+        geometry
+            .get(name)
+            .and_then(Value::as_i64)
+            .ok_or_else(|| anyhow!("Field geometry.{name} is not an integer"))
+    };
+    Ok(Some((
+        coordinate("x")?.try_into()?,
+        coordinate("y")?.try_into()?,
+        coordinate("width")?.try_into()?,
+        coordinate("height")?.try_into()?,
+    )))
+}
+
+fn monitor_field(object: &Map<String, Value>) -> anyhow::Result<Option<MonitorInfo>> {
+    let monitor = match object.get("monitor") {
+        None | Some(Value::Null) => return Ok(None),
+        Some(Value::Object(monitor)) => monitor,
+        Some(value) => return Err(anyhow!("Field monitor is not an object: {value}")),
+    };
+    let number = |name: &str| -> anyhow::Result<u32> {
+        let value = monitor
+            .get(name)
+            .and_then(Value::as_u64)
+            .ok_or_else(|| anyhow!("Field monitor.{name} is not an unsigned integer"))?;
+        Ok(value.try_into()?)
+    };
+    Ok(Some(MonitorInfo {
+        name: optional_field(monitor, "name")?
+            .ok_or_else(|| anyhow!("Field monitor.name is missing"))?,
+        index: number("index")?,
+        resolution: (number("width")?, number("height")?),
+    }))
+}
+
+const NANOS_PER_SEC: u32 = 1_000_000_000;
+
+/// Whole seconds since the Unix epoch, negative before it, and the nanoseconds past them. Times
+/// too far from the epoch for `secs` to hold are clamped.
+fn focused_at_value(time: SystemTime) -> Value {
+    let (secs, nanos) = match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => (
+            i64::try_from(since.as_secs()).unwrap_or(i64::MAX),
+            since.subsec_nanos(),
+        ),
+        Err(e) => {
+            let before = e.duration();
+            let secs = i64::try_from(before.as_secs()).map_or(i64::MIN, |secs| -secs);
+            match before.subsec_nanos() {
+                0 => (secs, 0),
+                nanos => (secs.saturating_sub(1), NANOS_PER_SEC - nanos),
+            }
+        }
+    };
+    json!({ "secs": secs, "nanos": nanos })
+}
+
+fn focused_at_field(object: &Map<String, Value>) -> anyhow::Result<Option<SystemTime>> {
+    let focused_at = match object.get("focused_at") {
+        None | Some(Value::Null) => return Ok(None),
+        Some(Value::Object(focused_at)) => focused_at,
+        Some(value) => return Err(anyhow!("Field focused_at is not an object: {value}")),
+    };
+    let secs = focused_at
+        .get("secs")
+        .and_then(Value::as_i64)
+        .ok_or_else(|| anyhow!("Field focused_at.secs is not an integer"))?;
+    let nanos = focused_at
+        .get("nanos")
+        .and_then(Value::as_u64)
+        .and_then(|nanos| u32::try_from(nanos).ok())
+        .filter(|nanos| *nanos < NANOS_PER_SEC)
+        .ok_or_else(|| anyhow!("Field focused_at.nanos is not below a second in nanoseconds"))?;
+    let time = if secs >= 0 {
+        UNIX_EPOCH.checked_add(Duration::new(secs.unsigned_abs(), nanos))
+    } else {
+        UNIX_EPOCH
+            .checked_sub(Duration::from_secs(secs.unsigned_abs()))
+            .and_then(|time| time.checked_add(Duration::from_nanos(nanos.into())))
+    };
+    time.map(Some)
+        .ok_or_else(|| anyhow!("Field focused_at is out of range: {secs}s {nanos}ns"))
+}
+
+impl TryFrom<Value> for ActiveWindowData {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        let object = value
+            .as_object()
+            .ok_or_else(|| anyhow!("Window data is not an object: {value}"))?;
+        let window_title = optional_field(object, "window_title")?
+            .ok_or_else(|| anyhow!("Field window_title is missing"))?;
+
+        Ok(Self {
+            window_title,
+            process_path: optional_field(object, "process_path")?,
+            app_identifier: optional_field(object, "app_identifier")?,
+            resource_class: optional_field(object, "resource_class")?,
+            app_name: optional_field(object, "app_name")?,
+            workspace: optional_field(object, "workspace")?,
+            window_type: optional_field(object, "window_type")?,
+            icon_name: optional_field(object, "icon_name")?,
+            publisher: optional_field(object, "publisher")?,
+            focused_at: focused_at_field(object)?,
+            monitor: monitor_field(object)?,
+            geometry: geometry_field(object)?,
+            is_fullscreen: match object.get("is_fullscreen") {
+                None | Some(Value::Null) => None,
+                Some(Value::Bool(value)) => Some(*value),
+                Some(value) => return Err(anyhow!("Field is_fullscreen is not a bool: {value}")),
+            },
+            pid: match object.get("pid") {
+                None | Some(Value::Null) => None,
+                Some(value) => Some(
+                    value
+                        .as_u64()
+                        .and_then(|pid| u32::try_from(pid).ok())
+                        .ok_or_else(|| anyhow!("Field pid is not a process id: {value}"))?,
+                ),
+            },
+            virtual_desktop: match object.get("virtual_desktop") {
+                None | Some(Value::Null) => None,
+                Some(value) => Some(
+                    value
+                        .as_u64()
+                        .and_then(|desktop| u32::try_from(desktop).ok())
+                        .ok_or_else(|| {
+                            anyhow!("Field virtual_desktop is not a desktop index: {value}")
+                        })?,
+                ),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(time: SystemTime) -> Option<SystemTime> {
+        let data = ActiveWindowData {
+            focused_at: Some(time),
+            ..ActiveWindowData::locked()
+        };
+        ActiveWindowData::try_from(Value::from(data))
+            .unwrap()
+            .focused_at
+    }
+
+    #[test]
+    fn focused_at_keeps_nanoseconds() {
+        let time = UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789);
+        assert_eq!(round_trip(time), Some(time));
+        assert_eq!(
+            focused_at_value(time),
+            json!({ "secs": 1_700_000_000, "nanos": 123_456_789 })
+        );
+    }
+
+    #[test]
+    fn focused_at_before_the_epoch_is_kept() {
+        let time = UNIX_EPOCH - Duration::new(5, 250_000_000);
+        assert_eq!(round_trip(time), Some(time));
+        assert_eq!(
+            focused_at_value(time),
+            json!({ "secs": -6, "nanos": 750_000_000 })
+        );
+
+        let time = UNIX_EPOCH - Duration::from_secs(5);
+        assert_eq!(round_trip(time), Some(time));
+        assert_eq!(focused_at_value(time), json!({ "secs": -5, "nanos": 0 }));
+    }
+
+    #[test]
+    fn focused_at_at_the_epoch_is_kept() {
+        assert_eq!(round_trip(UNIX_EPOCH), Some(UNIX_EPOCH));
+    }
+
+    #[test]
+    fn invalid_focused_at_is_rejected() {
+        for focused_at in [
+            json!(1_700_000_000_000u64),
+            json!({ "secs": 1, "nanos": NANOS_PER_SEC }),
+            json!({ "secs": 1.5, "nanos": 0 }),
+            json!({ "nanos": 0 }),
+        ] {
+            let mut value = Value::from(ActiveWindowData::locked());
+            value["focused_at"] = focused_at;
+            assert!(ActiveWindowData::try_from(value).is_err());
+        }
+    }
+}
@@ -1,6 +1,69 @@
 let connections = {};
+// Milliseconds since the Unix epoch when the active window was activated. It doesn't fit in an
+// int32, so callDBus sends it as a double
+let activatedAt = Date.now();
+
+// KDE 6 exposes frameGeometry, KDE 5 before 5.20 only has geometry
+function geometry(client) {
+    let rect = client.frameGeometry ? client.frameGeometry : client.geometry;
+    if (!rect) {
+        return { x: 0, y: 0, width: -1, height: -1 };
+    }
+    return {
+        x: Math.round(rect.x),
+        y: Math.round(rect.y),
+        width: Math.round(rect.width),
+        height: Math.round(rect.height)
+    };
+}
+
+// KDE 6 exposes the output of the window, KDE 5 only the index of its screen
+function screen(client) {
+    if (client.output) {
+        return {
+            name: String(client.output.name),
+            index: workspace.screens.indexOf(client.output),
+            width: client.output.geometry.width,
+            height: client.output.geometry.height
+        };
+    }
+    if (typeof client.screen === "number") {
+        let area = workspace.clientArea(KWin.ScreenArea, client.screen, workspace.currentDesktop);
+        return { name: "", index: client.screen, width: area.width, height: area.height };
+    }
+    return { name: "", index: -1, width: 0, height: 0 };
+}
+
+// Named like the _NET_WM_WINDOW_TYPE atoms of X11
+const windowTypes = [
+    ["desktopWindow", "DESKTOP"],
+    ["dock", "DOCK"],
+    ["toolbar", "TOOLBAR"],
+    ["menu", "MENU"],
+    ["dialog", "DIALOG"],
+    ["splash", "SPLASH"],
+    ["utility", "UTILITY"],
+    ["dropdownMenu", "DROPDOWN_MENU"],
+    ["popupMenu", "POPUP_MENU"],
+    ["tooltip", "TOOLTIP"],
+    ["notification", "NOTIFICATION"],
+    ["comboBox", "COMBO"],
+    ["dndIcon", "DND"],
+    ["normalWindow", "NORMAL"]
+];
+
+function windowType(client) {
+    for (let [property, type] of windowTypes) {
+        if (client[property]) {
+            return "_NET_WM_WINDOW_TYPE_" + type;
+        }
+    }
+    return "";
+}
 
 function send(client) {
+    let rect = geometry(client);
+    let clientScreen = screen(client);
     callDBus(
         "com.github.anoromi.whatawhat_lib",
         "/com/github/anoromi/whatawhat_lib",
@@ -9,7 +72,17 @@ function send(client) {
         "caption" in client ? client.caption : "",
         "resourceClass" in client ? String(client.resourceClass) : "",
         "resourceName" in client ? String(client.resourceName) : "",
-        "pid" in client ? client.pid : null
+        "pid" in client ? client.pid : null,
+        rect.x,
+        rect.y,
+        rect.width,
+        rect.height,
+        clientScreen.name,
+        clientScreen.index,
+        clientScreen.width,
+        clientScreen.height,
+        windowType(client),
+        activatedAt
     );
 }
 
@@ -19,13 +92,26 @@ let handler = function(client){
     }
     if (!(client.internalId in connections)) {
         connections[client.internalId] = true;
-        client.captionChanged.connect(function() {
+        let sendIfActive = function() {
             if (client.active) {
                 send(client);
             }
-        });
+        };
+        client.captionChanged.connect(sendIfActive);
+        if (client.outputChanged) {
+            client.outputChanged.connect(sendIfActive);
+        } else if (client.screenChanged) {
+            client.screenChanged.connect(sendIfActive);
+        }
+        if (client.frameGeometryChanged) {
+            client.frameGeometryChanged.connect(sendIfActive);
+        } else if (client.geometryChanged) {
+            // KDE version < 5.20
+            client.geometryChanged.connect(sendIfActive);
+        }
     }
 
+    activatedAt = Date.now();
     send(client);
 };
 
@@ -1,35 +1,124 @@
 /*
- * This uses a hack with KWin scripts in order to receive the active window.
- * For the moment of writing, KWin doesn't implement the appropriate protocols to get a top level window.
+ * The active window is received through org_kde_plasma_window_management when KWin exposes it to us.
+ * Otherwise this uses a hack with KWin scripts, since KWin doesn't implement the wlr or ext protocols
+ * to get a top level window.
  * Inspired by https://github.com/k0kubun/xremap/
  */
-use crate::idle::Status;
-use crate::linux_desktop::{DesktopInfo, LinuxDesktopInfo};
-use crate::simple_cache::SimpleCache;
+use crate::kde_plasma_wayland::PlasmaWindowWatcher;
+use crate::linux_desktop::get_process_path;
+use crate::linux_desktop::{
+    DesktopInfo, DesktopInfoResolver, SharedDesktopInfo, lock_desktop_info,
+};
+use crate::simple_cache::SharedCache;
 use crate::wayland_idle::IdleWatcherRunner;
-use crate::{ActiveWindowData, WindowManager, config::WatcherConfig};
+use crate::{
+    ActiveWindowData, IconRef, MonitorInfo, Sample, WatcherError, WindowManager,
+    config::WatcherConfig, lock_shared,
+};
 use anyhow::{Context, Result, anyhow};
+use chrono::Utc;
 use std::env::{self, temp_dir};
 use std::path::Path;
 use std::sync::Arc;
-use std::sync::Mutex;
-use tracing::{debug, error};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, error, trace};
 use zbus::blocking::{Connection, connection::Builder as ConnectionBuilder};
 use zbus::interface;
 
 const KWIN_SCRIPT_NAME: &str = "whatawhat-lib";
 const KWIN_SCRIPT: &str = include_str!("kde.js");
+/// First KWin version that exports loaded scripts under `/Scripting`.
+const KWIN_SCRIPTING_PATH_VERSION: i8 = 6;
+
+/// The calls the script is managed with. KWin answers them over the session bus.
+trait KWinCalls {
+    fn is_script_loaded(&self) -> anyhow::Result<bool>;
+    /// The number KWin registered the script under, negative if it refused it.
+    fn load_script(&self, path: &str) -> anyhow::Result<i32>;
+    fn unload_script(&self) -> anyhow::Result<bool>;
+    fn run_script(&self, object_path: &str) -> anyhow::Result<()>;
+    fn support_information(&self) -> anyhow::Result<String>;
+}
 
-struct KWinScript {
-    dbus_connection: Connection,
+impl KWinCalls for Connection {
+    fn is_script_loaded(&self) -> anyhow::Result<bool> {
+        self.call_method(
+            Some("org.kde.KWin"),
+            "/Scripting",
+            Some("org.kde.kwin.Scripting"),
+            "isScriptLoaded",
+            &KWIN_SCRIPT_NAME,
+        )?
+        .body()
+        .deserialize()
+        .map_err(std::convert::Into::into)
+    }
+
+    fn load_script(&self, path: &str) -> anyhow::Result<i32> {
+        self.call_method(
+            Some("org.kde.KWin"),
+            "/Scripting",
+            Some("org.kde.kwin.Scripting"),
+            "loadScript",
+            &(path, KWIN_SCRIPT_NAME),
+        )?
+        .body()
+        .deserialize()
+        .map_err(std::convert::Into::into)
+    }
+
+    fn unload_script(&self) -> anyhow::Result<bool> {
+        self.call_method(
+            Some("org.kde.KWin"),
+            "/Scripting",
+            Some("org.kde.kwin.Scripting"),
+            "unloadScript",
+            &KWIN_SCRIPT_NAME,
+        )?
+        .body()
+        .deserialize()
+        .map_err(std::convert::Into::into)
+    }
+
+    fn run_script(&self, object_path: &str) -> anyhow::Result<()> {
+        self.call_method(
+            Some("org.kde.KWin"),
+            object_path,
+            Some("org.kde.kwin.Script"),
+            "run",
+            &(),
+        )?;
+        Ok(())
+    }
+
+    fn support_information(&self) -> anyhow::Result<String> {
+        self.call_method(
+            Some("org.kde.KWin"),
+            "/KWin",
+            Some("org.kde.KWin"),
+            "supportInformation",
+            &(),
+        )?
+        .body()
+        .deserialize()
+        .map_err(std::convert::Into::into)
+    }
+}
+
+struct KWinScript<C: KWinCalls = Connection> {
+    dbus_connection: C,
     is_loaded: bool,
+    /// Resolved on first use, since asking KWin for it is expensive.
+    major_version: OnceLock<i8>,
 }
 
-impl KWinScript {
-    fn new(dbus_connection: Connection) -> Self {
+impl<C: KWinCalls> KWinScript<C> {
+    fn new(dbus_connection: C) -> Self {
         KWinScript {
             dbus_connection,
             is_loaded: false,
+            major_version: OnceLock::new(),
         }
     }
 
@@ -37,92 +126,76 @@ impl KWinScript {
         let path = temp_dir().join("whatawhat-lib.js");
         std::fs::write(&path, KWIN_SCRIPT).with_context(|| "Failed to create kwin script")?;
 
-        let number = self.get_registered_number(&path)?;
-        let result = self.start(number);
+        let result = self.get_registered_number(&path).and_then(|number| {
+            // KWin answers with a negative number if it doesn't load the script
+            if number < 0 {
+                return Err(anyhow!("KWin refused to load the script"));
+            }
+            self.is_loaded = true;
+            self.start(number)
+        });
         std::fs::remove_file(&path)?;
-        self.is_loaded = true;
 
         result
     }
 
+    /// Replaces a copy left loaded by an earlier run, then loads the script. Any failure means
+    /// the script can't be used in this session.
+    fn replace_and_load(&mut self) -> anyhow::Result<()> {
+        if self.is_loaded()? {
+            debug!("KWin script is already loaded, unloading");
+            self.unload()?;
+        }
+
+        self.load().map_err(|e| {
+            WatcherError::BackendUnavailable(format!("KWin script can't be loaded: {e:#}")).into()
+        })
+    }
+
     fn is_loaded(&self) -> anyhow::Result<bool> {
-        self.dbus_connection
-            .call_method(
-                Some("org.kde.KWin"),
-                "/Scripting",
-                Some("org.kde.kwin.Scripting"),
-                "isScriptLoaded",
-                &KWIN_SCRIPT_NAME,
-            )?
-            .body()
-            .deserialize()
-            .map_err(std::convert::Into::into)
+        self.dbus_connection.is_script_loaded()
     }
 
     fn get_registered_number(&self, path: &Path) -> anyhow::Result<i32> {
+        // since OsStr does not implement zvariant::Type, the temp-path must be valid utf-8
         let temp_path = path
             .to_str()
             .ok_or(anyhow!("Temporary file path is not valid"))?;
 
-        self.dbus_connection
-            .call_method(
-                Some("org.kde.KWin"),
-                "/Scripting",
-                Some("org.kde.kwin.Scripting"),
-                "loadScript",
-                // since OsStr does not implement zvariant::Type, the temp-path must be valid utf-8
-                &(temp_path, KWIN_SCRIPT_NAME),
-            )?
-            .body()
-            .deserialize()
-            .map_err(std::convert::Into::into)
+        self.dbus_connection.load_script(temp_path)
     }
 
     fn unload(&self) -> anyhow::Result<bool> {
-        self.dbus_connection
-            .call_method(
-                Some("org.kde.KWin"),
-                "/Scripting",
-                Some("org.kde.kwin.Scripting"),
-                "unloadScript",
-                &KWIN_SCRIPT_NAME,
-            )?
-            .body()
-            .deserialize()
-            .map_err(std::convert::Into::into)
+        self.dbus_connection.unload_script()
     }
 
     fn start(&self, script_number: i32) -> anyhow::Result<()> {
         debug!("Starting KWin script {script_number}");
 
-        let path = if self.get_major_version() < 6 {
+        let path = if self.get_major_version() < KWIN_SCRIPTING_PATH_VERSION {
             format!("/{script_number}")
         } else {
             format!("/Scripting/Script{script_number}")
         };
         self.dbus_connection
-            .call_method(
-                Some("org.kde.KWin"),
-                path,
-                Some("org.kde.kwin.Script"),
-                "run",
-                &(),
-            )
-            .with_context(|| "Error on starting the script")?;
-        Ok(())
+            .run_script(&path)
+            .with_context(|| "Error on starting the script")
     }
 
+    /// Falls back to 5 if the version can't be found. Cached after the first call.
     fn get_major_version(&self) -> i8 {
-        if let Ok(version) = Self::get_major_version_from_env() {
-            debug!("KWin version from KDE_SESSION_VERSION: {version}");
-
-            version
-        } else {
-            self.get_major_version_from_dbus().unwrap_or_else(|e| {
-                error!("Failed to get KWin version: {e}");
-                5
-            })
-        }
+        *self.major_version.get_or_init(|| {
+            if let Ok(version) = Self::get_major_version_from_env() {
+                debug!("KWin version from KDE_SESSION_VERSION: {version}");
+
+                version
+            } else {
+                self.get_major_version_from_dbus().unwrap_or_else(|e| {
+                    error!("Failed to get KWin version: {e}");
+                    5
+                })
+            }
+        })
     }
 
     fn get_major_version_from_env() -> anyhow::Result<i8> {
@@ -132,41 +205,30 @@ impl KWinScript {
     }
 
     fn get_major_version_from_dbus(&self) -> anyhow::Result<i8> {
-        let support_information: String = self
-            .dbus_connection
-            .call_method(
-                Some("org.kde.KWin"),
-                "/KWin",
-                Some("org.kde.KWin"),
-                "supportInformation",
-                &(),
-            )?
-            .body()
-            .deserialize()?;
-
-        // find a string like "KWin version: 5.27.8" and extract the version number from it:
-        let version = support_information
-            .lines()
-            .find(|line| line.starts_with("KWin version: "))
-            .ok_or(anyhow!("KWin version not found"))?
-            .split_whitespace()
-            .last()
-            .ok_or(anyhow!("KWin version is invalid"))?;
-
-        // Extract the major version number from the version number like "5.27.8":
-        let major_version = version
-            .split('.')
-            .next()
-            .ok_or(anyhow!("KWin version is invalid: {version}"))?
-            .parse::<i8>()?;
-
-        debug!("KWin version from DBus: {version}, major version: {major_version}");
-
+        let support_information = self.dbus_connection.support_information()?;
+        let major_version = parse_major_version(&support_information)?;
+        debug!("KWin major version from DBus: {major_version}");
         Ok(major_version)
     }
 }
 
-impl Drop for KWinScript {
+/// Finds a line like "KWin version: 5.27.8" in the output of `supportInformation` and returns
+/// the major version from it.
+fn parse_major_version(support_information: &str) -> anyhow::Result<i8> {
+    let version = support_information
+        .lines()
+        .find_map(|line| line.trim_start().strip_prefix("KWin version:"))
+        .ok_or(anyhow!("KWin version not found"))?
+        .trim();
+
+    version
+        .split('.')
+        .next()
+        .and_then(|major| major.parse::<i8>().ok())
+        .ok_or(anyhow!("KWin version is invalid: {version}"))
+}
+
+impl<C: KWinCalls> Drop for KWinScript<C> {
     fn drop(&mut self) {
         if let Err(e) = self.unload() {
             error!("Problem during stopping KWin script: {e}");
@@ -182,8 +244,19 @@ fn send_active_window(
     Ok(ActiveWindowData {
         window_title: active_window.caption.clone().into(),
         app_identifier: Some(active_window.resource_name.clone().into()),
+        resource_class: Some(active_window.resource_class.clone()),
         process_path: active_window.process_path.clone(),
         app_name: active_window.app_name.clone(),
+        workspace: None,
+        geometry: active_window.geometry,
+        monitor: active_window.monitor.clone(),
+        is_fullscreen: None,
+        pid: active_window.pid,
+        window_type: active_window.window_type.clone(),
+        virtual_desktop: None,
+        icon_name: active_window.icon_name.clone(),
+        publisher: None,
+        focused_at: active_window.focused_at,
     })
 }
 
@@ -193,40 +266,107 @@ struct ActiveWindow {
     caption: Arc<str>,
     process_path: Option<Arc<str>>,
     app_name: Option<Arc<str>>,
+    geometry: Option<(i32, i32, u32, u32)>,
+    monitor: Option<MonitorInfo>,
+    pid: Option<u32>,
+    window_type: Option<Arc<str>>,
+    icon_name: Option<Arc<str>>,
+    focused_at: Option<SystemTime>,
+}
+
+/// Arguments of a `notify_active_window` call, except the timestamp.
+#[derive(PartialEq)]
+struct Notification {
+    caption: String,
+    resource_class: String,
+    resource_name: String,
+    pid: i32,
+    geometry: (i32, i32, i32, i32),
+    screen: (String, i32, i32, i32),
+    window_type: String,
+}
+
+/// The script sends `Date.now()` of the activation. It doesn't fit in an int32, so KWin sends it
+/// as a double. The receive time is used if it's missing or invalid.
+fn activation_time(timestamp_ms: f64) -> SystemTime {
+    Some(timestamp_ms)
+        .filter(|millis| millis.is_finite() && *millis > 0.0)
+        .map(|millis| Duration::from_millis(millis.round() as u64))
+        .and_then(|since_epoch| UNIX_EPOCH.checked_add(since_epoch))
+        .unwrap_or_else(SystemTime::now)
 }
 
 struct ActiveWindowInterface {
     active_window: Arc<Mutex<ActiveWindow>>,
-    desktop_info_cache: SimpleCache<String, DesktopInfo>,
-    linux_desktop_info: LinuxDesktopInfo,
+    /// KWin repeats notifications for the same window on focus churn, they are skipped.
+    last_notification: Option<Notification>,
+    desktop_info: SharedDesktopInfo,
+    /// Executables of the notified pids.
+    process_path_cache: SharedCache<u32, Option<Arc<str>>>,
 }
 
 #[interface(name = "com.github.anoromi.whatawhat_lib")]
 impl ActiveWindowInterface {
+    // Arguments are positional D-Bus parameters sent by kde.js
+    #[allow(clippy::too_many_arguments)]
+    #[tracing::instrument(
+        name = "kde::notify_active_window",
+        skip_all,
+        fields(app_id = %resource_name, pid = pid)
+    )]
     fn notify_active_window(
         &mut self,
         caption: String,
         resource_class: String,
         resource_name: String,
-        _pid: i32,
+        pid: i32,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        screen_name: String,
+        screen_index: i32,
+        screen_width: i32,
+        screen_height: i32,
+        window_type: String,
+        timestamp_ms: f64,
     ) {
-        debug!(
-            "Active window class: \"{resource_class}\", name: \"{resource_name}\", caption: \"{caption}\""
-        );
+        let notification = Notification {
+            caption: caption.clone(),
+            resource_class: resource_class.clone(),
+            resource_name: resource_name.clone(),
+            pid,
+            geometry: (x, y, width, height),
+            screen: (
+                screen_name.clone(),
+                screen_index,
+                screen_width,
+                screen_height,
+            ),
+            window_type: window_type.clone(),
+        };
+        // Repeated activations of the same window keep the time it was first focused
+        if self.last_notification.as_ref() == Some(&notification) {
+            trace!("Active window is not changed");
+            return;
+        }
+        self.last_notification = Some(notification);
 
-        let (process_path, app_name) = match self.desktop_info_cache.get(&resource_name) {
-            Some(extra_info) => (Some(extra_info.process_path), Some(extra_info.app_name)),
-            None => {
-                if let Some(extra_info) = self.linux_desktop_info.get_extra_info(&resource_name) {
-                    self.desktop_info_cache
-                        .set(resource_name.clone(), extra_info.clone());
-                    (Some(extra_info.process_path), Some(extra_info.app_name))
-                } else {
-                    (None, None)
-                }
-            }
+        debug!("Active window class: \"{resource_class}\", name: \"{resource_name}\"");
+
+        let desktop_info = lock_desktop_info(&self.desktop_info).get(&resource_name);
+        let icon_name = desktop_info.as_ref().and_then(DesktopInfo::icon_name);
+        let (process_path, app_name) = match desktop_info {
+            Some(info) => (Some(info.process_path), Some(info.app_name)),
+            None => (None, None),
         };
 
+        // KWin reports 0 for windows without a known process
+        let pid = u32::try_from(pid).ok().filter(|pid| *pid != 0);
+        // Many resource names have no desktop entry, the executable of the process is used then
+        let process_path = process_path
+            .or_else(|| pid.and_then(|pid| get_process_path(&self.process_path_cache, pid)));
+
         let mut active_window = self.active_window.lock().expect("Mutex poisoned");
         active_window.caption = caption.into();
         active_window.resource_class = resource_class.into();
@@ -234,30 +374,117 @@ impl ActiveWindowInterface {
 
         active_window.process_path = process_path;
         active_window.app_name = app_name;
+        active_window.icon_name = icon_name;
+        active_window.pid = pid;
+        active_window.focused_at = Some(activation_time(timestamp_ms));
+        // The script reports an empty type when KWin doesn't know it
+        active_window.window_type = Some(window_type)
+            .filter(|window_type| !window_type.is_empty())
+            .map(Into::into);
+        // The script reports a negative size when the window has no geometry
+        active_window.geometry = match (u32::try_from(width), u32::try_from(height)) {
+            (Ok(width), Ok(height)) => Some((x, y, width, height)),
+            _ => None,
+        };
+        // The script reports a negative index when the screen is unknown
+        active_window.monitor = u32::try_from(screen_index).ok().map(|index| MonitorInfo {
+            // KDE 5 doesn't expose screen names
+            name: if screen_name.is_empty() {
+                index.to_string().into()
+            } else {
+                screen_name.into()
+            },
+            index,
+            resolution: (screen_width.max(0) as u32, screen_height.max(0) as u32),
+        });
     }
 }
 
+/// Where the active window is received from.
+enum WindowSource {
+    /// Shared so the async calls can make the roundtrip on tokio's blocking thread pool.
+    PlasmaWindowManagement(Arc<Mutex<PlasmaWindowWatcher>>),
+    KWinScript {
+        active_window: Arc<Mutex<ActiveWindow>>,
+        _kwin_script: KWinScript,
+    },
+}
+
 pub struct KdeWindowManager {
-    active_window: Arc<Mutex<ActiveWindow>>,
-    _kwin_script: KWinScript,
+    window_source: WindowSource,
     dbus_connection: Connection,
-    pub idle_watcher: IdleWatcherRunner,
+    /// `None` when the compositor has no idle protocol and logind is unreachable.
+    pub idle_watcher: Option<IdleWatcherRunner>,
+    desktop_info: SharedDesktopInfo,
+    /// Applied by the async calls only, the blocking ones are filtered by `GenericWindowManager`.
+    #[cfg(feature = "async")]
+    filter: crate::rules::WindowFilter,
 }
 
 impl KdeWindowManager {
+    /// The window that was active when the user last went idle. `None` if idle isn't tracked.
+    pub fn last_active_window_before_idle(&self) -> Option<ActiveWindowData> {
+        self.idle_watcher
+            .as_ref()
+            .and_then(IdleWatcherRunner::last_active_window_before_idle)
+    }
+
+    pub fn from_config(config: WatcherConfig) -> anyhow::Result<Self> {
+        let desktop_info = DesktopInfoResolver::shared(&config);
+        Self::with_desktop_info(config, desktop_info)
+    }
+
+    #[deprecated(note = "use `from_config`, which every backend has")]
     pub fn new(config: WatcherConfig) -> anyhow::Result<Self> {
-        let mut kwin_script = KWinScript::new(Connection::session()?);
-        if kwin_script.is_loaded()? {
-            debug!("KWin script is already loaded, unloading");
-            kwin_script.unload()?;
-        }
+        Self::from_config(config)
+    }
+
+    /// Like `from_config`, but looks the apps up in `desktop_info`, which other watchers may share.
+    pub fn with_desktop_info(
+        config: WatcherConfig,
+        desktop_info: SharedDesktopInfo,
+    ) -> anyhow::Result<Self> {
         if env::var("WAYLAND_DISPLAY").is_err()
             && env::var_os("XDG_SESSION_TYPE").unwrap_or("".into()) == "x11"
         {
             return Err(anyhow!("X11 should be tried instead"));
         }
 
-        kwin_script.load().unwrap();
+        let (window_source, dbus_connection) =
+            match PlasmaWindowWatcher::with_desktop_info(config.clone(), Arc::clone(&desktop_info))
+            {
+                Ok(watcher) => {
+                    debug!("Using plasma window management");
+                    (
+                        WindowSource::PlasmaWindowManagement(Arc::new(Mutex::new(watcher))),
+                        Connection::session()?,
+                    )
+                }
+                Err(e) => {
+                    debug!("Plasma window management is unavailable, loading KWin script: {e}");
+                    Self::load_kwin_script(&config, Arc::clone(&desktop_info))?
+                }
+            };
+
+        Ok(Self {
+            window_source,
+            dbus_connection,
+            // KWin only exposes idle over Wayland, org.freedesktop.ScreenSaver is its D-Bus side
+            idle_watcher: IdleWatcherRunner::prefer_dbus(&config)
+                .inspect_err(|e| error!("Idle is not tracked: {e}"))
+                .ok(),
+            desktop_info,
+            #[cfg(feature = "async")]
+            filter: crate::rules::WindowFilter::from_config(&config),
+        })
+    }
+
+    fn load_kwin_script(
+        config: &WatcherConfig,
+        desktop_info: SharedDesktopInfo,
+    ) -> anyhow::Result<(WindowSource, Connection)> {
+        let mut kwin_script = KWinScript::new(Connection::session()?);
+        kwin_script.replace_and_load()?;
 
         let active_window = Arc::new(Mutex::new(ActiveWindow {
             caption: "".into(),
@@ -265,11 +492,18 @@ impl KdeWindowManager {
             resource_class: "".into(),
             process_path: None,
             app_name: None,
+            geometry: None,
+            monitor: None,
+            pid: None,
+            window_type: None,
+            icon_name: None,
+            focused_at: None,
         }));
         let active_window_interface = ActiveWindowInterface {
             active_window: Arc::clone(&active_window),
-            desktop_info_cache: SimpleCache::new(config.cache_config),
-            linux_desktop_info: LinuxDesktopInfo::new(),
+            last_notification: None,
+            desktop_info,
+            process_path_cache: SharedCache::new(config.cache_config.clone()),
         };
 
         // Build the DBus connection and register the interface synchronously (no extra thread).
@@ -282,15 +516,18 @@ impl KdeWindowManager {
         // Intentionally avoid initial monitor_activity() here to ensure we only process
         // events when the caller invokes methods (run-when-called semantics).
 
-        Ok(Self {
+        let window_source = WindowSource::KWinScript {
             active_window,
             _kwin_script: kwin_script,
-            dbus_connection,
-            idle_watcher: IdleWatcherRunner::new(config.idle_timeout.as_millis() as u32)?,
-        })
+        };
+        Ok((window_source, dbus_connection))
     }
 
     fn pump_dbus(&self) {
+        // Only the script reports windows over DBus
+        if !matches!(self.window_source, WindowSource::KWinScript { .. }) {
+            return;
+        }
         // Best-effort: process any pending DBus activity inline.
         // monitor_activity blocks waiting for IO when nothing is pending on real KDE,
         // but KWin sends promptly on activation events; calls here are short in practice.
@@ -298,28 +535,372 @@ impl KdeWindowManager {
         // a timed variant or switching to async with a local runtime.
         self.dbus_connection.monitor_activity();
     }
+
+    fn record_window(&self, data: &ActiveWindowData) {
+        if let Some(idle_watcher) = &self.idle_watcher {
+            idle_watcher.record_window(data);
+        }
+    }
+
+    fn idle_state(&self) -> Result<(bool, Duration)> {
+        let Some(idle_watcher) = &self.idle_watcher else {
+            return Err(WatcherError::ProtocolUnsupported("no idle protocol".into()).into());
+        };
+        Ok(idle_watcher.idle_state())
+    }
+
+    /// Position and size of the active window as `(x, y, width, height)`.
+    pub fn window_geometry(&mut self) -> Result<(i32, i32, u32, u32)> {
+        self.get_active_window_data()?
+            .geometry
+            .ok_or_else(|| anyhow!("Geometry of the active window is unknown"))
+    }
+
+    fn get_screensaver_active(&self) -> Result<bool> {
+        self.dbus_connection
+            .call_method(
+                Some("org.freedesktop.ScreenSaver"),
+                "/ScreenSaver",
+                Some("org.freedesktop.ScreenSaver"),
+                "GetActive",
+                &(),
+            )
+            .with_context(|| "Failed to get screensaver state")?
+            .body()
+            .deserialize()
+            .with_context(|| "Failed to deserialize screensaver state")
+    }
 }
 
 impl WindowManager for KdeWindowManager {
+    #[tracing::instrument(name = "kde::get_active", skip(self))]
     fn get_active_window_data(&mut self) -> Result<ActiveWindowData> {
-        // Process any pending DBus events so our state is up-to-date when queried.
-        self.pump_dbus();
-        send_active_window(&self.active_window)
+        let data = match &self.window_source {
+            WindowSource::PlasmaWindowManagement(watcher) => lock_shared(watcher)?.run_iteration(),
+            WindowSource::KWinScript { active_window, .. } => {
+                // Process any pending DBus events so our state is up-to-date when queried.
+                self.dbus_connection.monitor_activity();
+                send_active_window(active_window)
+            }
+        }?;
+        self.record_window(&data);
+        Ok(data)
     }
 
+    #[tracing::instrument(name = "kde::is_idle", skip(self))]
     fn is_idle(&mut self) -> Result<bool> {
         // Keep consistency by pumping DBus here too, in case user calls this independently.
         self.pump_dbus();
 
-        let status_guard = self
-            .idle_watcher
-            .current_idle_status
+        Ok(self.idle_state()?.0)
+    }
+
+    fn is_screen_locked(&mut self) -> Result<bool> {
+        self.get_screensaver_active()
+    }
+
+    fn idle_duration(&mut self) -> Result<Duration> {
+        Ok(self.idle_state()?.1)
+    }
+
+    fn set_idle_timeout(&mut self, timeout: Duration) -> Result<()> {
+        let Some(idle_watcher) = &mut self.idle_watcher else {
+            return Err(WatcherError::ProtocolUnsupported("no idle protocol".into()).into());
+        };
+        idle_watcher.set_timeout(timeout)
+    }
+
+    fn get_app_icon(&mut self, app_id: &str) -> Result<Option<IconRef>> {
+        Ok(lock_desktop_info(&self.desktop_info).app_icon(app_id))
+    }
+
+    /// D-Bus is pumped once for the window and the idle state.
+    fn sample(&mut self) -> Result<Sample> {
+        let timestamp = Utc::now();
+        let window = self.get_active_window_data()?;
+        let (idle, idle_duration) = self.idle_state()?;
+        Ok(Sample {
+            window,
+            idle,
+            idle_duration,
+            timestamp,
+        })
+    }
+}
+
+/// The KWin script reports windows over D-Bus, which zbus serves on its own thread, so reading
+/// the window never waits for KWin. With plasma window management the Wayland roundtrip is made
+/// on tokio's blocking thread pool. The window is filtered according to the config, like
+/// `GenericWindowManager` does for the blocking calls.
+#[cfg(feature = "async")]
+impl crate::async_manager::AsyncWindowManager for KdeWindowManager {
+    async fn get_active_window_data(&mut self) -> Result<ActiveWindowData> {
+        let data = match &self.window_source {
+            WindowSource::PlasmaWindowManagement(watcher) => {
+                let watcher = Arc::clone(watcher);
+                tokio::task::spawn_blocking(move || lock_shared(&watcher)?.run_iteration()).await?
+            }
+            WindowSource::KWinScript { active_window, .. } => send_active_window(active_window),
+        }?;
+        self.record_window(&data);
+        self.filter
+            .apply(data)
+            .ok_or_else(|| crate::NoActiveWindow.into())
+    }
+
+    async fn is_idle(&mut self) -> Result<bool> {
+        Ok(self.idle_state()?.0)
+    }
+
+    async fn idle_duration(&mut self) -> Result<Duration> {
+        Ok(self.idle_state()?.1)
+    }
+
+    async fn is_screen_locked(&mut self) -> Result<bool> {
+        self.dbus_connection
+            .inner()
+            .call_method(
+                Some("org.freedesktop.ScreenSaver"),
+                "/ScreenSaver",
+                Some("org.freedesktop.ScreenSaver"),
+                "GetActive",
+                &(),
+            )
+            .await
+            .with_context(|| "Failed to get screensaver state")?
+            .body()
+            .deserialize()
+            .with_context(|| "Failed to deserialize screensaver state")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    /// The start of `supportInformation` of a Plasma 6 session.
+    const SUPPORT_INFORMATION: &str = "\
+KWin Support Information:
+The following information should be used when requesting support on e.g. https://discuss.kde.org.
+It provides information about the currently running instance, which options are used,
+what OpenGL driver and which effects are running.
+Please post the information provided underneath this introductory text to a paste bin service
+like https://paste.kde.org instead of pasting into support threads.
+
+==========================
+
+Version
+=======
+KWin version: 6.1.4
+Qt Version: 6.7.2
+Qt compile version: 6.7.2
+XCB compile version: 1.17.0
+
+Operation Mode: Xwayland
+
+Build Options
+=============
+KWIN_BUILD_DECORATIONS: yes
+KWIN_BUILD_TABBOX: yes
+HAVE_X11_XCB: yes
+HAVE_EPOXY_GLX: yes
+";
+
+    #[test]
+    fn major_version_is_parsed_from_support_information() {
+        assert_eq!(parse_major_version(SUPPORT_INFORMATION).unwrap(), 6);
+
+        let plasma_5 = SUPPORT_INFORMATION.replace("KWin version: 6.1.4", "KWin version: 5.27.11");
+        assert_eq!(parse_major_version(&plasma_5).unwrap(), 5);
+    }
+
+    #[test]
+    fn missing_or_invalid_version_is_an_error() {
+        let missing = SUPPORT_INFORMATION.replace("KWin version: 6.1.4\n", "");
+        assert!(parse_major_version(&missing).is_err());
+
+        let invalid = SUPPORT_INFORMATION.replace("6.1.4", "unknown");
+        assert!(parse_major_version(&invalid).is_err());
+        assert!(parse_major_version("").is_err());
+    }
+
+    /// Stands in for KWin on the session bus.
+    struct FakeKWin {
+        is_script_loaded: bool,
+        /// `None` fails the call like a missing Scripting object.
+        registered_number: Option<i32>,
+        is_run_failing: bool,
+        unload_count: Cell<u32>,
+    }
+
+    impl FakeKWin {
+        fn new(registered_number: Option<i32>) -> Self {
+            Self {
+                is_script_loaded: false,
+                registered_number,
+                is_run_failing: false,
+                unload_count: Cell::new(0),
+            }
+        }
+    }
+
+    impl KWinCalls for FakeKWin {
+        fn is_script_loaded(&self) -> anyhow::Result<bool> {
+            Ok(self.is_script_loaded)
+        }
+
+        fn load_script(&self, path: &str) -> anyhow::Result<i32> {
+            assert!(
+                Path::new(path).exists(),
+                "The script isn't written to {path}"
+            );
+            self.registered_number
+                .ok_or(anyhow!("org.freedesktop.DBus.Error.UnknownObject"))
+        }
+
+        fn unload_script(&self) -> anyhow::Result<bool> {
+            self.unload_count.set(self.unload_count.get() + 1);
+            Ok(true)
+        }
+
+        fn run_script(&self, _: &str) -> anyhow::Result<()> {
+            if self.is_run_failing {
+                return Err(anyhow!("org.freedesktop.DBus.Error.UnknownMethod"));
+            }
+            Ok(())
+        }
+
+        fn support_information(&self) -> anyhow::Result<String> {
+            Ok(SUPPORT_INFORMATION.into())
+        }
+    }
+
+    /// Every load writes the same temporary file.
+    static SCRIPT_FILE: Mutex<()> = Mutex::new(());
+
+    fn replace_and_load(kwin: FakeKWin) -> (anyhow::Result<()>, KWinScript<FakeKWin>) {
+        let _guard = SCRIPT_FILE
             .lock()
-            .expect("Mutex poisoned");
-        match *status_guard {
-            Some(Status::Active { .. }) => Ok(false),
-            Some(Status::Idle { .. }) => Ok(true),
-            None => Ok(false),
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let mut script = KWinScript::new(kwin);
+        (script.replace_and_load(), script)
+    }
+
+    fn is_backend_unavailable(result: &anyhow::Result<()>) -> bool {
+        matches!(
+            result
+                .as_ref()
+                .map_err(|e| e.downcast_ref::<WatcherError>()),
+            Err(Some(WatcherError::BackendUnavailable(_)))
+        )
+    }
+
+    #[test]
+    fn refused_script_is_backend_unavailable() {
+        let (result, script) = replace_and_load(FakeKWin::new(Some(-1)));
+        assert!(is_backend_unavailable(&result), "{result:?}");
+        assert!(!script.is_loaded);
+    }
+
+    #[test]
+    fn failed_load_call_is_backend_unavailable() {
+        let (result, _) = replace_and_load(FakeKWin::new(None));
+        assert!(is_backend_unavailable(&result), "{result:?}");
+    }
+
+    #[test]
+    fn failed_start_is_backend_unavailable() {
+        let (result, _) = replace_and_load(FakeKWin {
+            is_run_failing: true,
+            ..FakeKWin::new(Some(3))
+        });
+        assert!(is_backend_unavailable(&result), "{result:?}");
+    }
+
+    #[test]
+    fn stale_script_is_unloaded_before_loading() {
+        let (result, script) = replace_and_load(FakeKWin {
+            is_script_loaded: true,
+            ..FakeKWin::new(Some(0))
+        });
+        assert!(result.is_ok(), "{result:?}");
+        assert!(script.is_loaded);
+        assert_eq!(script.dbus_connection.unload_count.get(), 1);
+    }
+
+    #[test]
+    fn interface_accepts_the_script_signature() {
+        let interface = ActiveWindowInterface {
+            active_window: Arc::new(Mutex::new(ActiveWindow {
+                caption: "".into(),
+                resource_name: "".into(),
+                resource_class: "".into(),
+                process_path: None,
+                app_name: None,
+                geometry: None,
+                monitor: None,
+                pid: None,
+                window_type: None,
+                icon_name: None,
+                focused_at: None,
+            })),
+            last_notification: None,
+            desktop_info: DesktopInfoResolver::shared(&WatcherConfig::default()),
+            process_path_cache: SharedCache::new(WatcherConfig::default().cache_config),
+        };
+        let mut xml = String::new();
+        zbus::object_server::Interface::introspect_to_writer(&interface, &mut xml, 0);
+        let method = xml
+            .split("<method ")
+            .find(|method| method.starts_with("name=\"NotifyActiveWindow\""))
+            .expect("NotifyActiveWindow isn't exported");
+        let signature: String = method
+            .split("type=\"")
+            .skip(1)
+            .filter_map(|arg| arg.split('"').next())
+            .collect();
+
+        // The types kde.js passes to `callDBus`. Numbers that fit in an int32 are sent as `i`, others
+        // like `Date.now()` as `d`
+        let message =
+            zbus::Message::method_call("/com/github/anoromi/whatawhat_lib", "NotifyActiveWindow")
+                .unwrap()
+                .build(&(
+                    "Kate",
+                    "kate",
+                    "org.kde.kate",
+                    4242,
+                    0,
+                    0,
+                    1280,
+                    720,
+                    "DP-1",
+                    0,
+                    2560,
+                    1440,
+                    "_NET_WM_WINDOW_TYPE_NORMAL",
+                    1_760_000_000_123.0,
+                ))
+                .unwrap();
+        // The body of a call is displayed as a struct
+        assert_eq!(
+            format!("({signature})"),
+            message.body().signature().to_string()
+        );
+    }
+
+    #[test]
+    fn activation_time_is_read_from_milliseconds() {
+        assert_eq!(
+            activation_time(1_760_000_000_123.0),
+            UNIX_EPOCH + Duration::from_millis(1_760_000_000_123)
+        );
+
+        let before = SystemTime::now();
+        for invalid in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(activation_time(invalid) >= before, "{invalid}");
         }
     }
 }
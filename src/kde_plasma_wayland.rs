@@ -0,0 +1,246 @@
+//! Tracks the active window through org_kde_plasma_window_management, which KWin implements
+//! instead of the wlr and ext toplevel protocols.
+//!
+//! KWin only advertises the global to clients it trusts, and only one client can bind it at a
+//! time, so `KdeWindowManager` falls back to the KWin script when it's missing.
+
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::anyhow;
+use tracing::{debug, error, trace};
+use wayland_client::{
+    Connection, Dispatch, Proxy, QueueHandle, globals::GlobalListContents, protocol::wl_registry,
+};
+use wayland_protocols_plasma::plasma_window_management::client::{
+    org_kde_plasma_window::{Event as WindowEvent, OrgKdePlasmaWindow},
+    org_kde_plasma_window_management::{
+        Event as ManagementEvent, OrgKdePlasmaWindowManagement, State,
+    },
+};
+
+use crate::{
+    ActiveWindowData, NoActiveWindow,
+    config::WatcherConfig,
+    linux_desktop::{
+        DesktopInfo, DesktopInfoResolver, SharedDesktopInfo, get_process_path, lock_desktop_info,
+    },
+    simple_cache::SharedCache,
+    wl_connection::{WlEventConnection, subscribe_state},
+};
+
+/// Version where windows are announced with their uuid. `get_window` by internal id is
+/// deprecated since then.
+const WINDOW_WITH_UUID_SINCE: u32 = 13;
+/// Version where the destroy request is available.
+const DESTROY_SINCE: u32 = 4;
+
+struct PlasmaWindow {
+    title: String,
+    app_id: String,
+    pid: Option<u32>,
+    geometry: Option<(i32, i32, u32, u32)>,
+}
+
+struct PlasmaWindowState {
+    windows: HashMap<String, PlasmaWindow>,
+    current_window_id: Option<String>,
+}
+
+impl PlasmaWindowState {
+    fn new() -> Self {
+        Self {
+            windows: HashMap::new(),
+            current_window_id: None,
+        }
+    }
+
+    fn add_window(&mut self, window: OrgKdePlasmaWindow) {
+        debug!("Plasma window is received {}", window.id());
+        self.windows.insert(
+            window.id().to_string(),
+            PlasmaWindow {
+                title: String::new(),
+                app_id: String::new(),
+                pid: None,
+                geometry: None,
+            },
+        );
+    }
+}
+
+subscribe_state!(
+    wl_registry::WlRegistry,
+    GlobalListContents,
+    PlasmaWindowState
+);
+subscribe_state!(wl_registry::WlRegistry, (), PlasmaWindowState);
+
+impl Dispatch<OrgKdePlasmaWindowManagement, ()> for PlasmaWindowState {
+    fn event(
+        state: &mut Self,
+        management: &OrgKdePlasmaWindowManagement,
+        event: <OrgKdePlasmaWindowManagement as Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        queue_handle: &QueueHandle<Self>,
+    ) {
+        // Newer versions send both events for every window
+        match event {
+            ManagementEvent::WindowWithUuid { uuid, .. } => {
+                state.add_window(management.get_window_by_uuid(uuid, queue_handle, ()));
+            }
+            ManagementEvent::Window { id } if management.version() < WINDOW_WITH_UUID_SINCE => {
+                state.add_window(management.get_window(id, queue_handle, ()));
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Dispatch<OrgKdePlasmaWindow, ()> for PlasmaWindowState {
+    fn event(
+        state: &mut Self,
+        handle: &OrgKdePlasmaWindow,
+        event: <OrgKdePlasmaWindow as Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let id = handle.id().to_string();
+        let Some(window) = state.windows.get_mut(&id) else {
+            error!("Plasma window is not found: {id}");
+            return;
+        };
+        match event {
+            WindowEvent::TitleChanged { title } => {
+                trace!("Title is changed for {id}");
+                window.title = title;
+            }
+            WindowEvent::AppIdChanged { app_id } => {
+                trace!("App ID is changed for {id}: {app_id}");
+                window.app_id = app_id;
+            }
+            WindowEvent::PidChanged { pid } => {
+                window.pid = Some(pid);
+            }
+            WindowEvent::Geometry {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                window.geometry = Some((x, y, width, height));
+            }
+            WindowEvent::StateChanged { flags } => {
+                trace!("State is changed for {id}: {flags:#x}");
+                if flags & State::Active as u32 != 0 {
+                    trace!("Window is activated: {id}");
+                    state.current_window_id = Some(id);
+                } else if state.current_window_id.as_ref() == Some(&id) {
+                    trace!("Window is deactivated: {id}");
+                    state.current_window_id = None;
+                }
+            }
+            WindowEvent::Unmapped => {
+                trace!("Window is unmapped: {id}");
+                state.windows.remove(&id);
+                if state.current_window_id.as_ref() == Some(&id) {
+                    state.current_window_id = None;
+                }
+                if handle.version() >= DESTROY_SINCE {
+                    handle.destroy();
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+pub struct PlasmaWindowWatcher {
+    connection: WlEventConnection<PlasmaWindowState>,
+    state: PlasmaWindowState,
+    desktop_info: SharedDesktopInfo,
+    process_path_cache: SharedCache<u32, Option<Arc<str>>>,
+}
+
+impl PlasmaWindowWatcher {
+    /// Fails if the compositor doesn't advertise org_kde_plasma_window_management to this client.
+    pub fn from_config(config: WatcherConfig) -> anyhow::Result<Self> {
+        let desktop_info = DesktopInfoResolver::shared(&config);
+        Self::with_desktop_info(config, desktop_info)
+    }
+
+    #[deprecated(note = "use `from_config`, which every backend has")]
+    pub fn new(config: WatcherConfig) -> anyhow::Result<Self> {
+        Self::from_config(config)
+    }
+
+    /// Like `from_config`, but looks the apps up in `desktop_info`, which other watchers may share.
+    /// Fails if the compositor doesn't advertise org_kde_plasma_window_management to this client.
+    pub fn with_desktop_info(
+        config: WatcherConfig,
+        desktop_info: SharedDesktopInfo,
+    ) -> anyhow::Result<Self> {
+        let mut connection: WlEventConnection<PlasmaWindowState> = WlEventConnection::connect()?;
+        connection.get_plasma_window_management()?;
+
+        let mut state = PlasmaWindowState::new();
+        // The first roundtrip announces the windows, the second one delivers their state
+        for _ in 0..2 {
+            connection
+                .event_queue
+                .roundtrip(&mut state)
+                .map_err(|e| anyhow!("Event queue is not processed: {e}"))?;
+        }
+
+        Ok(Self {
+            connection,
+            state,
+            desktop_info,
+            process_path_cache: SharedCache::new(config.cache_config.clone()),
+        })
+    }
+
+    pub fn run_iteration(&mut self) -> anyhow::Result<ActiveWindowData> {
+        self.connection
+            .event_queue
+            .roundtrip(&mut self.state)
+            .map_err(|e| anyhow!("Event queue is not processed: {e}"))?;
+
+        let Some(active_window_id) = self.state.current_window_id.as_ref() else {
+            trace!("No window is active");
+            return Err(NoActiveWindow.into());
+        };
+        let active_window = self.state.windows.get(active_window_id).ok_or(anyhow!(
+            "Current window is not found by ID {active_window_id}"
+        ))?;
+        let app_id = active_window.app_id.clone();
+        let window_title = active_window.title.clone();
+        let geometry = active_window.geometry;
+        let pid = active_window.pid;
+
+        let desktop_info = lock_desktop_info(&self.desktop_info).get(&app_id);
+        let process_path = match &desktop_info {
+            Some(info) => Some(info.process_path.clone()),
+            None => pid.and_then(|pid| get_process_path(&self.process_path_cache, pid)),
+        };
+
+        Ok(ActiveWindowData {
+            window_title: window_title.into(),
+            app_identifier: Some(app_id.into()),
+            resource_class: None,
+            process_path,
+            icon_name: desktop_info.as_ref().and_then(DesktopInfo::icon_name),
+            publisher: None,
+            focused_at: None,
+            app_name: desktop_info.map(|info| info.app_name),
+            workspace: None,
+            geometry,
+            monitor: None,
+            is_fullscreen: None,
+            window_type: None,
+            virtual_desktop: None,
+            pid,
+        })
+    }
+}
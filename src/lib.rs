@@ -1,7 +1,13 @@
+#[cfg(feature = "async")]
+pub mod async_manager;
 #[cfg(feature = "gnome")]
 pub mod gnome;
 #[cfg(feature = "kde")]
 pub mod kde;
+#[cfg(feature = "kde")]
+pub mod kde_plasma_wayland;
+#[cfg(feature = "sway")]
+pub mod sway;
 #[cfg(feature = "wayland")]
 pub mod wayland_idle;
 #[cfg(feature = "wayland")]
@@ -10,6 +16,8 @@ pub mod wayland_wlr;
 pub mod win;
 #[cfg(feature = "win")]
 pub mod windows_desktop;
+#[cfg(feature = "win")]
+pub mod windows_idle;
 #[cfg(feature = "wayland")]
 pub mod wl_connection;
 #[cfg(feature = "x11")]
@@ -18,22 +26,36 @@ pub mod x11;
 #[cfg(feature = "macos")]
 pub mod macos;
 
+pub mod config;
+#[cfg(feature = "config-file")]
+mod config_file;
+#[cfg(feature = "mock")]
+pub mod fake;
+pub mod gnome_install;
 pub mod idle;
+#[cfg(feature = "json")]
+mod json;
 #[cfg(any(
     feature = "x11",
     feature = "wayland",
     feature = "gnome",
-    feature = "kde"
+    feature = "kde",
+    feature = "sway"
 ))]
 pub mod linux_desktop;
+#[cfg(feature = "replay")]
+pub mod replay;
+pub mod rules;
 pub mod simple_cache;
 pub mod utils;
-pub mod gnome_install;
-pub mod config;
 
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 #[cfg(any(
     feature = "x11",
     feature = "wayland",
@@ -42,22 +64,184 @@ use anyhow::Result;
 ))]
 use tracing::info;
 
-use crate::config::WatcherConfig;
+use crate::config::{LockedBehavior, RetryPolicy, WatcherConfig};
 
 #[derive(Debug, Clone)]
 pub struct ActiveWindowData {
     /// Name of the window. For example 'bash in hello' or 'Document 1' or 'Vibing in YouTube -
     /// Chrome'
     pub window_title: Arc<str>,
-    /// Represents an identifier of the application.
-    /// On windows it is a process name. For example `C:\Windows\System32\cmd.exe`
-    /// On x11 it is a process name. For example `/home/etc/nvim``
-    /// On wayland, gnome, and kde it's a resource class. For example `org.kde.kate`
+    /// Path of the executable. For example `C:\Windows\System32\cmd.exe` or `/usr/bin/nvim`.
     pub process_path: Option<Arc<str>>,
+    /// Identifier of the application the backend reports, used to find its desktop entry.
+    /// On windows it is the process path. For example `C:\Windows\System32\cmd.exe`
+    /// On x11 and kde it's the resource name, the instance part of WM_CLASS. For example `kate`
+    /// On wayland and gnome it's the app id. For example `org.kde.kate`
     pub app_identifier: Option<Arc<str>>,
+    /// The class part of WM_CLASS, for example `Kate` or `firefox`. Often differs from
+    /// `app_identifier` in case or naming. Only reported on X11 and by the KDE script.
+    pub resource_class: Option<Arc<str>>,
+    /// Human readable name of the application from its desktop entry or executable metadata.
+    /// For example `Kate`.
     pub app_name: Option<Arc<str>>,
+    /// Name of the workspace the window is on. Only reported by backends that get it for free,
+    /// like Sway and i3.
+    pub workspace: Option<Arc<str>>,
+    /// Position and size of the window on screen as `(x, y, width, height)`. Only reported by KDE.
+    pub geometry: Option<(i32, i32, u32, u32)>,
+    /// Display the window is on. If the window spans several displays, it's the one the window
+    /// entered most recently or the one containing its center, depending on the backend.
+    pub monitor: Option<MonitorInfo>,
+    /// Whether the window is fullscreen. `None` when the backend can't tell.
+    pub is_fullscreen: Option<bool>,
+    /// Id of the process owning the window. `None` on wlroots compositors other than Sway and
+    /// Hyprland, which don't report it, and when the window doesn't advertise it.
+    pub pid: Option<u32>,
+    /// Kind of the window, named like the `_NET_WM_WINDOW_TYPE` atoms, for example
+    /// `_NET_WM_WINDOW_TYPE_NORMAL` or `_NET_WM_WINDOW_TYPE_DIALOG`. Only reported on X11 and by
+    /// the KDE script.
+    pub window_type: Option<Arc<str>>,
+    /// Index of the current virtual desktop, from `_NET_CURRENT_DESKTOP`. Only reported on X11
+    /// window managers that set it.
+    pub virtual_desktop: Option<u32>,
+    /// The `Icon` of the app's desktop entry as written there, an icon theme name or an absolute
+    /// path. Only reported on Linux.
+    pub icon_name: Option<Arc<str>>,
+    /// Company that published the executable, from the `CompanyName` of its version resource.
+    /// Only reported on Windows.
+    pub publisher: Option<Arc<str>>,
+    /// When the window became active. Only reported by the KDE script.
+    pub focused_at: Option<SystemTime>,
+}
+
+/// Display a window is shown on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MonitorInfo {
+    /// Name of the display. For example `DP-1` on Linux or `\\.\DISPLAY1` on Windows.
+    pub name: Arc<str>,
+    /// Position of the display in the order the system enumerates them.
+    pub index: u32,
+    /// Width and height in pixels.
+    pub resolution: (u32, u32),
+}
+
+/// Icon of an application.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IconRef {
+    /// Name of an icon in the icon theme, for example `org.kde.kate`.
+    Name(Arc<str>),
+    /// Absolute path of the icon file.
+    Path(Arc<str>),
+    /// Pixels of the icon, row by row from the top left, 4 bytes of RGBA each. Reported on
+    /// Windows, where icons are embedded in the executable.
+    Rgba {
+        width: u32,
+        height: u32,
+        pixels: Arc<[u8]>,
+    },
+}
+
+/// Window title of the sentinel value reported while the screen is locked.
+pub const LOCKED_WINDOW_TITLE: &str = "Screen locked";
+
+impl ActiveWindowData {
+    /// Sentinel value reported instead of the active window while the screen is locked.
+    pub fn locked() -> Self {
+        Self {
+            window_title: LOCKED_WINDOW_TITLE.into(),
+            process_path: None,
+            app_identifier: None,
+            resource_class: None,
+            app_name: None,
+            workspace: None,
+            geometry: None,
+            monitor: None,
+            is_fullscreen: None,
+            pid: None,
+            window_type: None,
+            virtual_desktop: None,
+            icon_name: None,
+            publisher: None,
+            focused_at: None,
+        }
+    }
+
+    /// Id of the process owning the window, if the backend reported it.
+    pub fn pid(&self) -> Option<u32> {
+        self.pid
+    }
+}
+
+/// The active window and the idle state read together by `WindowManager::sample`.
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub window: ActiveWindowData,
+    pub idle: bool,
+    /// Time since the last input while the user is idle. Zero while they are active, or when the
+    /// backend can't tell.
+    pub idle_duration: Duration,
+    /// When the sample was taken.
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Returned by `get_active_window_data` when no window has focus, for example when the desktop
+/// is focused or the workspace is empty. `GenericWindowManager` also returns it for windows
+/// filtered out by the allow and deny lists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoActiveWindow;
+
+impl std::fmt::Display for NoActiveWindow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No window is active")
+    }
+}
+
+impl std::error::Error for NoActiveWindow {}
+
+/// Failures of the watchers callers may want to react to, for example by recreating the watcher.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatcherError {
+    /// The connection to the compositor is dead, usually because it crashed or restarted.
+    ProtocolError(String),
+    /// The compositor implements none of the protocols the feature needs.
+    ProtocolUnsupported(String),
+    /// The watcher gave up after repeated failures and has to be recreated or reset.
+    Fatal(String),
+    /// A call didn't complete in time. Later calls may succeed.
+    Transient(String),
+    /// The GNOME extension the GNOME watcher reads the focused window from can't be used.
+    ExtensionMissing {
+        state: gnome_install::ExtensionState,
+    },
+    /// The backend can't be used in this session, for example because KWin refuses to load
+    /// scripts. Another backend may still work.
+    BackendUnavailable(String),
+}
+
+impl std::fmt::Display for WatcherError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ProtocolError(e) => write!(f, "Connection to the compositor is lost: {e}"),
+            Self::ProtocolUnsupported(e) => {
+                write!(
+                    f,
+                    "Required protocol is not supported by the compositor: {e}"
+                )
+            }
+            Self::Fatal(e) => write!(f, "Watcher stopped after repeated failures: {e}"),
+            Self::Transient(e) => write!(f, "Call did not complete in time: {e}"),
+            Self::ExtensionMissing { state } => write!(
+                f,
+                "GNOME extension {} is {state}",
+                gnome_install::EXTENSION_UUID
+            ),
+            Self::BackendUnavailable(e) => write!(f, "Backend is unavailable: {e}"),
+        }
+    }
 }
 
+impl std::error::Error for WatcherError {}
+
 /// Intended to serve as a contract windows and linux systems must implement.
 #[cfg_attr(feature = "mock", mockall::automock)]
 pub trait WindowManager {
@@ -65,31 +249,360 @@ pub trait WindowManager {
 
     /// Retrieve amount of time user has been inactive in milliseconds
     fn is_idle(&mut self) -> Result<bool>;
+
+    /// Whether the screen is currently locked. Backends that can't detect it report `false`.
+    fn is_screen_locked(&mut self) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Time since the last input while the user is idle, like `Sample::idle_duration`. Zero while
+    /// they are active, or when the backend can't tell.
+    fn idle_duration(&mut self) -> Result<Duration> {
+        Ok(Duration::ZERO)
+    }
+
+    /// Every open window, the active one first if it's known.
+    fn get_window_list(&mut self) -> Result<Vec<ActiveWindowData>> {
+        Err(anyhow::anyhow!(
+            "Listing windows is not supported by this backend"
+        ))
+    }
+
+    /// Replaces the idle timeout the manager was created with.
+    fn set_idle_timeout(&mut self, timeout: Duration) -> Result<()> {
+        let _ = timeout;
+        Err(anyhow::anyhow!(
+            "Changing the idle timeout is not supported by this backend"
+        ))
+    }
+
+    /// Icon of the application with the given `app_identifier`, from its desktop entry. `None`
+    /// if the application has no icon or desktop entry.
+    fn get_app_icon(&mut self, app_id: &str) -> Result<Option<IconRef>> {
+        let _ = app_id;
+        Err(anyhow::anyhow!(
+            "Application icons are not supported by this backend"
+        ))
+    }
+
+    /// The active window and the idle state at the same moment. Backends that can read both at
+    /// once override it, the default calls `get_active_window_data` and `is_idle`.
+    fn sample(&mut self) -> Result<Sample> {
+        let timestamp = Utc::now();
+        let window = self.get_active_window_data()?;
+        Ok(Sample {
+            window,
+            idle: self.is_idle()?,
+            idle_duration: Duration::ZERO,
+            timestamp,
+        })
+    }
+}
+
+impl<W: WindowManager + ?Sized> WindowManager for Box<W> {
+    fn get_active_window_data(&mut self) -> Result<ActiveWindowData> {
+        (**self).get_active_window_data()
+    }
+
+    fn is_idle(&mut self) -> Result<bool> {
+        (**self).is_idle()
+    }
+
+    fn is_screen_locked(&mut self) -> Result<bool> {
+        (**self).is_screen_locked()
+    }
+
+    fn idle_duration(&mut self) -> Result<Duration> {
+        (**self).idle_duration()
+    }
+
+    fn get_window_list(&mut self) -> Result<Vec<ActiveWindowData>> {
+        (**self).get_window_list()
+    }
+
+    fn set_idle_timeout(&mut self, timeout: Duration) -> Result<()> {
+        (**self).set_idle_timeout(timeout)
+    }
+
+    fn get_app_icon(&mut self, app_id: &str) -> Result<Option<IconRef>> {
+        (**self).get_app_icon(app_id)
+    }
+
+    fn sample(&mut self) -> Result<Sample> {
+        (**self).sample()
+    }
+}
+
+/// Used by `impl_window_manager!`, so callers don't need to depend on anyhow.
+#[doc(hidden)]
+pub use anyhow as __anyhow;
+
+/// Implements `WindowManager` for a stub that always reports the same window and idle state,
+/// like `impl_window_manager!(MyStub, ActiveWindowData { .. }, false)`. The window expression is
+/// evaluated on every call, so it can't refer to local variables.
+#[macro_export]
+macro_rules! impl_window_manager {
+    ($manager:ty, $window:expr, $is_idle:expr $(,)?) => {
+        impl $crate::WindowManager for $manager {
+            fn get_active_window_data(
+                &mut self,
+            ) -> $crate::__anyhow::Result<$crate::ActiveWindowData> {
+                Ok($window)
+            }
+
+            fn is_idle(&mut self) -> $crate::__anyhow::Result<bool> {
+                Ok($is_idle)
+            }
+        }
+    };
+}
+
+/// Lets several threads share a manager. Each call holds the lock for its duration.
+impl<W: WindowManager + ?Sized> WindowManager for Arc<std::sync::Mutex<W>> {
+    fn get_active_window_data(&mut self) -> Result<ActiveWindowData> {
+        lock_shared(self)?.get_active_window_data()
+    }
+
+    fn is_idle(&mut self) -> Result<bool> {
+        lock_shared(self)?.is_idle()
+    }
+
+    fn is_screen_locked(&mut self) -> Result<bool> {
+        lock_shared(self)?.is_screen_locked()
+    }
+
+    fn idle_duration(&mut self) -> Result<Duration> {
+        lock_shared(self)?.idle_duration()
+    }
+
+    fn get_window_list(&mut self) -> Result<Vec<ActiveWindowData>> {
+        lock_shared(self)?.get_window_list()
+    }
+
+    fn set_idle_timeout(&mut self, timeout: Duration) -> Result<()> {
+        lock_shared(self)?.set_idle_timeout(timeout)
+    }
+
+    fn get_app_icon(&mut self, app_id: &str) -> Result<Option<IconRef>> {
+        lock_shared(self)?.get_app_icon(app_id)
+    }
+
+    fn sample(&mut self) -> Result<Sample> {
+        lock_shared(self)?.sample()
+    }
+}
+
+/// Fills the `window_title`, `app_id` and `pid` fields of the current span. Only called on
+/// filtered data, so the backends never record raw titles.
+fn record_window_fields(data: &ActiveWindowData) {
+    let span = tracing::Span::current();
+    span.record("window_title", &*data.window_title);
+    span.record("app_id", data.app_identifier.as_deref());
+    span.record("pid", data.pid);
+}
+
+pub(crate) fn lock_shared<W: ?Sized>(
+    manager: &std::sync::Mutex<W>,
+) -> Result<std::sync::MutexGuard<'_, W>> {
+    manager
+        .lock()
+        .map_err(|_| anyhow::anyhow!("A thread panicked while using the window manager"))
+}
+
+/// Locks `manager` and makes `call` outside of any async context. On a multi-threaded runtime the
+/// worker's other tasks are moved to another thread while waiting. Tokio doesn't tell whether a
+/// thread of a current-thread runtime may block, its async tasks can't but its `spawn_blocking`
+/// threads can. Waiting could deadlock there, so those calls only take a free lock and fail if
+/// another caller holds it.
+#[cfg(feature = "tokio")]
+fn with_tokio_lock<W: ?Sized, T>(
+    manager: &tokio::sync::Mutex<W>,
+    call: impl FnOnce(&mut W) -> Result<T>,
+) -> Result<T> {
+    use tokio::runtime::{Handle, RuntimeFlavor};
+    match Handle::try_current().map(|handle| handle.runtime_flavor()) {
+        Err(_) => call(&mut manager.blocking_lock()),
+        Ok(RuntimeFlavor::MultiThread) => {
+            tokio::task::block_in_place(|| call(&mut manager.blocking_lock()))
+        }
+        Ok(_) => match manager.try_lock() {
+            Ok(mut inner) => call(&mut inner),
+            Err(_) => Err(anyhow::anyhow!(
+                "The shared window manager is in use and can't be waited for on a current-thread \
+                 runtime, lock it with `lock().await` instead"
+            )),
+        },
+    }
+}
+
+/// Meant for synchronous code. Calls from a multi-threaded runtime block the worker thread while
+/// waiting, calls from a current-thread runtime fail if the lock is taken, see `with_tokio_lock`.
+#[cfg(feature = "tokio")]
+impl<W: WindowManager + ?Sized> WindowManager for Arc<tokio::sync::Mutex<W>> {
+    fn get_active_window_data(&mut self) -> Result<ActiveWindowData> {
+        with_tokio_lock(self, |inner| inner.get_active_window_data())
+    }
+
+    fn is_idle(&mut self) -> Result<bool> {
+        with_tokio_lock(self, |inner| inner.is_idle())
+    }
+
+    fn is_screen_locked(&mut self) -> Result<bool> {
+        with_tokio_lock(self, |inner| inner.is_screen_locked())
+    }
+
+    fn idle_duration(&mut self) -> Result<Duration> {
+        with_tokio_lock(self, |inner| inner.idle_duration())
+    }
+
+    fn get_window_list(&mut self) -> Result<Vec<ActiveWindowData>> {
+        with_tokio_lock(self, |inner| inner.get_window_list())
+    }
+
+    fn set_idle_timeout(&mut self, timeout: Duration) -> Result<()> {
+        with_tokio_lock(self, |inner| inner.set_idle_timeout(timeout))
+    }
+
+    fn get_app_icon(&mut self, app_id: &str) -> Result<Option<IconRef>> {
+        with_tokio_lock(self, |inner| inner.get_app_icon(app_id))
+    }
+
+    fn sample(&mut self) -> Result<Sample> {
+        with_tokio_lock(self, |inner| inner.sample())
+    }
+}
+
+/// Calls `call` until it succeeds, returns `NoActiveWindow` or `policy` gives up.
+fn with_retries<T>(policy: RetryPolicy, mut call: impl FnMut() -> Result<T>) -> Result<T> {
+    let mut failed_attempts = 0;
+    loop {
+        match call() {
+            Err(e) if !e.is::<NoActiveWindow>() => {
+                failed_attempts += 1;
+                let Some(delay) = policy.delay(failed_attempts) else {
+                    return Err(e);
+                };
+                tracing::debug!("Call failed, retrying in {delay:?}: {e}");
+                std::thread::sleep(delay);
+            }
+            result => return result,
+        }
+    }
 }
 
+/// The backend of `GenericWindowManager`. Every backend but the macOS one can be moved to
+/// another thread.
+#[cfg(not(feature = "macos"))]
+type Backend = Box<dyn WindowManager + Send>;
+#[cfg(feature = "macos")]
+type Backend = Box<dyn WindowManager>;
+
 /// Serves as a cross-compatible WindowManager implementation.
+///
+/// Every backend it picks from is created the same way, with `from_config`:
+///
+/// ```no_run
+/// use whatawhat_lib::{WindowManager, config::WatcherConfig};
+///
+/// fn create<W: WindowManager>(
+///     from_config: fn(WatcherConfig) -> anyhow::Result<W>,
+/// ) -> anyhow::Result<W> {
+///     from_config(WatcherConfig::default())
+/// }
+///
+/// # fn main() -> anyhow::Result<()> {
+/// #[cfg(feature = "gnome")]
+/// create(whatawhat_lib::gnome::GnomeWindowWatcher::from_config)?;
+/// #[cfg(feature = "kde")]
+/// create(whatawhat_lib::kde::KdeWindowManager::from_config)?;
+/// #[cfg(feature = "sway")]
+/// create(whatawhat_lib::sway::SwayWindowManager::from_config)?;
+/// #[cfg(feature = "wayland")]
+/// create(whatawhat_lib::wayland_wlr::WaylandWindowWatcher::from_config)?;
+/// #[cfg(feature = "x11")]
+/// create(whatawhat_lib::x11::LinuxWindowManager::from_config)?;
+/// #[cfg(feature = "win")]
+/// create(whatawhat_lib::win::WindowsWindowManager::from_config)?;
+/// #[cfg(feature = "macos")]
+/// create(whatawhat_lib::macos::MacosManger::from_config)?;
+/// create(whatawhat_lib::GenericWindowManager::new)?;
+/// # Ok(())
+/// # }
+/// ```
 pub struct GenericWindowManager {
-    inner: Box<dyn WindowManager>,
+    inner: Backend,
+    /// Name of the selected backend, recorded on the spans.
+    platform: &'static str,
+    behavior_when_locked: LockedBehavior,
+    retry_policy: RetryPolicy,
+    filter: rules::WindowFilter,
 }
 
 impl GenericWindowManager {
+    // Unused when no backend feature is enabled
+    #[allow(dead_code)]
+    fn with_inner(platform: &'static str, inner: Backend, config: &WatcherConfig) -> Self {
+        Self {
+            inner,
+            platform,
+            behavior_when_locked: config.behavior_when_locked,
+            retry_policy: config.retry_policy,
+            filter: rules::WindowFilter::from_config(config),
+        }
+    }
+
+    /// The window to report instead of the active one if the screen is locked, or an error if
+    /// `behavior_when_locked` asks for one.
+    fn locked_window(&mut self) -> Result<Option<ActiveWindowData>> {
+        match self.behavior_when_locked {
+            LockedBehavior::Ignore => Ok(None),
+            LockedBehavior::Error => {
+                if self.inner.is_screen_locked()? {
+                    return Err(anyhow::anyhow!("The screen is locked"));
+                }
+                Ok(None)
+            }
+            LockedBehavior::Sentinel => Ok(self
+                .inner
+                .is_screen_locked()?
+                .then(ActiveWindowData::locked)),
+        }
+    }
+
     pub fn new(_config: WatcherConfig) -> Result<Self> {
+        #[cfg(any(
+            feature = "wayland",
+            feature = "gnome",
+            feature = "kde",
+            feature = "sway"
+        ))]
+        let compositor = utils::detect_compositor();
+        // Shared by the backends that are tried, so the desktop entries are only loaded once
+        #[cfg(any(
+            feature = "x11",
+            feature = "wayland",
+            feature = "gnome",
+            feature = "kde",
+            feature = "sway"
+        ))]
+        let desktop_info = linux_desktop::DesktopInfoResolver::shared(&_config);
         #[cfg(feature = "win")]
         {
             use win::WindowsWindowManager;
-            return Ok(Self {
-                inner: Box::new(WindowsWindowManager::new(_config)),
-            });
+            let inner = Box::new(WindowsWindowManager::from_config(_config.clone())?);
+            return Ok(Self::with_inner("windows", inner, &_config));
         }
         #[cfg(feature = "gnome")]
         {
             use gnome::GnomeWindowWatcher;
-            let watcher = GnomeWindowWatcher::new(_config.clone());
+            let watcher = if compositor.may_be_gnome() {
+                GnomeWindowWatcher::with_desktop_info(_config.clone(), Arc::clone(&desktop_info))
+            } else {
+                Err(anyhow::anyhow!("The compositor is {compositor:?}"))
+            };
             match watcher {
                 Ok(watcher) => {
-                    let result = Ok(Self {
-                        inner: Box::new(watcher),
-                    });
+                    let result = Ok(Self::with_inner("gnome", Box::new(watcher), &_config));
                     info!("Loaded Gnome Wayland watcher");
                     return result;
                 }
@@ -102,12 +615,14 @@ impl GenericWindowManager {
         #[cfg(feature = "kde")]
         {
             use kde::KdeWindowManager;
-            let watcher = KdeWindowManager::new(_config.clone());
+            let watcher = if compositor.may_be_kde() {
+                KdeWindowManager::with_desktop_info(_config.clone(), Arc::clone(&desktop_info))
+            } else {
+                Err(anyhow::anyhow!("The compositor is {compositor:?}"))
+            };
             match watcher {
                 Ok(watcher) => {
-                    let result = Ok(Self {
-                        inner: Box::new(watcher),
-                    });
+                    let result = Ok(Self::with_inner("kde", Box::new(watcher), &_config));
                     info!("Loaded Kde wayland watcher");
                     return result;
                 }
@@ -117,15 +632,35 @@ impl GenericWindowManager {
                 }
             }
         }
+        // i3 is left to the X11 backend when it's built, the i3 IPC has no idle time
+        #[cfg(feature = "sway")]
+        if compositor == utils::Compositor::Sway
+            || (compositor.speaks_i3_ipc() && !cfg!(feature = "x11"))
+        {
+            use sway::SwayWindowManager;
+            match SwayWindowManager::with_desktop_info(_config.clone(), Arc::clone(&desktop_info)) {
+                Ok(watcher) => {
+                    let result = Ok(Self::with_inner("sway", Box::new(watcher), &_config));
+                    info!("Loaded Sway window manager");
+                    return result;
+                }
+                Err(e) => {
+                    use tracing::warn;
+                    warn!("Failed to load Sway window manager: {e}");
+                }
+            }
+        }
         #[cfg(feature = "wayland")]
         {
             use wayland_wlr::WaylandWindowWatcher;
-            let watcher = WaylandWindowWatcher::new(_config.clone());
+            let watcher = if compositor.may_support_wlr() {
+                WaylandWindowWatcher::with_desktop_info(_config.clone(), Arc::clone(&desktop_info))
+            } else {
+                Err(anyhow::anyhow!("The compositor is {compositor:?}"))
+            };
             match watcher {
                 Ok(watcher) => {
-                    let result = Ok(Self {
-                        inner: Box::new(watcher),
-                    });
+                    let result = Ok(Self::with_inner("wayland", Box::new(watcher), &_config));
                     info!("Loaded Wayland window watcher");
                     return result;
                 }
@@ -138,12 +673,10 @@ impl GenericWindowManager {
         #[cfg(feature = "x11")]
         {
             use x11::LinuxWindowManager;
-            let watcher = LinuxWindowManager::new(_config.clone());
+            let watcher = LinuxWindowManager::with_desktop_info(_config.clone(), desktop_info);
             match watcher {
                 Ok(watcher) => {
-                    let result = Ok(Self {
-                        inner: Box::new(watcher),
-                    });
+                    let result = Ok(Self::with_inner("x11", Box::new(watcher), &_config));
                     info!("Loaded X11 window manager");
                     return result;
                 }
@@ -156,23 +689,291 @@ impl GenericWindowManager {
         #[cfg(feature = "macos")]
         {
             use macos::MacosManger;
-            return Ok(Self {
-                inner: Box::new(MacosManger::new(_config)?),
-            });
+            let inner = Box::new(MacosManger::from_config(_config.clone())?);
+            return Ok(Self::with_inner("macos", inner, &_config));
         }
         #[allow(unreachable_code)]
         {
             Err(anyhow::anyhow!("No window manager was selected"))
         }
     }
+
+    /// Runs `new` on tokio's blocking thread pool, since connecting to the backends may block
+    /// on D-Bus or Wayland roundtrips and sleep between retries. Not available on macOS, where
+    /// the manager may have to stay on the main thread.
+    #[cfg(all(feature = "async", not(feature = "macos")))]
+    pub async fn new_async(config: WatcherConfig) -> Result<Self> {
+        tokio::task::spawn_blocking(move || Self::new(config)).await?
+    }
 }
 
 impl WindowManager for GenericWindowManager {
+    #[tracing::instrument(
+        name = "generic::get_active",
+        skip(self),
+        fields(platform = %self.platform, window_title, app_id, pid)
+    )]
     fn get_active_window_data(&mut self) -> Result<ActiveWindowData> {
-        self.inner.get_active_window_data()
+        if let Some(data) = self.locked_window()? {
+            return Ok(data);
+        }
+        let data = with_retries(self.retry_policy, || self.inner.get_active_window_data())?;
+        let data = self.filter.apply(data).ok_or(NoActiveWindow)?;
+        record_window_fields(&data);
+        Ok(data)
     }
 
+    #[tracing::instrument(name = "generic::is_idle", skip(self), fields(platform = %self.platform))]
     fn is_idle(&mut self) -> Result<bool> {
-        self.inner.is_idle()
+        with_retries(self.retry_policy, || self.inner.is_idle())
+    }
+
+    fn is_screen_locked(&mut self) -> Result<bool> {
+        self.inner.is_screen_locked()
+    }
+
+    fn idle_duration(&mut self) -> Result<Duration> {
+        with_retries(self.retry_policy, || self.inner.idle_duration())
+    }
+
+    /// Leaves out the windows the allow and deny lists reject and redacts excluded apps.
+    fn get_window_list(&mut self) -> Result<Vec<ActiveWindowData>> {
+        let windows = self.inner.get_window_list()?;
+        Ok(windows
+            .into_iter()
+            .filter_map(|data| self.filter.apply(data))
+            .collect())
+    }
+
+    fn set_idle_timeout(&mut self, timeout: Duration) -> Result<()> {
+        self.inner.set_idle_timeout(timeout)
+    }
+
+    fn get_app_icon(&mut self, app_id: &str) -> Result<Option<IconRef>> {
+        self.inner.get_app_icon(app_id)
+    }
+
+    /// Reports the locked screen and filters the window like `get_active_window_data`.
+    #[tracing::instrument(
+        name = "generic::sample",
+        skip(self),
+        fields(platform = %self.platform, window_title, app_id, pid)
+    )]
+    fn sample(&mut self) -> Result<Sample> {
+        if let Some(window) = self.locked_window()? {
+            return Ok(Sample {
+                window,
+                idle: self.inner.is_idle()?,
+                idle_duration: Duration::ZERO,
+                timestamp: Utc::now(),
+            });
+        }
+        let sample = with_retries(self.retry_policy, || self.inner.sample())?;
+        let window = self.filter.apply(sample.window).ok_or(NoActiveWindow)?;
+        record_window_fields(&window);
+        Ok(Sample { window, ..sample })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Counts the calls, so lost or doubled calls from other threads show up.
+    #[derive(Default)]
+    struct CountingManager {
+        calls: usize,
+    }
+
+    impl WindowManager for CountingManager {
+        fn get_active_window_data(&mut self) -> Result<ActiveWindowData> {
+            self.calls += 1;
+            Ok(ActiveWindowData::locked())
+        }
+
+        fn is_idle(&mut self) -> Result<bool> {
+            self.calls += 1;
+            Ok(false)
+        }
+    }
+
+    const THREADS: usize = 8;
+    const CALLS: usize = 100;
+
+    fn call_from_threads<W: WindowManager + Clone + Send + 'static>(manager: &W) {
+        let handles: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let mut manager = manager.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..CALLS {
+                        manager.get_active_window_data().unwrap();
+                        assert!(!manager.is_idle().unwrap());
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn std_mutex_is_shared_between_threads() {
+        let manager = Arc::new(std::sync::Mutex::new(CountingManager::default()));
+        call_from_threads(&manager);
+        assert_eq!(manager.lock().unwrap().calls, THREADS * CALLS * 2);
+    }
+
+    #[test]
+    fn poisoned_std_mutex_is_an_error() {
+        let manager = Arc::new(std::sync::Mutex::new(CountingManager::default()));
+        let poisoned = manager.clone();
+        let _ = std::thread::spawn(move || {
+            let _guard = poisoned.lock().unwrap();
+            panic!("Poisoning the mutex");
+        })
+        .join();
+        assert!(manager.clone().get_active_window_data().is_err());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn tokio_mutex_is_shared_between_threads() {
+        let manager = Arc::new(tokio::sync::Mutex::new(CountingManager::default()));
+        call_from_threads(&manager);
+        assert_eq!(manager.blocking_lock().calls, THREADS * CALLS * 2);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn tokio_mutex_is_usable_from_multi_threaded_runtime() {
+        let manager = Arc::new(tokio::sync::Mutex::new(CountingManager::default()));
+        let tasks: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let mut manager = manager.clone();
+                tokio::spawn(async move {
+                    for _ in 0..CALLS {
+                        manager.get_active_window_data().unwrap();
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(manager.lock().await.calls, THREADS * CALLS);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(flavor = "current_thread")]
+    async fn tokio_mutex_is_not_waited_for_on_current_thread_runtime() {
+        let mut manager = Arc::new(tokio::sync::Mutex::new(CountingManager::default()));
+        manager.get_active_window_data().unwrap();
+
+        let guard = manager.lock().await;
+        assert!(manager.clone().get_active_window_data().is_err());
+        drop(guard);
+        assert_eq!(manager.lock().await.calls, 1);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(flavor = "current_thread")]
+    async fn tokio_mutex_is_usable_from_spawn_blocking_on_current_thread_runtime() {
+        let manager = Arc::new(tokio::sync::Mutex::new(CountingManager::default()));
+        let mut shared = manager.clone();
+        tokio::task::spawn_blocking(move || {
+            for _ in 0..CALLS {
+                shared.get_active_window_data().unwrap();
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(manager.lock().await.calls, CALLS);
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn virtual_desktop_is_forwarded() {
+        let mut inner = MockWindowManager::new();
+        inner.expect_get_active_window_data().returning(|| {
+            Ok(ActiveWindowData {
+                window_title: "Editor".into(),
+                virtual_desktop: Some(3),
+                ..ActiveWindowData::locked()
+            })
+        });
+        let mut manager =
+            GenericWindowManager::with_inner("mock", Box::new(inner), &WatcherConfig::default());
+        let data = manager.get_active_window_data().unwrap();
+        assert_eq!(data.virtual_desktop, Some(3));
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn missing_virtual_desktop_stays_missing() {
+        let mut inner = MockWindowManager::new();
+        inner
+            .expect_get_active_window_data()
+            .returning(|| Ok(ActiveWindowData::locked()));
+        let mut manager =
+            GenericWindowManager::with_inner("mock", Box::new(inner), &WatcherConfig::default());
+        assert_eq!(
+            manager.get_active_window_data().unwrap().virtual_desktop,
+            None
+        );
+    }
+
+    /// Counts the attempts, failing with `error` the first `failures` of them.
+    fn failing_call(
+        attempts: &std::cell::Cell<u32>,
+        failures: u32,
+        error: fn() -> anyhow::Error,
+    ) -> impl FnMut() -> Result<u32> {
+        move || {
+            attempts.set(attempts.get() + 1);
+            if attempts.get() <= failures {
+                Err(error())
+            } else {
+                Ok(attempts.get())
+            }
+        }
+    }
+
+    fn transient() -> anyhow::Error {
+        anyhow::anyhow!("The compositor didn't answer")
+    }
+
+    #[test]
+    fn retries_until_success() {
+        let attempts = std::cell::Cell::new(0);
+        let call = failing_call(&attempts, 2, transient);
+        assert_eq!(with_retries(RetryPolicy::Immediate(2), call).unwrap(), 3);
+        assert_eq!(attempts.get(), 3);
+    }
+
+    #[test]
+    fn last_error_is_returned_when_attempts_run_out() {
+        let attempts = std::cell::Cell::new(0);
+        let result = with_retries(
+            RetryPolicy::Immediate(3),
+            failing_call(&attempts, 9, transient),
+        );
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 4);
+
+        let attempts = std::cell::Cell::new(0);
+        let result = with_retries(RetryPolicy::None, failing_call(&attempts, 9, transient));
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 1);
+    }
+
+    #[test]
+    fn no_active_window_isnt_retried() {
+        let attempts = std::cell::Cell::new(0);
+        let call = failing_call(&attempts, 9, || NoActiveWindow.into());
+        let error = with_retries(RetryPolicy::Immediate(5), call).unwrap_err();
+        assert!(error.is::<NoActiveWindow>());
+        assert_eq!(attempts.get(), 1);
     }
 }
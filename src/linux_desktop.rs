@@ -1,41 +1,990 @@
-use std::sync::Arc;
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant, SystemTime},
+};
 
-use freedesktop_desktop_entry::{DesktopEntry, unicase::Ascii};
-use tracing::warn;
+use freedesktop_desktop_entry::DesktopEntry;
+
+use crate::{
+    IconRef,
+    config::WatcherConfig,
+    simple_cache::{CacheConfig, SimpleCache},
+    utils::default_cache_config,
+};
+use tracing::{debug, warn};
+
+pub const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+pub const DEFAULT_ICON_SIZE: u32 = 48;
+
+/// Where Snap exports the desktop entries of installed snaps.
+const SNAP_APPLICATIONS: &str = "/var/lib/snapd/desktop/applications";
 
 pub struct LinuxDesktopInfo {
+    /// `None` until the first lookup.
+    loaded: Option<LoadedEntries>,
+    /// Minimal time between two checks of the application dirs after failed lookups.
+    refresh_interval: Duration,
+    last_check: Instant,
+    /// Preferred locales of the app names, most preferred first.
+    locales: Vec<String>,
+    icon_size: u32,
+    icon_theme: Option<String>,
+    /// Resolved icons by icon name.
+    icon_cache: SimpleCache<String, IconRef>,
+    /// Whether the user's application dir is searched before all others.
+    user_priority: bool,
+}
+
+struct LoadedEntries {
     entries: Vec<DesktopEntry>,
+    /// Modification time of every application dir when the entries were loaded, `None` if the
+    /// dir didn't exist.
+    dir_mtimes: Vec<(PathBuf, Option<SystemTime>)>,
 }
 
 #[derive(Clone)]
 pub struct DesktopInfo {
     pub app_name: Arc<str>,
     pub process_path: Arc<str>,
+    /// How the desktop entry was found from the app id.
+    pub matched_by: MatchStrategy,
+    /// The `Icon` of the entry, as written there.
+    pub icon: Option<IconRef>,
+}
+
+impl DesktopInfo {
+    /// The `Icon` of the entry, a name or a path.
+    pub fn icon_name(&self) -> Option<Arc<str>> {
+        match self.icon.as_ref()? {
+            IconRef::Name(name) | IconRef::Path(name) => Some(name.clone()),
+            IconRef::Rgba { .. } => None,
+        }
+    }
+}
+
+/// Desktop entries and a cache of the lookups in them. Watchers share one through
+/// `SharedDesktopInfo`, so the entries are only loaded once.
+pub struct DesktopInfoResolver {
+    /// Keyed by app id. `None` for apps without a desktop entry.
+    cache: SimpleCache<String, Option<DesktopInfo>>,
+    /// `None` if app metadata isn't resolved.
+    linux_desktop_info: Option<LinuxDesktopInfo>,
+}
+
+pub type SharedDesktopInfo = Arc<Mutex<DesktopInfoResolver>>;
+
+/// The cache stays usable if a thread panicked while holding the lock.
+pub(crate) fn lock_desktop_info(info: &SharedDesktopInfo) -> MutexGuard<'_, DesktopInfoResolver> {
+    info.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl DesktopInfoResolver {
+    /// Looks nothing up if `resolve_app_metadata` is false. Apps without a desktop entry are
+    /// cached for `negative_ttl`, or the refresh interval if it isn't set.
+    pub fn from_config(config: &WatcherConfig) -> Self {
+        Self {
+            cache: SimpleCache::new(CacheConfig {
+                negative_ttl: Some(
+                    config
+                        .cache_config
+                        .negative_ttl
+                        .unwrap_or(config.desktop_entries_refresh_interval),
+                ),
+                ..config.cache_config.clone()
+            }),
+            linux_desktop_info: config
+                .resolve_app_metadata
+                .then(|| LinuxDesktopInfo::from_config(config)),
+        }
+    }
+
+    pub fn shared(config: &WatcherConfig) -> SharedDesktopInfo {
+        Arc::new(Mutex::new(Self::from_config(config)))
+    }
+
+    /// The info of the app's desktop entry, cached by app id. Apps without an entry are searched
+    /// again once their negative entry expires.
+    pub fn get(&mut self, app_id: &str) -> Option<DesktopInfo> {
+        if let Some(info) = self.cache.get(&app_id.to_string()) {
+            return info;
+        }
+        let info = self.linux_desktop_info.as_mut()?.get_extra_info(app_id);
+        match &info {
+            Some(_) => self.cache.set(app_id.to_string(), info.clone()),
+            None => self.cache.set_negative(app_id.to_string()),
+        }
+        info
+    }
+
+    /// The process path and the app name of the app's desktop entry.
+    pub fn resolve(&mut self, app_id: &str) -> (Option<Arc<str>>, Option<Arc<str>>) {
+        match self.get(app_id) {
+            Some(info) => (Some(info.process_path), Some(info.app_name)),
+            None => (None, None),
+        }
+    }
+
+    /// Forgets the cached lookups and loads the desktop entries again, so updated apps are picked
+    /// up right away.
+    pub fn clear(&mut self) {
+        self.cache.clear();
+        if let Some(info) = &mut self.linux_desktop_info {
+            info.refresh();
+        }
+    }
+
+    /// See `LinuxDesktopInfo::app_icon`.
+    pub fn app_icon(&mut self, app_id: &str) -> Option<IconRef> {
+        self.linux_desktop_info.as_mut()?.app_icon(app_id)
+    }
+}
+
+/// The executable of the process, read from `/proc`. Processes that can't be resolved are
+/// cached as misses.
+#[cfg(any(
+    feature = "gnome",
+    feature = "kde",
+    feature = "wayland",
+    feature = "sway"
+))]
+pub(crate) fn get_process_path(
+    cache: &crate::simple_cache::SharedCache<u32, Option<Arc<str>>>,
+    pid: u32,
+) -> Option<Arc<str>> {
+    if let Some(path) = cache.get(&pid) {
+        return path;
+    }
+    let path: Option<Arc<str>> = std::fs::read_link(format!("/proc/{pid}/exe"))
+        .inspect_err(|e| tracing::trace!("Failed to resolve the executable of {pid}: {e}"))
+        .ok()
+        .and_then(|path| path.to_str().map(Into::into));
+    match &path {
+        Some(_) => cache.set(pid, path.clone()),
+        None => cache.set_negative(pid),
+    }
+    path
+}
+
+/// Ways an app id is matched with a desktop entry, tried in this order. All comparisons ignore
+/// case.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchStrategy {
+    /// The app id is the id of the entry.
+    Id,
+    /// The app id without a trailing `.desktop` is the id of the entry.
+    DesktopSuffix,
+    /// The app id is the `StartupWMClass` of the entry, which GTK and Electron apps often need.
+    StartupWmClass,
+    /// The last dot separated segment of the app id is the id of the entry, so
+    /// `org.mozilla.firefox` finds `firefox.desktop`.
+    LastSegment,
+    /// The app id is the last dot separated segment of the entry id, so `kate` finds
+    /// `org.kde.kate.desktop`.
+    EntryLastSegment,
+    /// The app id is the app name of a Snap entry, so `firefox` finds `firefox_firefox.desktop`.
+    SnapApp,
 }
 
 impl LinuxDesktopInfo {
     pub fn new() -> Self {
-        let entries = freedesktop_desktop_entry::desktop_entries(&["en_US".to_string()]);
-        Self { entries }
+        Self::with_refresh_interval(DEFAULT_REFRESH_INTERVAL)
     }
 
-    pub fn get_extra_info(&self, app_id: &str) -> Option<DesktopInfo> {
-        if !app_id.is_ascii() {
-            warn!("App ID is not ASCII: {}", app_id);
-            return None;
+    /// Like `new`, but the entries of `$XDG_DATA_HOME/applications` shadow the ones of every
+    /// other application dir.
+    pub fn new_with_user_priority() -> Self {
+        Self {
+            user_priority: true,
+            ..Self::new()
         }
-        let hm = Ascii::new(app_id);
-        let entry = freedesktop_desktop_entry::find_app_by_id(&self.entries, hm)?;
+    }
+
+    /// The entries are loaded on the first lookup. App names use the locales of the environment.
+    pub fn with_refresh_interval(refresh_interval: Duration) -> Self {
+        Self::with_locales(refresh_interval, Vec::new())
+    }
+
+    /// Uses the refresh interval, the locales and the icon settings of `config`.
+    pub fn from_config(config: &WatcherConfig) -> Self {
+        Self {
+            icon_size: config.icon_size,
+            icon_theme: config.icon_theme.clone(),
+            icon_cache: SimpleCache::new(config.cache_config.clone()),
+            ..Self::with_locales(
+                config.desktop_entries_refresh_interval,
+                config.locales.clone(),
+            )
+        }
+    }
+
+    /// If `locales` is empty, the ones of `LC_MESSAGES` and `LANG` are used.
+    pub fn with_locales(refresh_interval: Duration, locales: Vec<String>) -> Self {
+        Self {
+            loaded: None,
+            refresh_interval,
+            last_check: Instant::now(),
+            locales: if locales.is_empty() {
+                freedesktop_desktop_entry::get_languages_from_env()
+            } else {
+                locales
+            },
+            icon_size: DEFAULT_ICON_SIZE,
+            icon_theme: None,
+            icon_cache: SimpleCache::new(default_cache_config()),
+            user_priority: false,
+        }
+    }
+
+    /// Loads the desktop entries again, picking up applications installed since the last load.
+    pub fn refresh(&mut self) {
+        let dirs = self.application_dirs();
+        let dir_mtimes = dirs
+            .iter()
+            .map(|dir| (dir.clone(), modified(dir)))
+            .collect();
+        let entries = freedesktop_desktop_entry::Iter::new(dirs.into_iter())
+            .entries(Some(&self.locales))
+            .collect();
+        self.loaded = Some(LoadedEntries {
+            entries,
+            dir_mtimes,
+        });
+        self.last_check = Instant::now();
+    }
+
+    #[deprecated(note = "renamed to `refresh`")]
+    pub fn reload(&mut self) {
+        self.refresh();
+    }
+
+    /// Entries of earlier dirs win over entries with the same id in later ones.
+    fn application_dirs(&self) -> Vec<PathBuf> {
+        let mut dirs = application_dirs();
+        if self.user_priority
+            && let Some(user_dir) = data_home().map(|data_home| data_home.join("applications"))
+        {
+            dirs.retain(|dir| *dir != user_dir);
+            dirs.insert(0, user_dir);
+        }
+        dirs
+    }
+
+    fn ensure_loaded(&mut self) {
+        if self.loaded.is_none() {
+            self.refresh();
+        }
+    }
+
+    fn entries(&self) -> &[DesktopEntry] {
+        self.loaded
+            .as_ref()
+            .map_or(&[], |loaded| loaded.entries.as_slice())
+    }
+
+    /// Whether an application dir was modified, created or removed since the entries were loaded.
+    fn dirs_changed(&self) -> bool {
+        let Some(loaded) = &self.loaded else {
+            return true;
+        };
+        let dirs = self.application_dirs();
+        dirs.len() != loaded.dir_mtimes.len()
+            || dirs
+                .iter()
+                .zip(&loaded.dir_mtimes)
+                .any(|(dir, (loaded_dir, mtime))| dir != loaded_dir || modified(dir) != *mtime)
+    }
+
+    /// Finds the desktop entry of the app. If it's missing, the application dirs are checked
+    /// for changes at most once per refresh interval, and the entries are reloaded if they
+    /// changed.
+    pub fn get_extra_info(&mut self, app_id: &str) -> Option<DesktopInfo> {
+        self.ensure_loaded();
+        if self.find_entry(app_id).is_none() && self.last_check.elapsed() >= self.refresh_interval {
+            self.last_check = Instant::now();
+            if self.dirs_changed() {
+                debug!("No desktop entry for {app_id} and the application dirs changed, reloading");
+                self.refresh();
+            }
+        }
+        let (entry, matched_by) = self.find_entry(app_id)?;
+        debug!(
+            "Desktop entry {} matched {app_id} by {matched_by:?}",
+            entry.appid
+        );
+        self.entry_info(entry, matched_by)
+    }
+
+    /// The icon of the app's desktop entry. Icon names are resolved to a file of the configured
+    /// theme and size, and stay names if no file is found.
+    pub fn app_icon(&mut self, app_id: &str) -> Option<IconRef> {
+        let icon = self.get_extra_info(app_id)?.icon?;
+        let IconRef::Name(name) = icon else {
+            return Some(icon);
+        };
+        if let Some(resolved) = self.icon_cache.get(&name.to_string()) {
+            return Some(resolved);
+        }
+        let resolved = find_icon(&name, self.icon_size, self.icon_theme.as_deref())
+            .and_then(|path| path.to_str().map(|path| IconRef::Path(path.into())))
+            .unwrap_or_else(|| IconRef::Name(name.clone()));
+        self.icon_cache.set(name.to_string(), resolved.clone());
+        Some(resolved)
+    }
+
+    /// Tries the strategies of `MatchStrategy` in order.
+    fn find_entry(&self, app_id: &str) -> Option<(&DesktopEntry, MatchStrategy)> {
+        if let Some(entry) = self.find_by_id(app_id) {
+            return Some((entry, MatchStrategy::Id));
+        }
+        let app_id = match app_id.strip_suffix(".desktop") {
+            Some(stripped) => {
+                if let Some(entry) = self.find_by_id(stripped) {
+                    return Some((entry, MatchStrategy::DesktopSuffix));
+                }
+                stripped
+            }
+            None => app_id,
+        };
+        if let Some(entry) = self.entries().iter().find(|entry| {
+            entry
+                .startup_wm_class()
+                .is_some_and(|class| same_id(class, app_id))
+        }) {
+            return Some((entry, MatchStrategy::StartupWmClass));
+        }
+        if let Some(entry) = last_segment(app_id).and_then(|segment| self.find_by_id(segment)) {
+            return Some((entry, MatchStrategy::LastSegment));
+        }
+        if let Some(entry) = self.entries().iter().find(|entry| {
+            last_segment(&entry.appid).is_some_and(|segment| same_id(segment, app_id))
+        }) {
+            return Some((entry, MatchStrategy::EntryLastSegment));
+        }
+        self.entries()
+            .iter()
+            .find(|entry| snap_app(entry).is_some_and(|app| same_id(app, app_id)))
+            .map(|entry| (entry, MatchStrategy::SnapApp))
+    }
+
+    fn find_by_id(&self, id: &str) -> Option<&DesktopEntry> {
+        self.entries()
+            .iter()
+            .find(|entry| same_id(&entry.appid, id))
+    }
+
+    /// Lists the ids of all discovered desktop entries.
+    pub fn iter_all_app_ids(&mut self) -> impl Iterator<Item = &str> {
+        self.ensure_loaded();
+        self.entries().iter().map(|entry| entry.appid.as_str())
+    }
+
+    /// Lists the info of all discovered desktop entries, skipping the ones that can't be parsed.
+    /// Entries are parsed lazily as the iterator advances.
+    pub fn iter_all_info(&mut self) -> impl Iterator<Item = (&str, DesktopInfo)> {
+        self.ensure_loaded();
+        self.entries().iter().filter_map(|entry| {
+            Some((
+                entry.appid.as_str(),
+                self.entry_info(entry, MatchStrategy::Id)?,
+            ))
+        })
+    }
+
+    /// The name is the one of the first locale the entry has, or the unlocalized one.
+    fn entry_info(&self, entry: &DesktopEntry, matched_by: MatchStrategy) -> Option<DesktopInfo> {
         let exec_params = match entry.parse_exec() {
             Ok(params) => params,
             Err(e) => {
-                warn!("Failed to parse exec params for {}: {}", app_id, e);
+                warn!("Failed to parse exec params for {}: {}", entry.appid, e);
                 return None;
             }
         };
         Some(DesktopInfo {
-            app_name: entry.name(&["en_US".to_string()]).unwrap().into(),
-            process_path: exec_params.into_iter().next()?.into(),
+            app_name: entry
+                .name(&self.locales)
+                .or_else(|| entry.name::<&str>(&[]))?
+                .into(),
+            process_path: process_command(
+                &exec_params.iter().map(String::as_str).collect::<Vec<_>>(),
+            )?
+            .into(),
+            matched_by,
+            icon: entry.icon().map(|icon| {
+                if Path::new(icon).is_absolute() {
+                    IconRef::Path(icon.into())
+                } else {
+                    IconRef::Name(icon.into())
+                }
+            }),
         })
     }
 }
+
+/// Interpreters that run the script given as their first argument that isn't an option.
+const INTERPRETERS: &[&str] = &[
+    "sh", "bash", "dash", "zsh", "python", "python3", "perl", "ruby", "node", "gjs",
+];
+
+/// Finds the program an Exec line starts. Field codes, `env` and variable assignments are
+/// skipped, wrappers like `flatpak run` or interpreters are replaced by what they run, and bare
+/// names are resolved through `PATH`. A wrapper is only returned if its target can't be found.
+fn process_command(args: &[&str]) -> Option<String> {
+    let args: Vec<&str> = args
+        .iter()
+        .copied()
+        .filter(|arg| !is_field_code(arg))
+        .skip_while(|arg| *arg == "env" || *arg == "exec" || is_assignment(arg))
+        .collect();
+    let (&program, rest) = args.split_first()?;
+    let target = match program.rsplit('/').next().unwrap_or(program) {
+        // The flatpak app id, since its binary is only reachable inside the sandbox
+        "flatpak" => run_target(rest).map(str::to_string),
+        "snap" => run_target(rest).map(resolve_in_path),
+        "gtk-launch" => first_argument(rest).map(str::to_string),
+        "bwrap" => rest
+            .iter()
+            .position(|arg| *arg == "--")
+            .and_then(|separator| process_command(&rest[separator + 1..])),
+        name if INTERPRETERS.contains(&name) => interpreter_target(rest),
+        _ => None,
+    };
+    Some(target.unwrap_or_else(|| resolve_in_path(program)))
+}
+
+/// The target of `<wrapper> run [options] <target>`.
+fn run_target<'a>(args: &[&'a str]) -> Option<&'a str> {
+    match args.split_first()? {
+        (&"run", rest) => first_argument(rest),
+        _ => None,
+    }
+}
+
+fn first_argument<'a>(args: &[&'a str]) -> Option<&'a str> {
+    args.iter().copied().find(|arg| !arg.starts_with('-'))
+}
+
+/// The script an interpreter runs. For `-c` the command is split on whitespace and parsed again.
+fn interpreter_target(args: &[&str]) -> Option<String> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match *arg {
+            "-c" => {
+                let command: Vec<&str> = args.next()?.split_whitespace().collect();
+                return process_command(&command);
+            }
+            // Python modules are reported by name
+            "-m" => return args.next().map(|module| module.to_string()),
+            arg if arg.starts_with('-') => {}
+            script => return Some(script.to_string()),
+        }
+    }
+    None
+}
+
+/// `%f`, `%U` and the other field codes of the desktop entry spec.
+fn is_field_code(arg: &str) -> bool {
+    matches!(
+        arg.as_bytes(),
+        [
+            b'%',
+            b'f' | b'F'
+                | b'u'
+                | b'U'
+                | b'i'
+                | b'c'
+                | b'k'
+                | b'd'
+                | b'D'
+                | b'n'
+                | b'N'
+                | b'v'
+                | b'm'
+        ]
+    )
+}
+
+fn is_assignment(arg: &str) -> bool {
+    arg.split_once('=').is_some_and(|(name, _)| {
+        !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+/// The absolute path of an executable found in `PATH`, or the name itself if it isn't found or
+/// already contains a slash.
+fn resolve_in_path(name: &str) -> String {
+    use std::os::unix::fs::PermissionsExt;
+
+    if name.contains('/') {
+        return name.to_string();
+    }
+    std::env::var_os("PATH")
+        .and_then(|path| {
+            std::env::split_paths(&path)
+                .map(|dir| dir.join(name))
+                .find(|candidate| {
+                    candidate
+                        .metadata()
+                        .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+                })
+        })
+        .and_then(|path| path.to_str().map(str::to_string))
+        .unwrap_or_else(|| name.to_string())
+}
+
+/// The application dirs of the XDG data dirs, then the Flatpak and Snap export dirs in case
+/// they aren't part of `XDG_DATA_DIRS`.
+fn application_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = freedesktop_desktop_entry::default_paths().collect();
+    for dir in extra_paths() {
+        if !dirs.contains(&dir) {
+            dirs.push(dir);
+        }
+    }
+    dirs
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    path.metadata().and_then(|meta| meta.modified()).ok()
+}
+
+fn data_home() -> Option<PathBuf> {
+    std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))
+}
+
+/// Flatpak export dirs of the user and of the system, and the Snap one.
+fn extra_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::with_capacity(3);
+    if let Some(data_home) = data_home() {
+        paths.push(data_home.join("flatpak/exports/share/applications"));
+    }
+    paths.push(PathBuf::from("/var/lib/flatpak/exports/share/applications"));
+    paths.push(PathBuf::from(SNAP_APPLICATIONS));
+    paths
+}
+
+const ICON_EXTENSIONS: &[&str] = &["png", "svg", "xpm"];
+
+/// Where icons outside of any theme are kept.
+const PIXMAPS: &str = "/usr/share/pixmaps";
+
+/// A directory of an icon theme and the sizes its icons can be shown at.
+struct ThemeDir {
+    path: String,
+    min_size: u32,
+    max_size: u32,
+}
+
+impl ThemeDir {
+    fn distance(&self, size: u32) -> u32 {
+        if size < self.min_size {
+            self.min_size - size
+        } else {
+            size.saturating_sub(self.max_size)
+        }
+    }
+}
+
+/// Looks the icon up in `theme`, the themes it inherits from and hicolor, following the icon
+/// theme spec. Within a theme the directory closest to `size` wins. Icons that are in no theme
+/// are looked up in the pixmaps dir last.
+fn find_icon(name: &str, size: u32, theme: Option<&str>) -> Option<PathBuf> {
+    let base_dirs = icon_base_dirs();
+    // Themes are popped from the end, so hicolor comes after all the others
+    let mut pending: Vec<String> = std::iter::once("hicolor")
+        .chain(theme)
+        .map(str::to_string)
+        .collect();
+    let mut visited = Vec::new();
+    while let Some(theme) = pending.pop() {
+        if visited.contains(&theme) {
+            continue;
+        }
+        let Some((mut dirs, parents)) = read_theme_index(&base_dirs, &theme) else {
+            visited.push(theme);
+            continue;
+        };
+        dirs.sort_by_key(|dir| dir.distance(size));
+        for dir in &dirs {
+            for base in &base_dirs {
+                if let Some(path) = icon_file(&base.join(&theme).join(&dir.path), name) {
+                    return Some(path);
+                }
+            }
+        }
+        visited.push(theme);
+        pending.extend(parents.into_iter().rev());
+    }
+    icon_file(Path::new(PIXMAPS), name)
+}
+
+fn icon_file(dir: &Path, name: &str) -> Option<PathBuf> {
+    ICON_EXTENSIONS
+        .iter()
+        .map(|extension| dir.join(format!("{name}.{extension}")))
+        .find(|path| path.is_file())
+}
+
+/// `~/.icons`, then the `icons` dir of every XDG data dir and of the Flatpak exports.
+fn icon_base_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(home) = std::env::var_os("HOME") {
+        dirs.push(Path::new(&home).join(".icons"));
+    }
+    if let Some(data_home) = data_home() {
+        dirs.push(data_home.join("icons"));
+        dirs.push(data_home.join("flatpak/exports/share/icons"));
+    }
+    let data_dirs = std::env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+    dirs.extend(
+        data_dirs
+            .split(':')
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| Path::new(dir).join("icons")),
+    );
+    dirs.push(PathBuf::from("/var/lib/flatpak/exports/share/icons"));
+    dirs
+}
+
+/// The unscaled directories and the parents of a theme, from the first `index.theme` found.
+fn read_theme_index(base_dirs: &[PathBuf], theme: &str) -> Option<(Vec<ThemeDir>, Vec<String>)> {
+    let content = base_dirs
+        .iter()
+        .find_map(|base| std::fs::read_to_string(base.join(theme).join("index.theme")).ok())?;
+    let mut sections: Vec<(&str, Vec<(&str, &str)>)> = Vec::new();
+    for line in content.lines().map(str::trim) {
+        if let Some(section) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            sections.push((section, Vec::new()));
+        } else if let (Some((key, value)), Some((_, keys))) =
+            (line.split_once('='), sections.last_mut())
+        {
+            keys.push((key.trim(), value.trim()));
+        }
+    }
+    let get = |section: &str, key: &str| {
+        sections
+            .iter()
+            .find(|(name, _)| *name == section)
+            .and_then(|(_, keys)| keys.iter().find(|(name, _)| *name == key))
+            .map(|(_, value)| *value)
+    };
+    let list = |value: Option<&str>| -> Vec<String> {
+        value
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(str::to_string)
+            .collect()
+    };
+    let number = |section: &str, key: &str| get(section, key).and_then(|value| value.parse().ok());
+    let dirs = list(get("Icon Theme", "Directories"))
+        .into_iter()
+        .filter(|dir| number(dir, "Scale").unwrap_or(1) == 1)
+        .filter_map(|dir| {
+            let size: u32 = number(&dir, "Size")?;
+            let (min_size, max_size) = match get(&dir, "Type").unwrap_or("Threshold") {
+                "Fixed" => (size, size),
+                "Scalable" => (
+                    number(&dir, "MinSize").unwrap_or(size),
+                    number(&dir, "MaxSize").unwrap_or(size),
+                ),
+                _ => {
+                    let threshold = number(&dir, "Threshold").unwrap_or(2);
+                    (size.saturating_sub(threshold), size + threshold)
+                }
+            };
+            Some(ThemeDir {
+                path: dir,
+                min_size,
+                max_size,
+            })
+        })
+        .collect();
+    Some((dirs, list(get("Icon Theme", "Inherits"))))
+}
+
+/// Compares ids ignoring case. Non-ASCII ids are compared after lowercasing both sides.
+fn same_id(a: &str, b: &str) -> bool {
+    if a.is_ascii() && b.is_ascii() {
+        a.eq_ignore_ascii_case(b)
+    } else {
+        a.to_lowercase() == b.to_lowercase()
+    }
+}
+
+/// The part after the last dot, if the id has one.
+fn last_segment(id: &str) -> Option<&str> {
+    id.rsplit_once('.')
+        .map(|(_, segment)| segment)
+        .filter(|segment| !segment.is_empty())
+}
+
+/// The app part of a Snap entry id, which is `<snap>_<app>`.
+fn snap_app(entry: &DesktopEntry) -> Option<&str> {
+    if !entry.path.starts_with(SNAP_APPLICATIONS) {
+        return None;
+    }
+    entry.appid.split_once('_').map(|(_, app)| app)
+}
+
+impl Default for LinuxDesktopInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info_with_entries(entries: &[(&str, &str)]) -> LinuxDesktopInfo {
+        let locales = vec!["en".to_string()];
+        let entries = entries
+            .iter()
+            .map(|(path, contents)| {
+                DesktopEntry::from_str(*path, contents, Some(&locales)).unwrap()
+            })
+            .collect();
+        let mut info = LinuxDesktopInfo::with_locales(DEFAULT_REFRESH_INTERVAL, locales);
+        info.loaded = Some(LoadedEntries {
+            entries,
+            dir_mtimes: Vec::new(),
+        });
+        info
+    }
+
+    /// Writes the entries to a fresh application dir and loads it the way `refresh` does.
+    fn info_from_fixture_dir(name: &str, entries: &[(&str, &str)]) -> (LinuxDesktopInfo, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("whatawhat-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (file_name, contents) in entries {
+            std::fs::write(dir.join(file_name), contents).unwrap();
+        }
+        let mut info = LinuxDesktopInfo::with_locales(DEFAULT_REFRESH_INTERVAL, vec!["en".into()]);
+        info.loaded = Some(LoadedEntries {
+            entries: freedesktop_desktop_entry::Iter::new([dir.clone()].into_iter())
+                .entries(Some(&info.locales))
+                .collect(),
+            dir_mtimes: Vec::new(),
+        });
+        (info, dir)
+    }
+
+    #[test]
+    fn non_ascii_ids_ignore_case() {
+        assert!(same_id("Café", "cAFÉ"));
+        assert!(same_id("ÄRZTE", "ärzte"));
+        assert!(!same_id("Café", "Cafe"));
+        assert!(!same_id("ärzte", "arzte"));
+    }
+
+    #[test]
+    fn non_ascii_app_id_finds_its_entry() {
+        let info = info_with_entries(&[
+            (
+                "/usr/share/applications/org.example.Café.desktop",
+                "[Desktop Entry]\nType=Application\nName=Café\nExec=cafe\n",
+            ),
+            (
+                "/usr/share/applications/kalender.desktop",
+                "[Desktop Entry]\nType=Application\nName=Kalender\nExec=kalender\nStartupWMClass=Über-Kalender\n",
+            ),
+        ]);
+
+        let (entry, matched_by) = info.find_entry("org.example.CAFÉ").unwrap();
+        assert_eq!(entry.appid, "org.example.Café");
+        assert_eq!(matched_by, MatchStrategy::Id);
+
+        let (entry, matched_by) = info.find_entry("über-kalender").unwrap();
+        assert_eq!(entry.appid, "kalender");
+        assert_eq!(matched_by, MatchStrategy::StartupWmClass);
+
+        assert!(info.find_entry("org.example.Cafe").is_none());
+    }
+
+    #[test]
+    fn every_strategy_finds_its_entry() {
+        let (mut info, dir) = info_from_fixture_dir(
+            "strategies",
+            &[
+                (
+                    "org.gnome.TextEditor.desktop",
+                    "[Desktop Entry]\nName=Text Editor\nExec=gnome-text-editor\n",
+                ),
+                (
+                    "idea.desktop",
+                    "[Desktop Entry]\nName=IntelliJ IDEA\nExec=idea\nStartupWMClass=jetbrains-idea\n",
+                ),
+                (
+                    "firefox.desktop",
+                    "[Desktop Entry]\nName=Firefox\nExec=firefox\nStartupWMClass=firefox\n",
+                ),
+                (
+                    "org.kde.kate.desktop",
+                    "[Desktop Entry]\nName=Kate\nExec=kate\n",
+                ),
+            ],
+        );
+        // Only entries exported by Snap are matched by their app part
+        let snap_entry = DesktopEntry::from_str(
+            Path::new(SNAP_APPLICATIONS).join("spotify_spotify.desktop"),
+            "[Desktop Entry]\nName=Spotify\nExec=/snap/bin/spotify\n",
+            Some(&info.locales),
+        )
+        .unwrap();
+        info.loaded.as_mut().unwrap().entries.push(snap_entry);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let cases = [
+            (
+                "org.gnome.TextEditor",
+                "org.gnome.TextEditor",
+                MatchStrategy::Id,
+            ),
+            (
+                "ORG.GNOME.TEXTEDITOR",
+                "org.gnome.TextEditor",
+                MatchStrategy::Id,
+            ),
+            (
+                "org.gnome.TextEditor.desktop",
+                "org.gnome.TextEditor",
+                MatchStrategy::DesktopSuffix,
+            ),
+            ("jetbrains-idea", "idea", MatchStrategy::StartupWmClass),
+            ("org.mozilla.firefox", "firefox", MatchStrategy::LastSegment),
+            ("kate", "org.kde.kate", MatchStrategy::EntryLastSegment),
+            ("spotify", "spotify_spotify", MatchStrategy::SnapApp),
+        ];
+        for (app_id, entry_id, strategy) in cases {
+            let (entry, matched_by) = info
+                .find_entry(app_id)
+                .unwrap_or_else(|| panic!("No entry for {app_id}"));
+            assert_eq!(
+                (entry.appid.as_str(), matched_by),
+                (entry_id, strategy),
+                "{app_id}"
+            );
+        }
+        assert!(info.find_entry("org.example.Unknown").is_none());
+        assert!(info.find_entry("").is_none());
+    }
+
+    #[test]
+    fn process_command_finds_the_started_program() {
+        // Absolute paths and names missing from PATH, so the result doesn't depend on the system
+        let cases: &[(&[&str], &str)] = &[
+            (
+                &["/usr/bin/gnome-text-editor", "%U"],
+                "/usr/bin/gnome-text-editor",
+            ),
+            (
+                &[
+                    "flatpak",
+                    "run",
+                    "--branch=stable",
+                    "--arch=x86_64",
+                    "--command=spotify",
+                    "com.spotify.Client",
+                ],
+                "com.spotify.Client",
+            ),
+            (
+                &["/usr/bin/flatpak", "run", "org.gnome.Maps", "%U"],
+                "org.gnome.Maps",
+            ),
+            (&["/usr/bin/flatpak", "--help"], "/usr/bin/flatpak"),
+            (
+                &["snap", "run", "whatawhat-test-snap"],
+                "whatawhat-test-snap",
+            ),
+            (
+                &[
+                    "/usr/bin/bwrap",
+                    "--ro-bind",
+                    "/",
+                    "/",
+                    "--",
+                    "/opt/sandboxed/app",
+                    "%f",
+                ],
+                "/opt/sandboxed/app",
+            ),
+            (
+                &["sh", "-c", "exec /opt/app/bin/app --flag"],
+                "/opt/app/bin/app",
+            ),
+            (
+                &["env", "FOO=1", "BAR_2=x", "/opt/tool/tool", "%F"],
+                "/opt/tool/tool",
+            ),
+            (
+                &["env", "GDK_BACKEND=x11", "bash", "-c", "/opt/a/b %u"],
+                "/opt/a/b",
+            ),
+            (&["FOO=1", "/opt/x/x"], "/opt/x/x"),
+            (&["python3", "-m", "whatawhat_module"], "whatawhat_module"),
+            (
+                &["/usr/bin/python3", "-u", "/opt/script.py", "--arg"],
+                "/opt/script.py",
+            ),
+            (&["/bin/bash"], "/bin/bash"),
+            (
+                &["gtk-launch", "org.gnome.Calculator", "%u"],
+                "org.gnome.Calculator",
+            ),
+        ];
+        for (args, expected) in cases {
+            assert_eq!(
+                process_command(args).as_deref(),
+                Some(*expected),
+                "{args:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn process_command_needs_a_program() {
+        assert_eq!(process_command(&[]), None);
+        assert_eq!(process_command(&["%u", "%F"]), None);
+        assert_eq!(process_command(&["env", "FOO=1"]), None);
+    }
+
+    #[test]
+    fn repeated_lookups_are_cache_hits() {
+        let mut resolver = DesktopInfoResolver {
+            cache: SimpleCache::new(default_cache_config()),
+            linux_desktop_info: Some(info_with_entries(&[(
+                "/usr/share/applications/org.gnome.Calculator.desktop",
+                "[Desktop Entry]\nName=Calculator\nExec=/usr/bin/gnome-calculator\n",
+            )])),
+        };
+
+        let first = resolver.get("org.gnome.Calculator").unwrap();
+        assert_eq!(&*first.process_path, "/usr/bin/gnome-calculator");
+        assert!(resolver.get("org.example.Missing").is_none());
+        assert_eq!(resolver.cache.stats().hits, 0);
+
+        // Answered from the cache even though the entries are gone
+        resolver.linux_desktop_info.as_mut().unwrap().loaded = Some(LoadedEntries {
+            entries: Vec::new(),
+            dir_mtimes: Vec::new(),
+        });
+        let second = resolver.get("org.gnome.Calculator").unwrap();
+        assert_eq!(&*second.app_name, "Calculator");
+        assert!(resolver.get("org.example.Missing").is_none());
+
+        let stats = resolver.cache.stats();
+        assert_eq!((stats.hits, stats.misses), (2, 2));
+    }
+}
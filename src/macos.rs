@@ -3,19 +3,31 @@ use std::{
     process::{Child, Command, Stdio},
     sync::{Arc, Mutex},
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use anyhow::{Result, anyhow};
 use objc2::{AllocAnyThread, rc::Retained};
-use objc2_core_graphics::{CGEventSource, CGEventSourceStateID, CGEventType};
+use objc2_app_kit::NSWorkspace;
+use objc2_core_foundation::{CFBoolean, CFDictionary, CFNumber, CFRetained, CFString, CFType};
+use objc2_core_graphics::{
+    CGEventSource, CGEventSourceStateID, CGEventType, CGSessionCopyCurrentDictionary,
+};
 use objc2_foundation::{NSString, ns_string};
+use objc2_io_kit::{
+    IOObjectRelease, IORegistryEntryCreateCFProperty, IOServiceGetMatchingService,
+    IOServiceMatching, kIOMainPortDefault,
+};
 use objc2_osa_kit::{OSALanguage, OSAScript};
 use serde::{Deserialize, Serialize};
 use sysinfo::{self};
+use tracing::warn;
 
 use super::ActiveWindowData;
-use crate::{WindowManager, config::WatcherConfig};
+use crate::{
+    WindowManager,
+    config::{IdleSensitivity, WatcherConfig},
+};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -23,6 +35,8 @@ struct AppInfo {
     unix_id: u32,
     app: String,
     title: String,
+    #[serde(default)]
+    is_fullscreen: bool,
 }
 
 /// On-demand macOS manager: compiles the JXA once at construction and executes it
@@ -32,10 +46,13 @@ pub struct MacosManger {
     runner: MacosRunner,
     // script: Retained<OSAScript>,
     idle_timeout: Duration,
+    idle_sensitivity: IdleSensitivity,
+    treat_lock_as_idle: bool,
+    use_iokit_idle: bool,
 }
 
 impl MacosManger {
-    pub fn new(config: WatcherConfig) -> Result<Self> {
+    pub fn from_config(config: WatcherConfig) -> Result<Self> {
         let runner = if config.am_on_main_thread {
             create_on_main_thread_osascript_process()?
         } else {
@@ -47,11 +64,20 @@ impl MacosManger {
             sysinfo: sysinfo::System::new_all(),
             runner,
             idle_timeout: config.idle_timeout,
+            idle_sensitivity: config.idle_sensitivity,
+            treat_lock_as_idle: config.treat_lock_as_idle,
+            use_iokit_idle: config.macos_config.use_iokit_idle,
         })
     }
+
+    #[deprecated(note = "use `from_config`, which every backend has")]
+    pub fn new(config: WatcherConfig) -> Result<Self> {
+        Self::from_config(config)
+    }
 }
 
 impl WindowManager for MacosManger {
+    #[tracing::instrument(name = "macos::get_active", skip(self))]
     fn get_active_window_data(&mut self) -> Result<ActiveWindowData> {
         let app_info = match &mut self.runner {
             MacosRunner::OnMainThread { script } => {
@@ -95,33 +121,128 @@ impl WindowManager for MacosManger {
         let path = self.sysinfo.process(pid);
         let process_path = match path.and_then(|p| p.exe()) {
             Some(path) => path.to_str().map(|s| s.to_string()),
-            None => None,
+            // sysinfo can't read sandboxed processes
+            None => get_process_path_from_nsworkspace(app_info.unix_id),
         };
 
-        Ok(ActiveWindowData {
+        let data = ActiveWindowData {
             window_title: app_info.title.into(),
             process_path: process_path.map(|s| s.into()),
             app_identifier: None, // Could be a bundle ID in future; app name is below
+            resource_class: None,
             app_name: Some(app_info.app.into()),
-        })
+            workspace: None,
+            geometry: None,
+            monitor: None,
+            is_fullscreen: Some(app_info.is_fullscreen),
+            window_type: None,
+            virtual_desktop: None,
+            icon_name: None,
+            publisher: None,
+            focused_at: None,
+            pid: Some(app_info.unix_id),
+        };
+        Ok(data)
     }
 
+    #[tracing::instrument(name = "macos::is_idle", skip(self))]
     fn is_idle(&mut self) -> Result<bool> {
-        let any_event = CGEventType(!0);
-        let last_input = unsafe {
-            CGEventSource::seconds_since_last_event_type(
-                CGEventSourceStateID::HIDSystemState,
-                any_event,
-            )
+        if self.treat_lock_as_idle && is_session_locked() {
+            return Ok(true);
+        }
+
+        let last_input = if self.use_iokit_idle {
+            get_iokit_idle_seconds().unwrap_or_else(|e| {
+                warn!("Failed to get IOKit idle time, falling back to CGEventSource: {e}");
+                get_event_source_idle_seconds()
+            })
+        } else {
+            get_event_source_idle_seconds()
         };
-        Ok(last_input > self.idle_timeout.as_secs_f64())
+        // Negative or NaN readings are treated as input just now
+        let since_input = Duration::try_from_secs_f64(last_input).unwrap_or_default();
+        Ok(self
+            .idle_sensitivity
+            .is_idle(since_input, self.idle_timeout))
+    }
+
+    fn is_screen_locked(&mut self) -> Result<bool> {
+        Ok(is_session_locked())
+    }
+
+    fn set_idle_timeout(&mut self, timeout: Duration) -> Result<()> {
+        self.idle_timeout = timeout;
+        Ok(())
+    }
+}
+
+fn get_event_source_idle_seconds() -> f64 {
+    let any_event = CGEventType(!0);
+    unsafe {
+        CGEventSource::seconds_since_last_event_type(
+            CGEventSourceStateID::HIDSystemState,
+            any_event,
+        )
     }
 }
 
+/// Reads `HIDIdleTime` of the IOHIDSystem service, the same value `IOHIDGetParameter` reports
+/// for `kIOHIDIdleTimeKey`. Unlike `CGEventSource` it accounts for every HID device.
+pub fn get_iokit_idle_seconds() -> Result<f64> {
+    let matching = unsafe { IOServiceMatching(c"IOHIDSystem".as_ptr()) }
+        .ok_or_else(|| anyhow!("Failed to create IOHIDSystem matching dictionary"))?;
+    let matching = unsafe { CFRetained::cast_unchecked::<CFDictionary>(matching) };
+    let service = unsafe { IOServiceGetMatchingService(kIOMainPortDefault, Some(matching)) };
+    if service == 0 {
+        return Err(anyhow!("IOHIDSystem service is not found"));
+    }
+
+    let property = unsafe {
+        IORegistryEntryCreateCFProperty(
+            service,
+            Some(&CFString::from_static_str("HIDIdleTime")),
+            None,
+            0,
+        )
+    };
+    unsafe { IOObjectRelease(service) };
+
+    let idle_nanoseconds = property
+        .and_then(|property| property.downcast_ref::<CFNumber>()?.as_i64())
+        .ok_or_else(|| anyhow!("HIDIdleTime is not available"))?;
+    Ok(idle_nanoseconds as f64 / 1_000_000_000.0)
+}
+
+/// Finds the executable of a running application through `NSWorkspace`. Works for app bundles
+/// sysinfo can't inspect, like sandboxed ones.
+pub fn get_process_path_from_nsworkspace(pid: u32) -> Option<String> {
+    let workspace = unsafe { NSWorkspace::sharedWorkspace() };
+    let applications = unsafe { workspace.runningApplications() };
+    let application = applications
+        .iter()
+        .find(|application| unsafe { application.processIdentifier() } as i64 == i64::from(pid))?;
+    let url = unsafe { application.executableURL() }?;
+    let path = unsafe { url.path() }?;
+    Some(path.to_string())
+}
+
+fn is_session_locked() -> bool {
+    let Some(session) = (unsafe { CGSessionCopyCurrentDictionary() }) else {
+        return false;
+    };
+    // The session dictionary maps CFString keys to arbitrary CF values
+    let session = unsafe { CFRetained::cast_unchecked::<CFDictionary<CFString, CFType>>(session) };
+    session
+        .get(&CFString::from_static_str("CGSSessionScreenIsLocked"))
+        .and_then(|value| value.downcast_ref::<CFBoolean>().map(|value| value.value()))
+        .unwrap_or(false)
+}
+
 enum MacosRunner {
     SeparateProcess {
         process: Child,
-        _handle: thread::JoinHandle<Result<()>>,
+        /// Taken when the thread is joined on drop.
+        handle: Option<thread::JoinHandle<Result<()>>>,
         stop_signal: std::sync::mpsc::Sender<()>,
         current_app_info: Arc<Mutex<Option<AppInfo>>>,
     },
@@ -188,7 +309,7 @@ fn create_separate_osascript_process(collection_interval: Duration) -> Result<Ma
     });
     Ok(MacosRunner::SeparateProcess {
         process,
-        _handle: handle,
+        handle: Some(handle),
         stop_signal,
         current_app_info,
     })
@@ -199,17 +320,43 @@ impl Drop for MacosRunner {
         match self {
             MacosRunner::SeparateProcess {
                 process,
+                handle,
                 stop_signal,
                 ..
             } => {
+                // The signal goes first so the thread can exit on its own once the pipe closes
                 let _ = stop_signal.send(());
                 let _ = process.kill();
+                let _ = process.wait();
+                if let Some(handle) = handle.take() {
+                    join_with_timeout(handle, READER_STOP_TIMEOUT);
+                }
             }
             MacosRunner::OnMainThread { .. } => {}
         }
     }
 }
 
+const READER_STOP_TIMEOUT: Duration = Duration::from_secs(1);
+const READER_STOP_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Joins the thread if it finishes in time, otherwise leaves it detached.
+fn join_with_timeout(handle: thread::JoinHandle<Result<()>>, timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    while !handle.is_finished() {
+        let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+            warn!("osascript reader thread didn't stop in {timeout:?}, detaching it");
+            return;
+        };
+        thread::park_timeout(remaining.min(READER_STOP_POLL_INTERVAL));
+    }
+    match handle.join() {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => warn!("osascript reader thread failed: {e}"),
+        Err(_) => warn!("osascript reader thread panicked"),
+    }
+}
+
 fn create_osascript_command(collection_interval: Duration) -> String {
     format!(
         r#"#!/usr/bin/osascript -l JavaScript
@@ -233,7 +380,8 @@ function getApp() {{
   // and the previously set values will be cached otherwise
   var url = undefined,
     incognito = undefined,
-    title = undefined
+    title = undefined,
+    mainWindow = undefined
 
   // it's not possible to get the URL from firefox
   // https://stackoverflow.com/questions/17846948/does-firefox-offer-applescript-support-to-get-url-of-windows
@@ -271,6 +419,16 @@ function getApp() {{
       }}
   }}
 
+  // browsers are handled above without looking at the main window
+  if (!mainWindow) {{
+    mainWindow = oProcess
+      .windows()
+      .find((w) => w.attributes.byName("AXMain").value() === true)
+  }}
+  var isFullscreen = mainWindow
+    ? mainWindow.attributes.byName("AXFullScreen").value() === true
+    : false
+
   // key names must match expected names in lib.py
   return JSON.stringify({{
     app: appName,
@@ -278,6 +436,7 @@ function getApp() {{
     title,
     incognito,
     unixId,
+    isFullscreen,
   }})
 }}
 
@@ -295,3 +454,35 @@ runCollector()
         collection_interval.as_secs()
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Output of the JXA script for a browser window.
+    const SCRIPT_OUTPUT: &str = r#"{
+        "app": "Safari",
+        "url": "https://example.com/",
+        "unixId": 4242,
+        "title": "Example Domain",
+        "incognito": false,
+        "isFullscreen": true
+    }"#;
+
+    #[test]
+    fn app_info_is_parsed_from_script_output() {
+        let app_info: AppInfo = serde_json::from_str(SCRIPT_OUTPUT).unwrap();
+        assert_eq!(app_info.unix_id, 4242);
+        assert_eq!(app_info.app, "Safari");
+        assert_eq!(app_info.title, "Example Domain");
+        assert!(app_info.is_fullscreen);
+    }
+
+    #[test]
+    fn app_info_without_is_fullscreen_is_not_fullscreen() {
+        // Older versions of the script don't report it
+        let app_info: AppInfo =
+            serde_json::from_str(r#"{ "app": "Finder", "unixId": 1, "title": "" }"#).unwrap();
+        assert!(!app_info.is_fullscreen);
+    }
+}
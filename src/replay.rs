@@ -0,0 +1,231 @@
+//! Recording of the windows a `WindowManager` reports, and replaying them without a desktop.
+//!
+//! Recordings are JSON arrays with one entry per `get_active_window_data` call. An entry is the
+//! window data in the format of `serde_json::Value::from(ActiveWindowData)`, or `null` when no
+//! window was active.
+
+use std::{path::Path, time::Duration};
+
+use anyhow::{Context, Result, anyhow};
+use serde_json::Value;
+
+use crate::{ActiveWindowData, IconRef, NoActiveWindow, Sample, WindowManager};
+
+/// Passes the calls through to the wrapped manager and keeps every active window it returns.
+/// Errors other than `NoActiveWindow` aren't recorded.
+pub struct RecordingWindowManager<W> {
+    inner: W,
+    recording: Vec<Option<ActiveWindowData>>,
+}
+
+impl<W: WindowManager> RecordingWindowManager<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            recording: Vec::new(),
+        }
+    }
+
+    /// The recorded windows in call order, `None` where no window was active.
+    pub fn recording(&self) -> &[Option<ActiveWindowData>] {
+        &self.recording
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    fn record(&mut self, result: Result<&ActiveWindowData, &anyhow::Error>) {
+        match result {
+            Ok(data) => self.recording.push(Some(data.clone())),
+            Err(e) if e.is::<NoActiveWindow>() => self.recording.push(None),
+            Err(_) => {}
+        }
+    }
+
+    pub fn save_to_file(&self, path: &Path) -> Result<()> {
+        let entries: Vec<Value> = self
+            .recording
+            .iter()
+            .map(|data| data.clone().map_or(Value::Null, Value::from))
+            .collect();
+        let content = serde_json::to_string_pretty(&entries)?;
+        std::fs::write(path, content)
+            .with_context(|| format!("Failed to write the recording to {}", path.display()))
+    }
+}
+
+impl<W: WindowManager> WindowManager for RecordingWindowManager<W> {
+    fn get_active_window_data(&mut self) -> Result<ActiveWindowData> {
+        let result = self.inner.get_active_window_data();
+        self.record(result.as_ref());
+        result
+    }
+
+    fn is_idle(&mut self) -> Result<bool> {
+        self.inner.is_idle()
+    }
+
+    fn is_screen_locked(&mut self) -> Result<bool> {
+        self.inner.is_screen_locked()
+    }
+
+    fn idle_duration(&mut self) -> Result<Duration> {
+        self.inner.idle_duration()
+    }
+
+    fn get_window_list(&mut self) -> Result<Vec<ActiveWindowData>> {
+        self.inner.get_window_list()
+    }
+
+    fn set_idle_timeout(&mut self, timeout: Duration) -> Result<()> {
+        self.inner.set_idle_timeout(timeout)
+    }
+
+    fn get_app_icon(&mut self, app_id: &str) -> Result<Option<IconRef>> {
+        self.inner.get_app_icon(app_id)
+    }
+
+    fn sample(&mut self) -> Result<Sample> {
+        let result = self.inner.sample();
+        self.record(result.as_ref().map(|sample| &sample.window));
+        result
+    }
+}
+
+/// Returns the windows of a recording in order, starting over after the last one. The user is
+/// never idle.
+pub struct ReplayWindowManager {
+    entries: Vec<Option<ActiveWindowData>>,
+    position: usize,
+}
+
+impl ReplayWindowManager {
+    pub fn new(entries: Vec<Option<ActiveWindowData>>) -> Result<Self> {
+        if entries.is_empty() {
+            return Err(anyhow!("The recording has no entries"));
+        }
+        Ok(Self {
+            entries,
+            position: 0,
+        })
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read the recording {}", path.display()))?;
+        let Value::Array(values) = serde_json::from_str(&content)? else {
+            return Err(anyhow!("The recording is not a JSON array"));
+        };
+        let entries = values
+            .into_iter()
+            .map(|value| match value {
+                Value::Null => Ok(None),
+                value => ActiveWindowData::try_from(value).map(Some),
+            })
+            .collect::<Result<_>>()?;
+        Self::new(entries)
+    }
+}
+
+impl WindowManager for ReplayWindowManager {
+    fn get_active_window_data(&mut self) -> Result<ActiveWindowData> {
+        let entry = self.entries[self.position].clone();
+        self.position = (self.position + 1) % self.entries.len();
+        entry.ok_or_else(|| NoActiveWindow.into())
+    }
+
+    fn is_idle(&mut self) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn window(title: &str, app_identifier: &str) -> ActiveWindowData {
+        ActiveWindowData {
+            window_title: title.into(),
+            app_identifier: Some(Arc::from(app_identifier)),
+            pid: Some(42),
+            ..ActiveWindowData::locked()
+        }
+    }
+
+    fn recording_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("whatawhat-{name}-{}.json", std::process::id()))
+    }
+
+    /// Title and app id of the next window, `None` if the entry was `NoActiveWindow`.
+    fn next(manager: &mut impl WindowManager) -> Option<(String, Option<String>)> {
+        match manager.get_active_window_data() {
+            Ok(data) => Some((
+                data.window_title.to_string(),
+                data.app_identifier.as_deref().map(str::to_owned),
+            )),
+            Err(e) => {
+                assert!(e.is::<NoActiveWindow>(), "unexpected error {e:?}");
+                None
+            }
+        }
+    }
+
+    fn entry(title: &str, app_identifier: &str) -> Option<(String, Option<String>)> {
+        Some((title.to_owned(), Some(app_identifier.to_owned())))
+    }
+
+    #[test]
+    fn recording_round_trips_through_a_file() {
+        let source = ReplayWindowManager::new(vec![
+            Some(window("Document 1", "org.kde.kate")),
+            None,
+            Some(window("bash in hello", "org.kde.konsole")),
+        ])
+        .unwrap();
+        let mut recorder = RecordingWindowManager::new(source);
+        for _ in 0..3 {
+            let _ = recorder.get_active_window_data();
+        }
+        assert_eq!(recorder.recording().len(), 3);
+        assert!(recorder.recording()[1].is_none());
+
+        let path = recording_path("replay-round-trip");
+        recorder.save_to_file(&path).unwrap();
+        let replay = ReplayWindowManager::from_file(&path);
+        let _ = std::fs::remove_file(&path);
+        let replay = &mut replay.unwrap();
+
+        assert_eq!(replay.get_active_window_data().unwrap().pid, Some(42));
+        assert_eq!(next(replay), None);
+        assert_eq!(next(replay), entry("bash in hello", "org.kde.konsole"));
+        // Starts over after the last entry.
+        assert_eq!(next(replay), entry("Document 1", "org.kde.kate"));
+        assert!(!replay.is_idle().unwrap());
+    }
+
+    #[test]
+    fn null_entries_are_no_active_window() {
+        let path = recording_path("replay-null");
+        std::fs::write(&path, "[null]").unwrap();
+        let replay = ReplayWindowManager::from_file(&path);
+        let _ = std::fs::remove_file(&path);
+        let mut replay = replay.unwrap();
+        assert_eq!(next(&mut replay), None);
+        assert_eq!(next(&mut replay), None);
+    }
+
+    #[test]
+    fn empty_or_malformed_recordings_are_rejected() {
+        assert!(ReplayWindowManager::new(Vec::new()).is_err());
+        for (name, content) in [("replay-empty", "[]"), ("replay-object", "{}")] {
+            let path = recording_path(name);
+            std::fs::write(&path, content).unwrap();
+            let replay = ReplayWindowManager::from_file(&path);
+            let _ = std::fs::remove_file(&path);
+            assert!(replay.is_err(), "{content} was accepted");
+        }
+    }
+}
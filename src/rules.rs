@@ -0,0 +1,398 @@
+//! Matching of windows against user defined rules, used by the allow and deny lists of
+//! `WatcherConfig`.
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    ActiveWindowData,
+    config::{ExcludedAppMode, TitleMode, WatcherConfig},
+};
+
+/// Describes a set of windows. Every pattern that is set has to match, so a rule without any
+/// patterns matches every window. A pattern never matches a field the backend didn't report.
+#[cfg_attr(
+    feature = "config-file",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WindowRule {
+    /// Pattern for the window title, where `*` matches any sequence and `?` any single character.
+    pub title_glob: Option<String>,
+    /// Pattern for the process path, with the same wildcards as `title_glob`.
+    pub process_path_glob: Option<String>,
+    pub app_id_prefix: Option<String>,
+    pub app_name_contains: Option<String>,
+}
+
+impl WindowRule {
+    pub fn matches(&self, data: &ActiveWindowData) -> bool {
+        fn check(pattern: &Option<String>, value: Option<&str>, f: fn(&str, &str) -> bool) -> bool {
+            match (pattern, value) {
+                (None, _) => true,
+                (Some(pattern), Some(value)) => f(pattern, value),
+                (Some(_), None) => false,
+            }
+        }
+
+        check(&self.title_glob, Some(&data.window_title), glob_matches)
+            && check(
+                &self.process_path_glob,
+                data.process_path.as_deref(),
+                glob_matches,
+            )
+            && check(
+                &self.app_id_prefix,
+                data.app_identifier.as_deref(),
+                |prefix, value| value.starts_with(prefix),
+            )
+            && check(
+                &self.app_name_contains,
+                data.app_name.as_deref(),
+                |part, value| value.contains(part),
+            )
+    }
+}
+
+/// A window is allowed if it matches none of the deny rules and, when there are allow rules, at
+/// least one of them.
+pub fn is_allowed(
+    data: &ActiveWindowData,
+    allow_list: &[WindowRule],
+    deny_list: &[WindowRule],
+) -> bool {
+    (allow_list.is_empty() || allow_list.iter().any(|rule| rule.matches(data)))
+        && !deny_list.iter().any(|rule| rule.matches(data))
+}
+
+/// Whether `app` is the app identifier, the process path or the file name of the process path of
+/// the window.
+pub fn is_app(app: &str, data: &ActiveWindowData) -> bool {
+    data.app_identifier.as_deref() == Some(app)
+        || data
+            .process_path
+            .as_deref()
+            .is_some_and(|path| path == app || path.rsplit(['/', '\\']).next() == Some(app))
+}
+
+/// A window is excluded if it belongs to one of `excluded_apps` or, when `included_apps` is set,
+/// to none of them.
+pub fn is_excluded(
+    data: &ActiveWindowData,
+    excluded_apps: &[String],
+    included_apps: Option<&[String]>,
+) -> bool {
+    excluded_apps.iter().any(|app| is_app(app, data))
+        || included_apps.is_some_and(|apps| !apps.iter().any(|app| is_app(app, data)))
+}
+
+/// The window with the fields `mode` hides removed.
+pub fn redact(data: ActiveWindowData, mode: ExcludedAppMode) -> ActiveWindowData {
+    match mode {
+        ExcludedAppMode::RedactTitle => ActiveWindowData {
+            window_title: "".into(),
+            ..data
+        },
+        ExcludedAppMode::RedactAll => ActiveWindowData {
+            window_title: "".into(),
+            process_path: None,
+            app_identifier: None,
+            resource_class: None,
+            app_name: None,
+            workspace: None,
+            geometry: None,
+            monitor: None,
+            is_fullscreen: None,
+            pid: None,
+            window_type: None,
+            virtual_desktop: None,
+            icon_name: None,
+            publisher: None,
+            focused_at: None,
+        },
+    }
+}
+
+/// The window with the title transformed according to `mode`. Empty titles stay empty.
+pub fn apply_title_mode(data: ActiveWindowData, mode: TitleMode) -> ActiveWindowData {
+    let window_title = match mode {
+        TitleMode::Full => return data,
+        TitleMode::Hashed if !data.window_title.is_empty() => {
+            let hash = Sha256::digest(data.window_title.as_bytes());
+            hash.iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect::<String>()
+                .into()
+        }
+        TitleMode::Hashed | TitleMode::Empty => "".into(),
+    };
+    ActiveWindowData {
+        window_title,
+        ..data
+    }
+}
+
+/// The allow and deny lists, the excluded apps and the title mode of a `WatcherConfig`.
+#[derive(Clone, Debug)]
+pub(crate) struct WindowFilter {
+    allow_list: Vec<WindowRule>,
+    deny_list: Vec<WindowRule>,
+    excluded_apps: Vec<String>,
+    included_apps: Option<Vec<String>>,
+    excluded_app_mode: ExcludedAppMode,
+    title_mode: TitleMode,
+}
+
+impl WindowFilter {
+    pub(crate) fn from_config(config: &WatcherConfig) -> Self {
+        Self {
+            allow_list: config.allow_list.clone(),
+            deny_list: config.deny_list.clone(),
+            excluded_apps: config.excluded_apps.clone(),
+            included_apps: config.included_apps.clone(),
+            excluded_app_mode: config.excluded_app_mode,
+            title_mode: config.title_mode,
+        }
+    }
+
+    /// `None` if the allow and deny lists reject the window, otherwise the window with excluded
+    /// apps redacted and the title transformed according to `title_mode`.
+    pub(crate) fn apply(&self, data: ActiveWindowData) -> Option<ActiveWindowData> {
+        if !is_allowed(&data, &self.allow_list, &self.deny_list) {
+            return None;
+        }
+        let data = if is_excluded(&data, &self.excluded_apps, self.included_apps.as_deref()) {
+            redact(data, self.excluded_app_mode)
+        } else {
+            data
+        };
+        Some(apply_title_mode(data, self.title_mode))
+    }
+}
+
+/// fnmatch-style matching with `*` and `?`. There is no escaping, so both are always wildcards.
+fn glob_matches(pattern: &str, value: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let value: Vec<char> = value.chars().collect();
+    let (mut p, mut v) = (0, 0);
+    // Position of the last star and of the value character it's matched up to
+    let mut backtrack = None;
+    while v < value.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, v));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == value[v] => {
+                p += 1;
+                v += 1;
+            }
+            _ => match backtrack {
+                // Let the star take one more character
+                Some((star, star_v)) => {
+                    backtrack = Some((star, star_v + 1));
+                    p = star + 1;
+                    v = star_v + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(title: &str, app_id: Option<&str>, process_path: Option<&str>) -> ActiveWindowData {
+        ActiveWindowData {
+            window_title: title.into(),
+            app_identifier: app_id.map(Into::into),
+            process_path: process_path.map(Into::into),
+            app_name: None,
+            ..ActiveWindowData::locked()
+        }
+    }
+
+    fn title_rule(glob: &str) -> WindowRule {
+        WindowRule {
+            title_glob: Some(glob.into()),
+            ..WindowRule::default()
+        }
+    }
+
+    #[test]
+    fn glob_wildcards() {
+        let cases = [
+            ("*", "", true),
+            ("*", "anything", true),
+            ("", "", true),
+            ("", "a", false),
+            ("a", "", false),
+            ("?", "", false),
+            ("?", "a", true),
+            ("?", "ab", false),
+            ("a?c", "abc", true),
+            ("a?c", "ac", false),
+            ("*.rs", "lib.rs", true),
+            ("*.rs", "lib.rs.bak", false),
+            ("Mozilla*", "Mozilla Firefox", true),
+            ("*Firefox", "Mozilla Firefox", true),
+            ("*zil*Fire*", "Mozilla Firefox", true),
+            ("**", "x", true),
+            ("a*", "b", false),
+            // The first star match has to be abandoned for a longer one
+            ("*ab", "aab", true),
+            ("*abc*abd", "abcabcabd", true),
+            ("a*b*c", "abbbcbc", true),
+            ("a*b*c", "abbbcb", false),
+            ("*?", "", false),
+            ("*?", "x", true),
+            // Case matters
+            ("firefox", "Firefox", false),
+            // Non-ASCII characters are matched whole
+            ("caf?", "café", true),
+            ("?", "é", true),
+            ("*ü*", "Grüße", true),
+            ("??", "日本", true),
+            ("?", "日本", false),
+        ];
+        for (pattern, value, expected) in cases {
+            assert_eq!(
+                glob_matches(pattern, value),
+                expected,
+                "{pattern:?} on {value:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn rule_patterns_all_have_to_match() {
+        let data = ActiveWindowData {
+            app_name: Some("Firefox".into()),
+            ..window(
+                "Docs — Mozilla Firefox",
+                Some("org.mozilla.firefox"),
+                Some("/usr/lib/firefox/firefox"),
+            )
+        };
+        let rule = WindowRule {
+            title_glob: Some("*Firefox".into()),
+            process_path_glob: Some("/usr/lib/*".into()),
+            app_id_prefix: Some("org.mozilla.".into()),
+            app_name_contains: Some("fox".into()),
+        };
+        assert!(rule.matches(&data));
+        assert!(WindowRule::default().matches(&data));
+
+        let cases = [
+            WindowRule {
+                title_glob: Some("*Chromium".into()),
+                ..rule.clone()
+            },
+            WindowRule {
+                process_path_glob: Some("/opt/*".into()),
+                ..rule.clone()
+            },
+            WindowRule {
+                app_id_prefix: Some("org.gnome.".into()),
+                ..rule.clone()
+            },
+            WindowRule {
+                app_name_contains: Some("Chrom".into()),
+                ..rule.clone()
+            },
+        ];
+        for rule in cases {
+            assert!(!rule.matches(&data), "{rule:?}");
+        }
+    }
+
+    #[test]
+    fn patterns_never_match_absent_fields() {
+        let data = window("Terminal", None, None);
+        let cases = [
+            WindowRule {
+                process_path_glob: Some("*".into()),
+                ..WindowRule::default()
+            },
+            WindowRule {
+                app_id_prefix: Some("".into()),
+                ..WindowRule::default()
+            },
+            WindowRule {
+                app_name_contains: Some("".into()),
+                ..WindowRule::default()
+            },
+        ];
+        for rule in cases {
+            assert!(!rule.matches(&data), "{rule:?}");
+        }
+        // The title is always reported, an empty one matches an empty pattern
+        assert!(title_rule("").matches(&window("", None, None)));
+        assert!(title_rule("*").matches(&data));
+    }
+
+    #[test]
+    fn deny_rules_win_over_allow_rules() {
+        let editor = window("notes.txt - Editor", Some("org.gnome.TextEditor"), None);
+        let browser = window("Docs - Browser", Some("org.mozilla.firefox"), None);
+        let allow_list = [title_rule("*Editor"), title_rule("*Browser")];
+        let deny_list = [title_rule("notes*")];
+
+        assert!(is_allowed(&browser, &allow_list, &deny_list));
+        assert!(!is_allowed(&editor, &allow_list, &deny_list));
+        // Without allow rules, everything that isn't denied is allowed
+        assert!(is_allowed(&browser, &[], &deny_list));
+        assert!(!is_allowed(&editor, &[], &deny_list));
+        assert!(!is_allowed(&browser, &[title_rule("*Editor")], &[]));
+        assert!(is_allowed(&editor, &[], &[]));
+    }
+
+    #[test]
+    fn filter_rejects_redacts_and_transforms_titles() {
+        let config = WatcherConfig {
+            allow_list: vec![title_rule("*")],
+            deny_list: vec![title_rule("*Bank*")],
+            excluded_apps: vec!["keepassxc".into()],
+            excluded_app_mode: ExcludedAppMode::RedactTitle,
+            title_mode: TitleMode::Empty,
+            ..WatcherConfig::default()
+        };
+        let filter = WindowFilter::from_config(&config);
+
+        assert!(
+            filter
+                .apply(window("My Bank - Browser", None, None))
+                .is_none()
+        );
+
+        let password_manager = window(
+            "Passwords.kdbx",
+            Some("org.keepassxc.KeePassXC"),
+            Some("/usr/bin/keepassxc"),
+        );
+        let filtered = filter.apply(password_manager).unwrap();
+        assert_eq!(&*filtered.window_title, "");
+        assert_eq!(filtered.process_path.as_deref(), Some("/usr/bin/keepassxc"));
+
+        let editor = filter
+            .apply(window("notes.txt", Some("org.gnome.TextEditor"), None))
+            .unwrap();
+        assert_eq!(&*editor.window_title, "");
+        assert_eq!(
+            editor.app_identifier.as_deref(),
+            Some("org.gnome.TextEditor")
+        );
+
+        let hashed = WindowFilter::from_config(&WatcherConfig {
+            title_mode: TitleMode::Hashed,
+            ..WatcherConfig::default()
+        })
+        .apply(window("notes.txt", None, None))
+        .unwrap();
+        assert_eq!(hashed.window_title.len(), 64);
+        assert_ne!(&*hashed.window_title, "notes.txt");
+    }
+}
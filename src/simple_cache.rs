@@ -1,25 +1,69 @@
 use std::{
     collections::HashMap,
     hash::Hash,
-    time::{Duration, SystemTime},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
 };
 
+use tracing::debug;
+
 #[derive(Debug)]
 pub struct SimpleCache<G, T> {
     cache: HashMap<G, CacheEntry<T>>,
     config: CacheConfig,
+    stats: CacheStats,
+    /// `get` and `set` calls since the stats were last logged.
+    operations: u64,
 }
 
 #[derive(Clone, Debug, Default)]
+#[cfg_attr(
+    feature = "config-file",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default = "crate::config::watcher_cache_config", deny_unknown_fields)
+)]
 pub struct CacheConfig {
+    #[cfg_attr(feature = "config-file", serde(with = "crate::config_file"))]
     pub ttl: Duration,
     pub max_size: usize,
+    /// How long misses stored with `set_negative` are kept. `None` keeps them for `ttl`.
+    #[cfg_attr(
+        feature = "config-file",
+        serde(
+            with = "crate::config_file::option",
+            skip_serializing_if = "Option::is_none"
+        )
+    )]
+    pub negative_ttl: Option<Duration>,
+    /// If set, the stats are logged at debug level every that many `get` and `set` calls.
+    pub stats_log_interval: Option<u64>,
+}
+
+/// Counters of a `SimpleCache` since it was created.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    /// Lookups of missing or expired keys.
+    pub misses: u64,
+    /// Entries removed because they expired.
+    pub evictions_ttl: u64,
+    /// Entries removed because the cache was full. The oldest ones go first.
+    pub evictions_capacity: u64,
+    pub current_size: usize,
 }
 
 #[derive(Clone, Debug)]
 struct CacheEntry<T> {
     data: T,
-    timestamp: SystemTime,
+    /// Monotonic, so stepping the wall clock back can't break expiry.
+    timestamp: Instant,
+    override_ttl: Option<Duration>,
+}
+
+impl<T> CacheEntry<T> {
+    fn is_valid(&self, default_ttl: Duration) -> bool {
+        self.timestamp.elapsed() < self.override_ttl.unwrap_or(default_ttl)
+    }
 }
 
 impl<G: Hash + Eq + Clone, T: Clone> SimpleCache<G, T> {
@@ -27,32 +71,556 @@ impl<G: Hash + Eq + Clone, T: Clone> SimpleCache<G, T> {
         Self {
             cache: HashMap::new(),
             config,
+            stats: CacheStats::default(),
+            operations: 0,
+        }
+    }
+
+    /// Creates a cache holding the entries as if they were stored at the given instants.
+    pub fn from_entries(
+        config: CacheConfig,
+        entries: impl IntoIterator<Item = (G, T, Instant)>,
+    ) -> Self {
+        let mut cache = Self::new(config);
+        for (key, data, timestamp) in entries {
+            cache.cache.insert(
+                key,
+                CacheEntry {
+                    data,
+                    timestamp,
+                    override_ttl: None,
+                },
+            );
         }
+        cache.cleanup_if_full();
+        cache
     }
 
     pub fn get(&mut self, key: &G) -> Option<T> {
-        let entry = (*self.cache.get(key)?).clone();
-        if entry.timestamp.elapsed().expect("Should be always ok") < self.config.ttl {
-            Some(entry.data)
+        self.count_operation();
+        let Some(entry) = self.cache.get(key) else {
+            self.stats.misses += 1;
+            return None;
+        };
+        if entry.is_valid(self.config.ttl) {
+            self.stats.hits += 1;
+            Some(entry.data.clone())
         } else {
             self.cache.remove(key);
+            self.stats.misses += 1;
+            self.stats.evictions_ttl += 1;
             None
         }
     }
 
     pub fn set(&mut self, key: G, data: T) {
+        self.insert(key, data, None);
+    }
+
+    /// Stores the entry with a TTL that overrides the configured one.
+    pub fn set_with_ttl(&mut self, key: G, data: T, ttl: Duration) {
+        self.insert(key, data, Some(ttl));
+    }
+
+    /// Removes the entry, returning its data if it was still valid.
+    pub fn remove(&mut self, key: &G) -> Option<T> {
+        self.cache
+            .remove(key)
+            .filter(|entry| entry.is_valid(self.config.ttl))
+            .map(|entry| entry.data)
+    }
+
+    /// Removes all entries. The stats are kept.
+    pub fn clear(&mut self) {
+        self.cache.clear();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            current_size: self.cache.len(),
+            ..self.stats
+        }
+    }
+
+    fn insert(&mut self, key: G, data: T, override_ttl: Option<Duration>) {
+        self.count_operation();
         let entry = CacheEntry {
             data,
-            timestamp: SystemTime::now(),
+            timestamp: Instant::now(),
+            override_ttl,
         };
         self.cache.insert(key, entry);
-        if self.cache.len() > self.config.max_size {
-            self.cleanup();
+        self.cleanup_if_full();
+    }
+
+    fn count_operation(&mut self) {
+        let Some(interval) = self.config.stats_log_interval else {
+            return;
+        };
+        self.operations += 1;
+        if self.operations >= interval {
+            self.operations = 0;
+            debug!("Cache stats: {:?}", self.stats());
+        }
+    }
+
+    /// Removes the expired entries, then the oldest ones until the size limit is met.
+    fn cleanup_if_full(&mut self) {
+        if self.cache.len() <= self.config.max_size {
+            return;
+        }
+        self.cleanup();
+        let excess = self.cache.len().saturating_sub(self.config.max_size);
+        if excess == 0 {
+            return;
+        }
+        let mut by_age: Vec<(G, Instant)> = self
+            .cache
+            .iter()
+            .map(|(key, entry)| (key.clone(), entry.timestamp))
+            .collect();
+        by_age.sort_unstable_by_key(|(_, timestamp)| *timestamp);
+        for (key, _) in by_age.into_iter().take(excess) {
+            self.cache.remove(&key);
         }
+        self.stats.evictions_capacity += excess as u64;
     }
+
     pub fn cleanup(&mut self) {
-        self.cache.retain(|_, entry| {
-            entry.timestamp.elapsed().expect("Should be always ok") < self.config.ttl
+        let size = self.cache.len();
+        self.cache
+            .retain(|_, entry| entry.is_valid(self.config.ttl));
+        self.stats.evictions_ttl += (size - self.cache.len()) as u64;
+    }
+}
+
+/// Stores every entry like `set`, but checks the size limit only once at the end.
+impl<G: Hash + Eq + Clone, T: Clone> Extend<(G, T)> for SimpleCache<G, T> {
+    fn extend<I: IntoIterator<Item = (G, T)>>(&mut self, entries: I) {
+        let timestamp = Instant::now();
+        for (key, data) in entries {
+            self.cache.insert(
+                key,
+                CacheEntry {
+                    data,
+                    timestamp,
+                    override_ttl: None,
+                },
+            );
+        }
+        self.cleanup_if_full();
+    }
+}
+
+impl<G: Hash + Eq + Clone, T: Clone> SimpleCache<G, Option<T>> {
+    /// Remembers that the lookup of `key` found nothing, for `negative_ttl`.
+    pub fn set_negative(&mut self, key: G) {
+        let ttl = self.config.negative_ttl.unwrap_or(self.config.ttl);
+        self.insert(key, None, Some(ttl));
+    }
+}
+
+/// A `SimpleCache` behind a mutex, usable from several threads through `&self`. Clones share the
+/// entries.
+#[derive(Debug)]
+pub struct SharedCache<G, T> {
+    inner: Arc<Mutex<SimpleCache<G, T>>>,
+}
+
+impl<G, T> Clone for SharedCache<G, T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<G: Hash + Eq + Clone, T: Clone> SharedCache<G, T> {
+    pub fn new(config: CacheConfig) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(SimpleCache::new(config))),
+        }
+    }
+
+    /// The cache stays usable if a thread panicked while holding the lock.
+    fn lock(&self) -> MutexGuard<'_, SimpleCache<G, T>> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn get(&self, key: &G) -> Option<T> {
+        self.lock().get(key)
+    }
+
+    pub fn set(&self, key: G, data: T) {
+        self.lock().set(key, data);
+    }
+
+    pub fn set_with_ttl(&self, key: G, data: T, ttl: Duration) {
+        self.lock().set_with_ttl(key, data, ttl);
+    }
+
+    pub fn remove(&self, key: &G) -> Option<T> {
+        self.lock().remove(key)
+    }
+
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    pub fn cleanup(&self) {
+        self.lock().cleanup();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.lock().stats()
+    }
+}
+
+impl<G: Hash + Eq + Clone, T: Clone> SharedCache<G, Option<T>> {
+    pub fn set_negative(&self, key: G) {
+        self.lock().set_negative(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(ttl: Duration, max_size: usize) -> CacheConfig {
+        CacheConfig {
+            ttl,
+            max_size,
+            negative_ttl: None,
+            stats_log_interval: None,
+        }
+    }
+
+    /// An instant `age` in the past, for entries stored before the test started.
+    fn ago(age: Duration) -> Instant {
+        Instant::now()
+            .checked_sub(age)
+            .expect("The monotonic clock started long enough ago")
+    }
+
+    #[test]
+    fn entries_older_than_ttl_expire() {
+        let mut cache = SimpleCache::from_entries(
+            config(Duration::from_secs(60), 10),
+            [
+                ("fresh", 1, ago(Duration::from_secs(10))),
+                ("stale", 2, ago(Duration::from_secs(61))),
+            ],
+        );
+        assert_eq!(cache.get(&"fresh"), Some(1));
+        assert_eq!(cache.get(&"stale"), None);
+        assert_eq!(cache.stats().current_size, 1);
+    }
+
+    #[test]
+    fn cleanup_removes_only_expired_entries() {
+        let mut cache = SimpleCache::from_entries(
+            config(Duration::from_secs(60), 10),
+            [
+                ("fresh", 1, ago(Duration::from_secs(10))),
+                ("stale", 2, ago(Duration::from_secs(61))),
+                ("older", 3, ago(Duration::from_secs(3600))),
+            ],
+        );
+        cache.cleanup();
+        assert_eq!(cache.stats().current_size, 1);
+        assert_eq!(cache.stats().evictions_ttl, 2);
+        assert_eq!(cache.get(&"fresh"), Some(1));
+    }
+
+    #[test]
+    fn removing_an_expired_entry_returns_nothing() {
+        let mut cache = SimpleCache::from_entries(
+            config(Duration::from_secs(60), 10),
+            [("stale", 2, ago(Duration::from_secs(61)))],
+        );
+        assert_eq!(cache.remove(&"stale"), None);
+        assert_eq!(cache.stats().current_size, 0);
+    }
+
+    #[test]
+    fn from_entries_keeps_the_newest_entries_up_to_max_size() {
+        let mut cache = SimpleCache::from_entries(
+            config(Duration::from_secs(60), 2),
+            [
+                ("oldest", 1, ago(Duration::from_secs(30))),
+                ("newest", 2, ago(Duration::from_secs(10))),
+                ("middle", 3, ago(Duration::from_secs(20))),
+            ],
+        );
+        assert_eq!(cache.stats().current_size, 2);
+        assert_eq!(cache.stats().evictions_capacity, 1);
+        assert_eq!(cache.get(&"oldest"), None);
+        assert_eq!(cache.get(&"middle"), Some(3));
+        assert_eq!(cache.get(&"newest"), Some(2));
+    }
+
+    #[test]
+    fn from_entries_drops_expired_entries_before_the_oldest_valid_ones() {
+        let cache = SimpleCache::from_entries(
+            config(Duration::from_secs(60), 2),
+            [
+                ("stale", 1, ago(Duration::from_secs(61))),
+                ("fresh", 2, ago(Duration::from_secs(30))),
+                ("newest", 3, ago(Duration::from_secs(10))),
+            ],
+        );
+        let stats = cache.stats();
+        assert_eq!(stats.current_size, 2);
+        assert_eq!(stats.evictions_ttl, 1);
+        assert_eq!(stats.evictions_capacity, 0);
+    }
+
+    #[test]
+    fn extend_enforces_max_size_once() {
+        let mut cache = SimpleCache::from_entries(
+            config(Duration::from_secs(60), 3),
+            [
+                ("old", 0, ago(Duration::from_secs(30))),
+                ("stale", 0, ago(Duration::from_secs(61))),
+            ],
+        );
+        cache.extend([("a", 1), ("b", 2), ("c", 3)]);
+        let stats = cache.stats();
+        assert_eq!(stats.current_size, 3);
+        assert_eq!(stats.evictions_ttl, 1);
+        assert_eq!(stats.evictions_capacity, 1);
+        assert_eq!(cache.get(&"old"), None);
+        assert_eq!(
+            [cache.get(&"a"), cache.get(&"b"), cache.get(&"c")],
+            [Some(1), Some(2), Some(3)]
+        );
+    }
+
+    #[test]
+    fn extended_entries_use_the_configured_ttl() {
+        let mut cache = SimpleCache::new(config(Duration::ZERO, 10));
+        cache.extend([("a", 1)]);
+        assert_eq!(cache.get(&"a"), None);
+
+        let mut cache = SimpleCache::new(config(Duration::from_secs(60), 10));
+        cache.extend([("a", 1)]);
+        assert_eq!(cache.get(&"a"), Some(1));
+    }
+
+    /// Looks `key` up through `cache` like the backends do, counting the lookups that miss it.
+    fn lookup(
+        cache: &mut SimpleCache<&'static str, Option<u32>>,
+        lookups: &mut u32,
+        key: &'static str,
+    ) -> Option<u32> {
+        if let Some(value) = cache.get(&key) {
+            return value;
+        }
+        *lookups += 1;
+        cache.set_negative(key);
+        None
+    }
+
+    #[test]
+    fn missing_key_is_looked_up_once_within_negative_ttl() {
+        let mut cache = SimpleCache::new(CacheConfig {
+            negative_ttl: Some(Duration::from_secs(60)),
+            ..config(Duration::from_secs(60), 10)
         });
+        let mut lookups = 0;
+        for _ in 0..5 {
+            assert_eq!(lookup(&mut cache, &mut lookups, "missing"), None);
+        }
+        assert_eq!(lookups, 1);
+        assert_eq!(cache.stats().hits, 4);
+    }
+
+    #[test]
+    fn missing_key_is_looked_up_again_after_negative_ttl() {
+        let mut cache = SimpleCache::new(CacheConfig {
+            negative_ttl: Some(Duration::ZERO),
+            ..config(Duration::from_secs(60), 10)
+        });
+        let mut lookups = 0;
+        for _ in 0..3 {
+            lookup(&mut cache, &mut lookups, "missing");
+        }
+        assert_eq!(lookups, 3);
+
+        // Found values still use the regular TTL
+        cache.set("found", Some(1));
+        assert_eq!(cache.get(&"found"), Some(Some(1)));
+    }
+
+    #[test]
+    fn negative_entries_use_ttl_without_negative_ttl() {
+        let mut cache = SimpleCache::new(config(Duration::from_secs(60), 10));
+        let mut lookups = 0;
+        for _ in 0..3 {
+            lookup(&mut cache, &mut lookups, "missing");
+        }
+        assert_eq!(lookups, 1);
+
+        let mut cache = SimpleCache::new(config(Duration::ZERO, 10));
+        let mut lookups = 0;
+        for _ in 0..3 {
+            lookup(&mut cache, &mut lookups, "missing");
+        }
+        assert_eq!(lookups, 3);
+    }
+
+    #[test]
+    fn stats_count_hits_and_misses() {
+        let mut cache = SimpleCache::new(config(Duration::from_secs(60), 10));
+        assert_eq!(cache.get(&"a"), None);
+        cache.set("a", 1);
+        assert_eq!(cache.get(&"a"), Some(1));
+        assert_eq!(cache.get(&"a"), Some(1));
+        assert_eq!(cache.get(&"b"), None);
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 2,
+                misses: 2,
+                evictions_ttl: 0,
+                evictions_capacity: 0,
+                current_size: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn stats_count_an_expired_lookup_as_miss_and_eviction() {
+        let mut cache = SimpleCache::from_entries(
+            config(Duration::from_secs(60), 10),
+            [("stale", 1, ago(Duration::from_secs(61)))],
+        );
+        assert_eq!(cache.get(&"stale"), None);
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 0,
+                misses: 1,
+                evictions_ttl: 1,
+                evictions_capacity: 0,
+                current_size: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn stats_count_capacity_evictions() {
+        let mut cache = SimpleCache::new(config(Duration::from_secs(60), 2));
+        for (key, value) in [("a", 1), ("b", 2), ("c", 3), ("d", 4)] {
+            cache.set(key, value);
+        }
+        let stats = cache.stats();
+        assert_eq!(stats.evictions_capacity, 2);
+        assert_eq!(stats.evictions_ttl, 0);
+        assert_eq!(stats.current_size, 2);
+    }
+
+    #[test]
+    fn clear_keeps_the_stats() {
+        let mut cache = SimpleCache::new(config(Duration::from_secs(60), 10));
+        cache.set("a", 1);
+        cache.get(&"a");
+        cache.clear();
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.current_size, 0);
+        assert_eq!(cache.get(&"a"), None);
+    }
+
+    #[test]
+    fn shared_cache_is_consistent_across_threads() {
+        const THREADS: u32 = 8;
+        const KEYS: u32 = 50;
+        let cache = SharedCache::new(config(Duration::from_secs(60), 1000));
+        let handles: Vec<_> = (0..THREADS)
+            .map(|thread| {
+                let cache = cache.clone();
+                std::thread::spawn(move || {
+                    for key in 0..KEYS {
+                        cache.set((thread, key), thread * KEYS + key);
+                    }
+                    for key in 0..KEYS {
+                        assert_eq!(cache.get(&(thread, key)), Some(thread * KEYS + key));
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let stats = cache.stats();
+        assert_eq!(stats.current_size, (THREADS * KEYS) as usize);
+        assert_eq!(stats.hits, u64::from(THREADS * KEYS));
+        assert_eq!(stats.misses, 0);
+    }
+
+    #[test]
+    fn shared_cache_stays_usable_after_a_panic() {
+        let cache = SharedCache::new(config(Duration::from_secs(60), 10));
+        cache.set("a", 1);
+        let poisoned = cache.clone();
+        let _ = std::thread::spawn(move || {
+            let _guard = poisoned.lock();
+            panic!("Poisoning the cache");
+        })
+        .join();
+        assert_eq!(cache.get(&"a"), Some(1));
+    }
+
+    #[test]
+    fn set_with_ttl_can_outlive_the_configured_ttl() {
+        let mut cache = SimpleCache::new(config(Duration::ZERO, 10));
+        cache.set_with_ttl("long", 1, Duration::from_secs(60));
+        cache.set("default", 2);
+        cache.cleanup();
+        assert_eq!(cache.get(&"long"), Some(1));
+        assert_eq!(cache.get(&"default"), None);
+        assert_eq!(cache.remove(&"long"), Some(1));
+    }
+
+    #[test]
+    fn set_with_ttl_can_expire_before_the_configured_ttl() {
+        let mut cache = SimpleCache::new(config(Duration::from_secs(60), 10));
+        cache.set_with_ttl("short", 1, Duration::ZERO);
+        cache.set("default", 2);
+        assert_eq!(cache.get(&"short"), None);
+        assert_eq!(cache.get(&"default"), Some(2));
+        assert_eq!(cache.stats().evictions_ttl, 1);
+
+        cache.set_with_ttl("short", 1, Duration::ZERO);
+        assert_eq!(cache.remove(&"short"), None);
+    }
+
+    #[test]
+    fn set_with_ttl_entries_count_towards_max_size() {
+        let mut cache = SimpleCache::new(config(Duration::from_secs(60), 2));
+        cache.set_with_ttl("long", 1, Duration::from_secs(3600));
+        std::thread::sleep(Duration::from_millis(1));
+        cache.set("a", 2);
+        std::thread::sleep(Duration::from_millis(1));
+        cache.set("b", 3);
+        let stats = cache.stats();
+        assert_eq!(stats.current_size, 2);
+        assert_eq!(stats.evictions_capacity, 1);
+        // The oldest entry goes first, even if its TTL is the longest.
+        assert_eq!(cache.get(&"long"), None);
+        assert_eq!([cache.get(&"a"), cache.get(&"b")], [Some(2), Some(3)]);
+    }
+
+    #[test]
+    fn expired_set_with_ttl_entries_are_dropped_before_capacity_evictions() {
+        let mut cache = SimpleCache::new(config(Duration::from_secs(60), 2));
+        cache.set("old", 1);
+        cache.set_with_ttl("short", 2, Duration::ZERO);
+        cache.set("new", 3);
+        let stats = cache.stats();
+        assert_eq!(stats.evictions_ttl, 1);
+        assert_eq!(stats.evictions_capacity, 0);
+        assert_eq!([cache.get(&"old"), cache.get(&"new")], [Some(1), Some(3)]);
     }
 }
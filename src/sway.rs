@@ -0,0 +1,259 @@
+//! Active window tracking through the i3 IPC protocol, spoken by both Sway and i3.
+//! The layout tree carries more than the generic backends get (pid, workspace), and the same
+//! code serves i3 on X11.
+//! See https://i3wm.org/docs/ipc.html
+
+use std::{
+    env,
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{Context, Result, anyhow};
+use chrono::Utc;
+use serde_json::Value;
+use tracing::{debug, trace};
+
+use crate::{
+    ActiveWindowData, IconRef, NoActiveWindow, Sample, WindowManager,
+    config::WatcherConfig,
+    linux_desktop::{
+        DesktopInfo, DesktopInfoResolver, SharedDesktopInfo, get_process_path, lock_desktop_info,
+    },
+    simple_cache::SharedCache,
+    utils::is_wayland,
+    wayland_idle::IdleWatcherRunner,
+};
+
+const IPC_MAGIC: &[u8] = b"i3-ipc";
+const IPC_HEADER_LEN: usize = IPC_MAGIC.len() + 8;
+const GET_TREE: u32 = 4;
+/// A compositor that stops answering would otherwise block the caller forever.
+const IPC_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn socket_path() -> Result<String> {
+    env::var("SWAYSOCK")
+        .or_else(|_| env::var("I3SOCK"))
+        .map_err(|_| anyhow!("Neither SWAYSOCK nor I3SOCK is set"))
+}
+
+struct IpcConnection {
+    stream: UnixStream,
+}
+
+impl IpcConnection {
+    fn connect() -> Result<Self> {
+        let path = socket_path()?;
+        let stream = UnixStream::connect(&path)
+            .with_context(|| format!("Failed to connect to the IPC socket {path}"))?;
+        stream.set_read_timeout(Some(IPC_TIMEOUT))?;
+        stream.set_write_timeout(Some(IPC_TIMEOUT))?;
+        Ok(Self { stream })
+    }
+
+    fn request(&mut self, message_type: u32, payload: &[u8]) -> Result<Value> {
+        let mut message = Vec::with_capacity(IPC_HEADER_LEN + payload.len());
+        message.extend_from_slice(IPC_MAGIC);
+        message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+        message.extend_from_slice(&message_type.to_ne_bytes());
+        message.extend_from_slice(payload);
+        self.stream.write_all(&message)?;
+
+        let mut header = [0; IPC_HEADER_LEN];
+        self.stream.read_exact(&mut header)?;
+        if &header[..IPC_MAGIC.len()] != IPC_MAGIC {
+            return Err(anyhow!("IPC reply has an invalid magic string"));
+        }
+        let length = u32::from_ne_bytes(header[6..10].try_into()?) as usize;
+        let reply_type = u32::from_ne_bytes(header[10..14].try_into()?);
+        if reply_type != message_type {
+            return Err(anyhow!(
+                "Expected IPC reply of type {message_type}, got {reply_type}"
+            ));
+        }
+
+        let mut body = vec![0; length];
+        self.stream.read_exact(&mut body)?;
+        serde_json::from_slice(&body).with_context(|| "IPC reply is not valid JSON")
+    }
+}
+
+struct FocusedNode<'a> {
+    node: &'a Value,
+    workspace: Option<&'a str>,
+}
+
+/// Walks the layout tree looking for the focused node, keeping track of the workspace on the way.
+fn find_focused<'a>(node: &'a Value, workspace: Option<&'a str>) -> Option<FocusedNode<'a>> {
+    let workspace = if node["type"] == "workspace" {
+        node["name"].as_str()
+    } else {
+        workspace
+    };
+    if node["focused"].as_bool() == Some(true) {
+        return Some(FocusedNode { node, workspace });
+    }
+    ["nodes", "floating_nodes"]
+        .iter()
+        .filter_map(|key| node[key].as_array())
+        .flatten()
+        .find_map(|child| find_focused(child, workspace))
+}
+
+pub struct SwayWindowManager {
+    connection: IpcConnection,
+    desktop_info: SharedDesktopInfo,
+    process_path_cache: SharedCache<u32, Option<Arc<str>>>,
+    /// Missing on i3, where idle has to be read through X11.
+    pub idle_watcher: Option<IdleWatcherRunner>,
+}
+
+impl SwayWindowManager {
+    pub fn from_config(config: WatcherConfig) -> Result<Self> {
+        let desktop_info = DesktopInfoResolver::shared(&config);
+        Self::with_desktop_info(config, desktop_info)
+    }
+
+    #[deprecated(note = "use `from_config`, which every backend has")]
+    pub fn new(config: WatcherConfig) -> Result<Self> {
+        Self::from_config(config)
+    }
+
+    /// Like `from_config`, but looks the apps up in `desktop_info`, which other watchers may share.
+    pub fn with_desktop_info(
+        config: WatcherConfig,
+        desktop_info: SharedDesktopInfo,
+    ) -> Result<Self> {
+        let mut connection = IpcConnection::connect()?;
+        connection.request(GET_TREE, &[])?;
+        debug!("Connected to the i3 IPC socket");
+
+        let idle_watcher = if is_wayland() {
+            Some(IdleWatcherRunner::new(&config)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            connection,
+            desktop_info,
+            process_path_cache: SharedCache::new(config.cache_config.clone()),
+            idle_watcher,
+        })
+    }
+
+    fn get_tree(&mut self) -> Result<Value> {
+        match self.connection.request(GET_TREE, &[]) {
+            Ok(tree) => Ok(tree),
+            Err(e) => {
+                debug!("IPC request failed, reconnecting: {e}");
+                self.connection = IpcConnection::connect()?;
+                self.connection.request(GET_TREE, &[])
+            }
+        }
+    }
+
+    fn idle_state(&self) -> Result<(bool, Duration)> {
+        let Some(idle_watcher) = &self.idle_watcher else {
+            return Err(anyhow!(
+                "Idle time is not available through i3 IPC, use the X11 backend instead"
+            ));
+        };
+        Ok(idle_watcher.idle_state())
+    }
+
+    fn get_desktop_info(&mut self, app_id: &str) -> Option<DesktopInfo> {
+        lock_desktop_info(&self.desktop_info).get(app_id)
+    }
+}
+
+impl WindowManager for SwayWindowManager {
+    #[tracing::instrument(name = "sway::get_active", skip(self))]
+    fn get_active_window_data(&mut self) -> Result<ActiveWindowData> {
+        let tree = self.get_tree()?;
+        let Some(focused) = find_focused(&tree, None) else {
+            trace!("No window is active");
+            return Err(NoActiveWindow.into());
+        };
+        // An empty workspace or an output gets the focus when there are no windows on it
+        if !matches!(focused.node["type"].as_str(), Some("con" | "floating_con")) {
+            trace!("No window is active");
+            return Err(NoActiveWindow.into());
+        }
+
+        let node = focused.node;
+        // Native Wayland windows have an app_id, XWayland and i3 windows only have a class
+        let app_id = node["app_id"]
+            .as_str()
+            .or_else(|| node["window_properties"]["class"].as_str());
+        let window_title = node["name"].as_str().unwrap_or_default();
+        trace!(
+            r#"Focused node id={}, app_id="{}", title="{}""#,
+            node["id"],
+            app_id.unwrap_or_default(),
+            window_title
+        );
+
+        let pid = node["pid"].as_u64().and_then(|pid| u32::try_from(pid).ok());
+        let desktop_info = app_id.and_then(|app_id| self.get_desktop_info(app_id));
+        let process_path = match &desktop_info {
+            Some(info) => Some(info.process_path.clone()),
+            None => pid.and_then(|pid| get_process_path(&self.process_path_cache, pid)),
+        };
+
+        let data = ActiveWindowData {
+            window_title: window_title.into(),
+            process_path,
+            app_identifier: app_id.map(Into::into),
+            resource_class: None,
+            icon_name: desktop_info.as_ref().and_then(DesktopInfo::icon_name),
+            publisher: None,
+            focused_at: None,
+            app_name: desktop_info.map(|info| info.app_name),
+            workspace: focused.workspace.map(Into::into),
+            geometry: None,
+            monitor: None,
+            is_fullscreen: None,
+            window_type: None,
+            virtual_desktop: None,
+            pid,
+        };
+        Ok(data)
+    }
+
+    #[tracing::instrument(name = "sway::is_idle", skip(self))]
+    fn is_idle(&mut self) -> Result<bool> {
+        Ok(self.idle_state()?.0)
+    }
+
+    fn idle_duration(&mut self) -> Result<Duration> {
+        Ok(self.idle_state()?.1)
+    }
+
+    fn set_idle_timeout(&mut self, timeout: Duration) -> Result<()> {
+        let Some(idle_watcher) = &mut self.idle_watcher else {
+            return Err(anyhow!(
+                "Idle time is not available through i3 IPC, use the X11 backend instead"
+            ));
+        };
+        idle_watcher.set_timeout(timeout)
+    }
+
+    fn get_app_icon(&mut self, app_id: &str) -> Result<Option<IconRef>> {
+        Ok(lock_desktop_info(&self.desktop_info).app_icon(app_id))
+    }
+
+    fn sample(&mut self) -> Result<Sample> {
+        let timestamp = Utc::now();
+        let window = self.get_active_window_data()?;
+        let (idle, idle_duration) = self.idle_state()?;
+        Ok(Sample {
+            window,
+            idle,
+            idle_duration,
+            timestamp,
+        })
+    }
+}
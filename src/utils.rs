@@ -23,9 +23,148 @@ pub fn is_x11() -> bool {
         && env::var_os("XDG_SESSION_TYPE").unwrap_or("".into()) == "x11"
 }
 
+/// Desktop environment or compositor the process is running under.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Compositor {
+    Gnome,
+    Kde,
+    Sway,
+    /// i3 on X11, which speaks the same IPC protocol as Sway.
+    I3,
+    Hyprland,
+    Cosmic,
+    Wayfire,
+    /// A desktop that isn't recognized. Holds the value of `XDG_CURRENT_DESKTOP`.
+    Other(String),
+    Unknown,
+}
+
+impl Compositor {
+    /// Whether the GNOME extension based watcher may work.
+    pub fn may_be_gnome(&self) -> bool {
+        matches!(self, Self::Gnome | Self::Other(_) | Self::Unknown)
+    }
+
+    /// Whether the KWin script based watcher may work.
+    pub fn may_be_kde(&self) -> bool {
+        matches!(self, Self::Kde | Self::Other(_) | Self::Unknown)
+    }
+
+    /// Whether the compositor may implement wlr-foreign-toplevel-management.
+    pub fn may_support_wlr(&self) -> bool {
+        !matches!(self, Self::Gnome | Self::Kde | Self::I3)
+    }
+
+    /// Whether the i3 IPC based watcher works.
+    pub fn speaks_i3_ipc(&self) -> bool {
+        matches!(self, Self::Sway | Self::I3)
+    }
+}
+
+fn parse_desktop(value: &str) -> Option<Compositor> {
+    // XDG_CURRENT_DESKTOP is a colon separated list, like "ubuntu:GNOME"
+    value
+        .split(':')
+        .find_map(|desktop| match desktop.to_lowercase().as_str() {
+            d if d.starts_with("gnome") => Some(Compositor::Gnome),
+            "kde" => Some(Compositor::Kde),
+            "sway" => Some(Compositor::Sway),
+            "i3" => Some(Compositor::I3),
+            "hyprland" => Some(Compositor::Hyprland),
+            "cosmic" => Some(Compositor::Cosmic),
+            "wayfire" => Some(Compositor::Wayfire),
+            _ => None,
+        })
+}
+
+pub fn detect_compositor() -> Compositor {
+    for var in ["XDG_CURRENT_DESKTOP", "ORIGINAL_XDG_CURRENT_DESKTOP"] {
+        if let Ok(value) = env::var(var)
+            && let Some(compositor) = parse_desktop(&value)
+        {
+            return compositor;
+        }
+    }
+
+    if env::var_os("SWAYSOCK").is_some() {
+        return Compositor::Sway;
+    }
+    if env::var_os("I3SOCK").is_some() {
+        return Compositor::I3;
+    }
+    if env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some() {
+        return Compositor::Hyprland;
+    }
+
+    match env::var("XDG_CURRENT_DESKTOP") {
+        Ok(value) if !value.is_empty() => Compositor::Other(value),
+        _ => Compositor::Unknown,
+    }
+}
+
 pub fn default_cache_config() -> CacheConfig {
     CacheConfig {
         ttl: Duration::from_secs(60),
         max_size: 1000,
+        negative_ttl: None,
+        stats_log_interval: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_desktops_are_parsed() {
+        for (value, compositor) in [
+            ("GNOME", Compositor::Gnome),
+            ("KDE", Compositor::Kde),
+            ("sway", Compositor::Sway),
+            ("i3", Compositor::I3),
+            ("Hyprland", Compositor::Hyprland),
+            ("COSMIC", Compositor::Cosmic),
+            ("Wayfire", Compositor::Wayfire),
+        ] {
+            assert_eq!(parse_desktop(value), Some(compositor), "{value}");
+        }
+    }
+
+    #[test]
+    fn case_is_ignored() {
+        for value in ["gnome", "Gnome", "GNOME"] {
+            assert_eq!(parse_desktop(value), Some(Compositor::Gnome), "{value}");
+        }
+        assert_eq!(parse_desktop("kde"), Some(Compositor::Kde));
+        assert_eq!(parse_desktop("SWAY"), Some(Compositor::Sway));
+    }
+
+    #[test]
+    fn gnome_variants_are_gnome() {
+        for value in ["GNOME-Classic", "GNOME-Flashback", "GNOME-Classic:GNOME"] {
+            assert_eq!(parse_desktop(value), Some(Compositor::Gnome), "{value}");
+        }
+    }
+
+    #[test]
+    fn first_known_entry_of_the_list_is_used() {
+        assert_eq!(parse_desktop("ubuntu:GNOME"), Some(Compositor::Gnome));
+        assert_eq!(parse_desktop("pop:COSMIC"), Some(Compositor::Cosmic));
+        assert_eq!(parse_desktop("KDE:GNOME"), Some(Compositor::Kde));
+    }
+
+    #[test]
+    fn unknown_desktops_are_not_parsed() {
+        for value in [
+            "",
+            "X-Cinnamon",
+            "XFCE",
+            "ubuntu",
+            "unity:",
+            "kde-plasma",
+            "i3wm",
+        ] {
+            assert_eq!(parse_desktop(value), None, "{value}");
+        }
     }
 }
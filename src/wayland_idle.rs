@@ -1,156 +1,602 @@
-use crate::idle::{self, Status};
+use crate::{
+    ActiveWindowData, WatcherError,
+    config::WatcherConfig,
+    idle::{self, Status},
+    utils::{Compositor, detect_compositor},
+};
 
-use super::wl_connection::{WlEventConnection, subscribe_state};
-use anyhow::Context as _;
-use chrono::{TimeDelta, Utc};
+use super::wl_connection::{ReconnectBackoff, WlEventConnection, subscribe_state};
+use anyhow::{Context as _, anyhow};
+use chrono::{DateTime, TimeDelta, Utc};
 use std::{
-    sync::{Arc, Mutex, mpsc},
+    collections::{HashMap, HashSet},
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, RecvTimeoutError},
+    },
     thread::{self, JoinHandle},
     time::Duration,
 };
-use tracing::{error, info};
+use tracing::{debug, error, info, warn};
 use wayland_client::{
     Connection, Dispatch, Proxy, QueueHandle,
+    backend::ObjectId,
     globals::GlobalListContents,
-    protocol::{wl_registry, wl_seat::WlSeat},
+    protocol::{
+        wl_registry,
+        wl_seat::{Event as SeatEvent, WlSeat},
+    },
 };
 use wayland_protocols::ext::idle_notify::v1::client::ext_idle_notification_v1::Event as IdleNotificationV1Event;
 use wayland_protocols::ext::idle_notify::v1::client::ext_idle_notification_v1::ExtIdleNotificationV1;
 use wayland_protocols::ext::idle_notify::v1::client::ext_idle_notifier_v1::ExtIdleNotifierV1;
+use wayland_protocols_plasma::idle::client::{
+    org_kde_kwin_idle::OrgKdeKwinIdle,
+    org_kde_kwin_idle_timeout::{Event as KWinIdleTimeoutEvent, OrgKdeKwinIdleTimeout},
+};
+use zbus::zvariant::OwnedValue;
+
+/// The notification the compositor reports idle changes through.
+enum IdleNotification {
+    Ext(ExtIdleNotificationV1),
+    /// Legacy protocol of KWin before 5.27.
+    KWin(OrgKdeKwinIdleTimeout),
+}
 
 pub struct WatcherState {
-    idle_notification: ExtIdleNotificationV1,
+    /// One notification per watched seat.
+    idle_notifications: Vec<IdleNotification>,
+    /// Notifications whose seat is currently idle.
+    idle_seats: HashSet<ObjectId>,
+    seat_names: HashMap<ObjectId, String>,
     pub idle_state: idle::Tracker,
 }
 
 impl Drop for WatcherState {
     fn drop(&mut self) {
-        info!("Releasing idle notification");
-        self.idle_notification.destroy();
+        info!("Releasing idle notifications");
+        for notification in &self.idle_notifications {
+            match notification {
+                IdleNotification::Ext(notification) => notification.destroy(),
+                IdleNotification::KWin(timeout) => timeout.release(),
+            }
+        }
     }
 }
 
 impl WatcherState {
-    fn new(idle_notification: ExtIdleNotificationV1, idle_timeout: TimeDelta) -> Self {
+    fn new(idle_timeout: TimeDelta) -> Self {
         Self {
-            idle_notification,
+            idle_notifications: Vec::new(),
+            idle_seats: HashSet::new(),
+            seat_names: HashMap::new(),
             idle_state: idle::Tracker::new(Utc::now(), idle_timeout),
         }
     }
 
-    fn idle(&mut self) {
-        let time = Utc::now();
-        self.idle_state.mark_idle(time);
+    /// The user is idle only once every seat is.
+    fn idle(&mut self, notification: ObjectId) {
+        self.idle_seats.insert(notification);
+        if self.idle_seats.len() == self.idle_notifications.len() {
+            let time = Utc::now();
+            self.idle_state.mark_idle(time);
+        }
     }
 
-    fn resume(&mut self) {
-        let time = Utc::now();
-        self.idle_state.mark_not_idle(time);
+    fn resume(&mut self, notification: ObjectId) {
+        let was_idle = self.idle_seats.len() == self.idle_notifications.len();
+        self.idle_seats.remove(&notification);
+        if was_idle {
+            let time = Utc::now();
+            self.idle_state.mark_not_idle(time);
+        }
     }
 }
 
 subscribe_state!(wl_registry::WlRegistry, GlobalListContents, WatcherState);
 subscribe_state!(wl_registry::WlRegistry, (), WatcherState);
-subscribe_state!(WlSeat, (), WatcherState);
 subscribe_state!(ExtIdleNotifierV1, (), WatcherState);
+subscribe_state!(OrgKdeKwinIdle, (), WatcherState);
 
 impl Dispatch<ExtIdleNotificationV1, ()> for WatcherState {
     fn event(
         state: &mut Self,
-        _: &ExtIdleNotificationV1,
+        notification: &ExtIdleNotificationV1,
         event: <ExtIdleNotificationV1 as Proxy>::Event,
         _: &(),
         _: &Connection,
         _: &QueueHandle<Self>,
     ) {
         if let IdleNotificationV1Event::Idled = event {
-            state.idle();
+            state.idle(notification.id());
         } else if let IdleNotificationV1Event::Resumed = event {
-            state.resume();
+            state.resume(notification.id());
+        }
+    }
+}
+
+impl Dispatch<WlSeat, ()> for WatcherState {
+    fn event(
+        state: &mut Self,
+        seat: &WlSeat,
+        event: <WlSeat as Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let SeatEvent::Name { name } = event {
+            debug!("Seat is advertised: {name}");
+            state.seat_names.insert(seat.id(), name);
+        }
+    }
+}
+
+impl Dispatch<OrgKdeKwinIdleTimeout, ()> for WatcherState {
+    fn event(
+        state: &mut Self,
+        timeout: &OrgKdeKwinIdleTimeout,
+        event: <OrgKdeKwinIdleTimeout as Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let KWinIdleTimeoutEvent::Idle = event {
+            state.idle(timeout.id());
+        } else if let KWinIdleTimeoutEvent::Resumed = event {
+            state.resume(timeout.id());
+        }
+    }
+}
+
+/// Polls the idle hint of the logind session. Used when the compositor has no idle protocol.
+struct LogindIdle {
+    connection: zbus::blocking::Connection,
+    idle_state: idle::Tracker,
+    /// Last value of `IdleHint`.
+    is_idle: bool,
+}
+
+impl LogindIdle {
+    fn new(idle_timeout: TimeDelta) -> anyhow::Result<Self> {
+        let logind = Self {
+            connection: zbus::blocking::Connection::system()
+                .with_context(|| "Unable to connect to the system bus")?,
+            idle_state: idle::Tracker::new(Utc::now(), idle_timeout),
+            is_idle: false,
+        };
+        logind.get_property::<bool>("IdleHint")?;
+        Ok(logind)
+    }
+
+    fn get_property<T>(&self, name: &str) -> anyhow::Result<T>
+    where
+        T: TryFrom<OwnedValue, Error = zbus::zvariant::Error>,
+    {
+        let response = self
+            .connection
+            .call_method(
+                Some("org.freedesktop.login1"),
+                "/org/freedesktop/login1/session/auto",
+                Some("org.freedesktop.DBus.Properties"),
+                "Get",
+                &("org.freedesktop.login1.Session", name),
+            )
+            .with_context(|| format!("Failed to get {name} of the logind session"))?;
+        let value = response
+            .body()
+            .deserialize::<OwnedValue>()
+            .with_context(|| format!("Failed to deserialize {name}"))?;
+        T::try_from(value).with_context(|| format!("{name} has an unexpected type"))
+    }
+
+    fn run_iteration(&mut self) -> anyhow::Result<Status> {
+        let is_idle = self.get_property::<bool>("IdleHint")?;
+        // Microseconds since the epoch when the hint last changed
+        let changed_at = self.get_property::<u64>("IdleSinceHint")?;
+        let now = Utc::now();
+        let changed_at = DateTime::from_timestamp_micros(changed_at as i64)
+            .filter(|time| *time <= now)
+            .unwrap_or(now);
+
+        if is_idle != self.is_idle {
+            if is_idle {
+                self.idle_state.mark_idle(changed_at);
+            } else {
+                self.idle_state.mark_not_idle(changed_at);
+            }
+            self.is_idle = is_idle;
         }
+        self.idle_state.get_reactive(now)
+    }
+}
+
+/// Polls `GetSessionIdleTime` of org.freedesktop.ScreenSaver on the session bus. Used when the
+/// compositor has no idle protocol.
+pub struct DbusIdleWatcher {
+    connection: zbus::blocking::Connection,
+    idle_state: idle::Tracker,
+    /// KDE's implementation reports milliseconds instead of the seconds of the spec.
+    reports_millis: bool,
+}
+
+impl DbusIdleWatcher {
+    pub fn new(idle_timeout: TimeDelta) -> anyhow::Result<Self> {
+        let watcher = Self {
+            connection: zbus::blocking::Connection::session()
+                .with_context(|| "Unable to connect to the session bus")?,
+            idle_state: idle::Tracker::new(Utc::now(), idle_timeout),
+            reports_millis: detect_compositor() == Compositor::Kde,
+        };
+        watcher.get_idle_seconds()?;
+        Ok(watcher)
+    }
+
+    fn get_idle_seconds(&self) -> anyhow::Result<u32> {
+        let idle_time = self
+            .connection
+            .call_method(
+                Some("org.freedesktop.ScreenSaver"),
+                "/org/freedesktop/ScreenSaver",
+                Some("org.freedesktop.ScreenSaver"),
+                "GetSessionIdleTime",
+                &(),
+            )
+            .with_context(|| "Failed to get the session idle time")?
+            .body()
+            .deserialize::<u32>()
+            .with_context(|| "Failed to deserialize the session idle time")?;
+        Ok(if self.reports_millis {
+            idle_time / 1000
+        } else {
+            idle_time
+        })
+    }
+
+    pub fn run_iteration(&mut self) -> anyhow::Result<Status> {
+        let seconds = self.get_idle_seconds()?;
+        self.idle_state.get_with_last_input(Utc::now(), seconds)
     }
 }
 
+enum IdleBackend {
+    Wayland {
+        connection: WlEventConnection<WatcherState>,
+        watcher_state: WatcherState,
+    },
+    DbusScreenSaver(DbusIdleWatcher),
+    Logind(LogindIdle),
+}
+
+/// Where `IdleWatcher` reads the idle state from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdleSource {
+    /// ext-idle-notify or org_kde_kwin_idle.
+    WaylandProtocol,
+    /// `GetSessionIdleTime` of org.freedesktop.ScreenSaver.
+    DBusScreenSaver,
+    /// The idle hint of the logind session.
+    Logind,
+}
+
 pub struct IdleWatcher {
-    connection: WlEventConnection<WatcherState>,
-    pub watcher_state: WatcherState,
+    backend: IdleBackend,
 }
 
 impl IdleWatcher {
-    pub fn new(timeout: u32) -> anyhow::Result<Self> {
+    /// Tries ext-idle-notify and org_kde_kwin_idle, then org.freedesktop.ScreenSaver, then the
+    /// idle hint of the logind session.
+    pub fn new(timeout: u32, seat_name: Option<&str>) -> anyhow::Result<Self> {
+        let idle_timeout = TimeDelta::milliseconds(timeout as i64);
+        let wayland_error = match Self::wayland(timeout, seat_name) {
+            Ok(watcher) => return Ok(watcher),
+            Err(e) => e,
+        };
+        warn!("No Wayland idle protocol is available, trying D-Bus: {wayland_error}");
+        let screensaver_error = match DbusIdleWatcher::new(idle_timeout) {
+            Ok(watcher) => {
+                return Ok(Self {
+                    backend: IdleBackend::DbusScreenSaver(watcher),
+                });
+            }
+            Err(e) => e,
+        };
+        debug!("org.freedesktop.ScreenSaver is not available, polling logind: {screensaver_error}");
+        let logind = LogindIdle::new(idle_timeout).map_err(|e| {
+            WatcherError::ProtocolUnsupported(format!(
+                "{wayland_error}, org.freedesktop.ScreenSaver: {screensaver_error}, logind: {e}"
+            ))
+        })?;
+        Ok(Self {
+            backend: IdleBackend::Logind(logind),
+        })
+    }
+
+    /// Only polls org.freedesktop.ScreenSaver, which doesn't need the Wayland socket.
+    pub fn dbus_screensaver(timeout: u32) -> anyhow::Result<Self> {
+        Ok(Self {
+            backend: IdleBackend::DbusScreenSaver(DbusIdleWatcher::new(TimeDelta::milliseconds(
+                timeout as i64,
+            ))?),
+        })
+    }
+
+    /// Only tries the Wayland protocols, ext-idle-notify first. Watches every seat, or only the
+    /// one named `seat_name`.
+    pub fn wayland(timeout: u32, seat_name: Option<&str>) -> anyhow::Result<Self> {
         let mut connection: WlEventConnection<WatcherState> = WlEventConnection::connect()?;
-        connection.get_ext_idle()?;
+        let mut watcher_state = WatcherState::new(TimeDelta::milliseconds(timeout as i64));
+
+        let mut seats = connection.get_seats();
+        // Receives the names of the seats
+        connection
+            .event_queue
+            .roundtrip(&mut watcher_state)
+            .with_context(|| "Event queue is not processed")?;
+        if let Some(seat_name) = seat_name {
+            seats.retain(|seat| {
+                watcher_state.seat_names.get(&seat.id()).map(String::as_str) == Some(seat_name)
+            });
+        }
+        if seats.is_empty() {
+            return Err(anyhow!(
+                "No seat is found{}",
+                seat_name
+                    .map(|name| format!(" by name {name}"))
+                    .unwrap_or_default()
+            ));
+        }
 
-        let mut watcher_state = WatcherState::new(
-            connection.get_ext_idle_notification(timeout).unwrap(),
-            TimeDelta::milliseconds(timeout as i64),
-        );
+        for seat in &seats {
+            let notification = match connection.get_ext_idle_notification(timeout, seat) {
+                Ok(notification) => IdleNotification::Ext(notification),
+                Err(ext_error) => {
+                    let timeout = connection
+                        .get_kwin_idle_timeout(timeout, seat)
+                        .map_err(|e| {
+                            anyhow!("ext-idle-notify: {ext_error}, org_kde_kwin_idle: {e}")
+                        })?;
+                    debug!("Using org_kde_kwin_idle");
+                    IdleNotification::KWin(timeout)
+                }
+            };
+            watcher_state.idle_notifications.push(notification);
+        }
         connection
             .event_queue
             .roundtrip(&mut watcher_state)
-            .unwrap();
+            .with_context(|| "Event queue is not processed")?;
 
         Ok(Self {
-            connection,
-            watcher_state,
+            backend: IdleBackend::Wayland {
+                connection,
+                watcher_state,
+            },
         })
     }
 
+    pub fn source(&self) -> IdleSource {
+        match self.backend {
+            IdleBackend::Wayland { .. } => IdleSource::WaylandProtocol,
+            IdleBackend::DbusScreenSaver(_) => IdleSource::DBusScreenSaver,
+            IdleBackend::Logind(_) => IdleSource::Logind,
+        }
+    }
+
+    /// D-Bus sources are polled through a new call every time, so they're always considered
+    /// connected.
+    pub fn is_connected(&self) -> bool {
+        match &self.backend {
+            IdleBackend::Wayland { connection, .. } => connection.is_connected(),
+            IdleBackend::DbusScreenSaver(_) | IdleBackend::Logind(_) => true,
+        }
+    }
+
     pub fn run_iteration(&mut self) -> anyhow::Result<Status> {
-        self.connection
-            .event_queue
-            .roundtrip(&mut self.watcher_state)
-            .with_context(|| "Event queue is not processed")?;
-        Ok(self.watcher_state.idle_state.get_reactive(Utc::now())?)
+        match &mut self.backend {
+            IdleBackend::Wayland {
+                connection,
+                watcher_state,
+            } => {
+                connection
+                    .event_queue
+                    .roundtrip(watcher_state)
+                    .with_context(|| "Event queue is not processed")?;
+                watcher_state.idle_state.get_reactive(Utc::now())
+            }
+            IdleBackend::DbusScreenSaver(watcher) => watcher.run_iteration(),
+            IdleBackend::Logind(logind) => logind.run_iteration(),
+        }
     }
 }
 
 pub struct IdleWatcherRunner {
     pub stop_signal: mpsc::Sender<()>,
-    pub handle: JoinHandle<()>,
+    /// Taken when the thread is joined.
+    pub handle: Option<JoinHandle<()>>,
+    /// `None` until the first reading and while the compositor is unreachable.
     pub current_idle_status: Arc<Mutex<Option<idle::Status>>>,
+    /// The latest window passed to `record_window` while the user was active.
+    last_active_window: Arc<Mutex<Option<ActiveWindowData>>>,
+    /// Copied from `last_active_window` when the user goes idle.
+    window_before_idle: Arc<Mutex<Option<ActiveWindowData>>>,
+    /// Only the Wayland source can be lost, and it's reconnected to the same source.
+    source: IdleSource,
+    seat_name: Option<String>,
+    prefer_dbus: bool,
 }
 
 const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
 
 impl IdleWatcherRunner {
-    pub fn new(timeout: u32) -> anyhow::Result<Self> {
-        let mut idle_watcher = IdleWatcher::new(timeout)?;
+    /// Uses the idle timeout and the Wayland seat of `config`.
+    pub fn new(config: &WatcherConfig) -> anyhow::Result<Self> {
+        Self::start(
+            config.idle_timeout,
+            config.wayland_seat_name.clone(),
+            false,
+            Arc::new(Mutex::new(None)),
+            Arc::new(Mutex::new(None)),
+        )
+    }
+
+    /// Like `new`, but tries org.freedesktop.ScreenSaver before the Wayland protocols, for
+    /// processes that can reach the session bus but not the Wayland socket.
+    pub fn prefer_dbus(config: &WatcherConfig) -> anyhow::Result<Self> {
+        Self::start(
+            config.idle_timeout,
+            config.wayland_seat_name.clone(),
+            true,
+            Arc::new(Mutex::new(None)),
+            Arc::new(Mutex::new(None)),
+        )
+    }
+
+    /// Replaces the watcher and its thread with ones using `timeout`, since idle notifications
+    /// can't change their timeout. The recorded windows are kept.
+    pub fn set_timeout(&mut self, timeout: Duration) -> anyhow::Result<()> {
+        *self = Self::start(
+            timeout,
+            self.seat_name.clone(),
+            self.prefer_dbus,
+            self.last_active_window.clone(),
+            self.window_before_idle.clone(),
+        )?;
+        Ok(())
+    }
+
+    fn start(
+        timeout: Duration,
+        seat_name: Option<String>,
+        prefer_dbus: bool,
+        last_active_window: Arc<Mutex<Option<ActiveWindowData>>>,
+        window_before_idle: Arc<Mutex<Option<ActiveWindowData>>>,
+    ) -> anyhow::Result<Self> {
+        let timeout = timeout.as_millis() as u32;
+        let watcher = match prefer_dbus.then(|| IdleWatcher::dbus_screensaver(timeout)) {
+            Some(Ok(watcher)) => watcher,
+            Some(Err(e)) => {
+                debug!("org.freedesktop.ScreenSaver is not available, trying Wayland: {e}");
+                IdleWatcher::new(timeout, seat_name.as_deref())?
+            }
+            None => IdleWatcher::new(timeout, seat_name.as_deref())?,
+        };
+        let source = watcher.source();
+        let mut idle_watcher = Some(watcher);
+        let mut backoff = ReconnectBackoff::new();
         let (stop_signal, stop_signal_receiver) = mpsc::channel();
         let current_idle_status = Arc::new(Mutex::new(None));
 
         let handle = {
             let current_idle_status = current_idle_status.clone();
+            let last_active_window = last_active_window.clone();
+            let window_before_idle = window_before_idle.clone();
+            let seat_name = seat_name.clone();
             thread::spawn(move || {
-                // while let Ok(_) = stop_signal_receiver.recv() {
                 loop {
-                    match idle_watcher.run_iteration() {
-                        Ok(status) => {
-                            let mut current_idle_status = current_idle_status.lock().unwrap();
-                            *current_idle_status = Some(status);
+                    if idle_watcher.is_none() {
+                        // Only a Wayland source can be lost, so it's the one to reconnect
+                        idle_watcher =
+                            backoff.attempt(|| IdleWatcher::wayland(timeout, seat_name.as_deref()));
+                        if idle_watcher.is_some() {
+                            info!("Reconnected the idle watcher");
                         }
-                        Err(e) => {
-                            error!("Error running idle watcher: {}", e);
+                    }
+                    if let Some(watcher) = &mut idle_watcher {
+                        match watcher.run_iteration() {
+                            Ok(status) => {
+                                let mut current_idle_status = current_idle_status.lock().unwrap();
+                                let was_idle =
+                                    matches!(*current_idle_status, Some(Status::Idle { .. }));
+                                if matches!(status, Status::Idle { .. }) && !was_idle {
+                                    *window_before_idle.lock().unwrap() =
+                                        last_active_window.lock().unwrap().clone();
+                                }
+                                *current_idle_status = Some(status);
+                            }
+                            Err(e) if !watcher.is_connected() => {
+                                warn!("Idle watcher lost the compositor, reconnecting: {e}");
+                                idle_watcher = None;
+                                *current_idle_status.lock().unwrap() = None;
+                            }
+                            Err(e) => {
+                                error!("Error running idle watcher: {}", e);
+                            }
                         }
                     }
 
-                    thread::sleep(IDLE_CHECK_INTERVAL);
-                    if let Ok(_) = stop_signal_receiver.try_recv() {
-                        break;
+                    let wait = match idle_watcher {
+                        Some(_) => IDLE_CHECK_INTERVAL,
+                        None => backoff.remaining().min(IDLE_CHECK_INTERVAL),
+                    };
+                    // Stops on the signal or when the runner is gone
+                    match stop_signal_receiver.recv_timeout(wait) {
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Ok(()) | Err(RecvTimeoutError::Disconnected) => break,
                     }
                 }
             })
         };
         Ok(Self {
             stop_signal,
-            handle,
+            handle: Some(handle),
             current_idle_status,
+            last_active_window,
+            window_before_idle,
+            source,
+            seat_name,
+            prefer_dbus,
         })
     }
+
+    /// Remembers `data` as the active window unless the user is idle, so a window focused during
+    /// the idle period isn't attributed to it.
+    pub fn record_window(&self, data: &ActiveWindowData) {
+        let current_idle_status = self.current_idle_status.lock().unwrap();
+        if !matches!(*current_idle_status, Some(Status::Idle { .. })) {
+            *self.last_active_window.lock().unwrap() = Some(data.clone());
+        }
+    }
+
+    /// Whether the user is idle, and the time since their last input if they are. Active until
+    /// the first reading.
+    pub fn idle_state(&self) -> (bool, Duration) {
+        match *self.current_idle_status.lock().unwrap() {
+            Some(Status::Idle {
+                last_input_time, ..
+            }) => (
+                true,
+                (Utc::now() - last_input_time).to_std().unwrap_or_default(),
+            ),
+            _ => (false, Duration::ZERO),
+        }
+    }
+
+    /// The window recorded last before the user went idle, `None` until they first do.
+    pub fn last_active_window_before_idle(&self) -> Option<ActiveWindowData> {
+        self.window_before_idle.lock().unwrap().clone()
+    }
+
+    pub fn source(&self) -> IdleSource {
+        self.source
+    }
+
+    /// Stops the watcher thread and waits for it to finish.
+    pub fn stop(mut self) {
+        self.stop_and_join();
+    }
+
+    fn stop_and_join(&mut self) {
+        let _ = self.stop_signal.send(());
+        if let Some(handle) = self.handle.take()
+            && handle.join().is_err()
+        {
+            error!("Idle watcher thread panicked");
+        }
+    }
 }
 
 impl Drop for IdleWatcherRunner {
     fn drop(&mut self) {
-        let _ = self.stop_signal.send(());
+        self.stop_and_join();
     }
 }
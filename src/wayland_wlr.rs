@@ -1,20 +1,57 @@
+//! Tracks the active window through wlr-foreign-toplevel-management, falling back to
+//! ext-foreign-toplevel-list when the compositor only advertises the latter.
+//!
+//! ext-foreign-toplevel-list has no notion of an activated toplevel, so with it the most recently
+//! updated toplevel is reported as active. This is usually right when the title of the focused
+//! window changes, but switching between windows without a title change isn't noticed.
+//!
+//! Neither protocol carries the pid, so the process path normally comes from the desktop entry
+//! of the app id. Compositors that expose the pid of the active window some other way, currently
+//! only Hyprland through its IPC socket, get the executable of the process for apps without one.
+
 use crate::ActiveWindowData;
+use crate::IconRef;
+use crate::MonitorInfo;
+use crate::NoActiveWindow;
+use crate::Sample;
+use crate::WatcherError;
 use crate::WindowManager;
 use crate::config::WatcherConfig;
-use crate::idle::Status;
 use crate::linux_desktop::DesktopInfo;
-use crate::linux_desktop::LinuxDesktopInfo;
-use crate::simple_cache::SimpleCache;
+use crate::linux_desktop::DesktopInfoResolver;
+use crate::linux_desktop::SharedDesktopInfo;
+use crate::linux_desktop::get_process_path;
+use crate::linux_desktop::lock_desktop_info;
+use crate::simple_cache::SharedCache;
 use crate::wayland_idle::IdleWatcherRunner;
 
+use super::wl_connection::ReconnectBackoff;
 use super::wl_connection::WlEventConnection;
 use super::wl_connection::subscribe_state;
 use anyhow::anyhow;
-use std::collections::HashMap;
-use tracing::{debug, error, trace, warn};
+use chrono::Utc;
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+use tracing::{debug, error, info, trace, warn};
 use wayland_client::{
-    Connection, Dispatch, Proxy, QueueHandle, event_created_child, globals::GlobalListContents,
-    protocol::wl_registry,
+    Connection, Dispatch, Proxy, QueueHandle, WEnum, event_created_child,
+    globals::GlobalListContents,
+    protocol::{
+        wl_output::{Event as OutputEvent, Mode as OutputMode, WlOutput},
+        wl_registry,
+    },
+};
+use wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_handle_v1::{
+    Event as ExtHandleEvent, ExtForeignToplevelHandleV1,
+};
+use wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_list_v1::{
+    EVT_TOPLEVEL_OPCODE as EXT_EVT_TOPLEVEL_OPCODE, Event as ExtListEvent, ExtForeignToplevelListV1,
 };
 use wayland_protocols_wlr::foreign_toplevel::v1::client::zwlr_foreign_toplevel_handle_v1::{
     Event as HandleEvent, State as HandleState, ZwlrForeignToplevelHandleV1,
@@ -23,14 +60,94 @@ use wayland_protocols_wlr::foreign_toplevel::v1::client::zwlr_foreign_toplevel_m
     EVT_TOPLEVEL_OPCODE, Event as ManagerEvent, ZwlrForeignToplevelManagerV1,
 };
 
+/// State bits of a wlr toplevel.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct WindowState {
+    activated: bool,
+    fullscreen: bool,
+    maximized: bool,
+    minimized: bool,
+}
+
+impl WindowState {
+    /// Parses the state array, which holds native endian u32 values of `HandleState`.
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let mut state = Self::default();
+        for value in bytes.chunks_exact(4) {
+            let value = u32::from_ne_bytes([value[0], value[1], value[2], value[3]]);
+            match HandleState::try_from(value) {
+                Ok(HandleState::Activated) => state.activated = true,
+                Ok(HandleState::Fullscreen) => state.fullscreen = true,
+                Ok(HandleState::Maximized) => state.maximized = true,
+                Ok(HandleState::Minimized) => state.minimized = true,
+                _ => (),
+            }
+        }
+        state
+    }
+}
+
 struct WindowData {
     app_id: String,
     title: String,
+    /// Changes received since the last `Done` event. The protocol requires them to be applied
+    /// atomically.
+    pending_app_id: Option<String>,
+    pending_title: Option<String>,
+    /// Ids of the outputs the window is on, the most recently entered one is last.
+    outputs: Vec<String>,
+    /// Missing until the compositor reports it, ext-foreign-toplevel-list never does.
+    state: Option<WindowState>,
+    /// Only Hyprland reports it, through its IPC socket.
+    pid: Option<u32>,
+    /// Whether the pid was asked for since the window was last activated.
+    is_pid_queried: bool,
+}
+
+impl WindowData {
+    fn new() -> Self {
+        Self {
+            app_id: "unknown".into(),
+            title: "unknown".into(),
+            pending_app_id: None,
+            pending_title: None,
+            outputs: Vec::new(),
+            state: None,
+            pid: None,
+            is_pid_queried: false,
+        }
+    }
+
+    /// Applies the pending changes, returns whether there were any.
+    fn apply_pending(&mut self) -> bool {
+        let mut is_changed = false;
+        if let Some(title) = self.pending_title.take() {
+            self.title = title;
+            is_changed = true;
+        }
+        if let Some(app_id) = self.pending_app_id.take() {
+            self.app_id = app_id;
+            is_changed = true;
+        }
+        is_changed
+    }
+}
+
+/// The protocol the toplevels are received from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ToplevelSource {
+    Wlr,
+    ExtList,
 }
 
 struct ToplevelState {
     windows: HashMap<String, WindowData>,
     current_window_id: Option<String>,
+    /// Bound outputs by their ids, indexed in the order they were announced. Outputs connected
+    /// after the start aren't bound.
+    monitors: HashMap<String, MonitorInfo>,
+    /// Set when the compositor revokes the manager, the state has to be rebuilt from a new one.
+    is_finished: bool,
 }
 
 impl ToplevelState {
@@ -38,6 +155,79 @@ impl ToplevelState {
         Self {
             windows: HashMap::new(),
             current_window_id: None,
+            monitors: HashMap::new(),
+            is_finished: false,
+        }
+    }
+
+    /// Forgets the toplevels of a finished source and collects the new ones through `rebind`.
+    /// The bound outputs are kept, they aren't announced again.
+    fn rebuild(
+        &mut self,
+        rebind: impl FnOnce(&mut Self) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let monitors = std::mem::take(&mut self.monitors);
+        *self = Self::new();
+        self.monitors = monitors;
+        rebind(self)
+    }
+
+    /// Applies an event of the wlr toplevel `id`.
+    fn handle_wlr_event(&mut self, id: String, event: HandleEvent) {
+        let window = self.windows.get_mut(&id);
+        if let Some(window) = window {
+            match event {
+                HandleEvent::Title { title } => {
+                    trace!("Title is changed for {id}");
+                    window.pending_title = Some(title);
+                }
+                HandleEvent::AppId { app_id } => {
+                    trace!("App ID is changed for {id}: {app_id}");
+                    window.pending_app_id = Some(app_id);
+                }
+                HandleEvent::State { state } => {
+                    let state = WindowState::from_bytes(&state);
+                    trace!("State is changed for {id}: {state:?}");
+                    window.state = Some(state);
+                    if state.activated {
+                        trace!("Window is activated: {id}");
+                        if self.current_window_id.as_ref() != Some(&id) {
+                            window.is_pid_queried = false;
+                        }
+                        self.current_window_id = Some(id);
+                    } else if self.current_window_id.as_ref() == Some(&id) {
+                        trace!("Window is deactivated: {id}");
+                        self.current_window_id = None;
+                    }
+                }
+                HandleEvent::OutputEnter { output } => {
+                    let output_id = output.id().to_string();
+                    trace!("Window {id} entered output {output_id}");
+                    window.outputs.retain(|id| id != &output_id);
+                    window.outputs.push(output_id);
+                }
+                HandleEvent::OutputLeave { output } => {
+                    let output_id = output.id().to_string();
+                    trace!("Window {id} left output {output_id}");
+                    window.outputs.retain(|id| id != &output_id);
+                }
+                HandleEvent::Done => {
+                    trace!("Done: {id}");
+                    window.apply_pending();
+                }
+                HandleEvent::Closed => {
+                    trace!("Window is closed: {id}");
+                    if self.windows.remove(&id).is_none() {
+                        warn!("Window is already removed: {id}");
+                    }
+                    if self.current_window_id.as_ref() == Some(&id) {
+                        self.current_window_id = None;
+                    }
+                }
+                _ => (),
+            };
+        } else {
+            error!("Window is not found: {id}");
         }
     }
 }
@@ -54,16 +244,13 @@ impl Dispatch<ZwlrForeignToplevelManagerV1, ()> for ToplevelState {
         match event {
             ManagerEvent::Toplevel { toplevel } => {
                 debug!("Toplevel handle is received {}", toplevel.id());
-                state.windows.insert(
-                    toplevel.id().to_string(),
-                    WindowData {
-                        app_id: "unknown".into(),
-                        title: "unknown".into(),
-                    },
-                );
+                state
+                    .windows
+                    .insert(toplevel.id().to_string(), WindowData::new());
             }
             ManagerEvent::Finished => {
-                error!("Toplevel manager is finished, the application may crash");
+                error!("Toplevel manager is finished, it will be rebound");
+                state.is_finished = true;
             }
             _ => (),
         };
@@ -85,123 +272,417 @@ impl Dispatch<ZwlrForeignToplevelHandleV1, ()> for ToplevelState {
         _: &(),
         _: &Connection,
         _: &QueueHandle<Self>,
+    ) {
+        toplevel_state.handle_wlr_event(handle.id().to_string(), event);
+    }
+}
+
+impl Dispatch<WlOutput, ()> for ToplevelState {
+    fn event(
+        state: &mut Self,
+        output: &WlOutput,
+        event: <WlOutput as Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let index = state.monitors.len() as u32;
+        let monitor = state
+            .monitors
+            .entry(output.id().to_string())
+            .or_insert_with(|| MonitorInfo {
+                name: "".into(),
+                index,
+                resolution: (0, 0),
+            });
+        match event {
+            OutputEvent::Name { name } => {
+                debug!("Output {} is named {name}", output.id());
+                monitor.name = name.into();
+            }
+            // Outputs older than version 4 don't have a name
+            OutputEvent::Geometry { make, model, .. } if monitor.name.is_empty() => {
+                monitor.name = format!("{make} {model}").into();
+            }
+            OutputEvent::Mode {
+                flags: WEnum::Value(flags),
+                width,
+                height,
+                ..
+            } if flags.contains(OutputMode::Current) => {
+                monitor.resolution = (width as u32, height as u32);
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Dispatch<ExtForeignToplevelListV1, ()> for ToplevelState {
+    fn event(
+        state: &mut Self,
+        _: &ExtForeignToplevelListV1,
+        event: <ExtForeignToplevelListV1 as Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            ExtListEvent::Toplevel { toplevel } => {
+                debug!("Ext toplevel handle is received {}", toplevel.id());
+                state
+                    .windows
+                    .insert(toplevel.id().to_string(), WindowData::new());
+            }
+            ExtListEvent::Finished => {
+                error!("Ext toplevel list is finished, it will be rebound");
+                state.is_finished = true;
+            }
+            _ => (),
+        };
+    }
+
+    event_created_child!(ToplevelState, ExtForeignToplevelListV1, [
+        EXT_EVT_TOPLEVEL_OPCODE => (ExtForeignToplevelHandleV1, ()),
+    ]);
+}
+
+impl Dispatch<ExtForeignToplevelHandleV1, ()> for ToplevelState {
+    fn event(
+        toplevel_state: &mut Self,
+        handle: &ExtForeignToplevelHandleV1,
+        event: <ExtForeignToplevelHandleV1 as Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
     ) {
         let id = handle.id().to_string();
-        let window = toplevel_state.windows.get_mut(&id);
-        if let Some(window) = window {
-            match event {
-                HandleEvent::Title { title } => {
-                    trace!("Title is changed for {id}: {title}");
-                    window.title = title;
-                }
-                HandleEvent::AppId { app_id } => {
-                    trace!("App ID is changed for {id}: {app_id}");
-                    window.app_id = app_id;
-                }
-                HandleEvent::State { state } => {
-                    trace!("State is changed for {id}: {state:?}");
-                    if state.contains(&(HandleState::Activated as u8)) {
-                        trace!("Window is activated: {id}");
-                        toplevel_state.current_window_id = Some(id);
+        let Some(window) = toplevel_state.windows.get_mut(&id) else {
+            error!("Window is not found: {id}");
+            return;
+        };
+        match event {
+            ExtHandleEvent::Title { title } => {
+                trace!("Title is changed for {id}");
+                window.pending_title = Some(title);
+            }
+            ExtHandleEvent::AppId { app_id } => {
+                trace!("App ID is changed for {id}: {app_id}");
+                window.pending_app_id = Some(app_id);
+            }
+            ExtHandleEvent::Done => {
+                trace!("Done: {id}");
+                // The protocol doesn't expose activation, the latest updated window is assumed
+                if window.apply_pending() {
+                    if toplevel_state.current_window_id.as_ref() != Some(&id) {
+                        window.is_pid_queried = false;
                     }
+                    toplevel_state.current_window_id = Some(id);
                 }
-                HandleEvent::Done => trace!("Done: {id}"),
-                HandleEvent::Closed => {
-                    trace!("Window is closed: {id}");
-                    if toplevel_state.windows.remove(&id).is_none() {
-                        warn!("Window is already removed: {id}");
-                    }
+            }
+            ExtHandleEvent::Closed => {
+                trace!("Window is closed: {id}");
+                toplevel_state.windows.remove(&id);
+                if toplevel_state.current_window_id.as_ref() == Some(&id) {
+                    toplevel_state.current_window_id = None;
                 }
-                _ => (),
-            };
-        } else {
-            error!("Window is not found: {id}");
-        }
+                handle.destroy();
+            }
+            _ => (),
+        };
     }
 }
 
+/// Binds wlr-foreign-toplevel-management if it's advertised, ext-foreign-toplevel-list otherwise.
+fn bind_toplevel_source(
+    connection: &WlEventConnection<ToplevelState>,
+) -> anyhow::Result<ToplevelSource> {
+    let wlr_error = match connection.get_foreign_toplevel_manager() {
+        Ok(_) => return Ok(ToplevelSource::Wlr),
+        Err(e) => e,
+    };
+    debug!("wlr foreign toplevel manager is unavailable: {wlr_error}");
+
+    connection
+        .get_ext_foreign_toplevel_list()
+        .map(|_| {
+            warn!("Only ext-foreign-toplevel-list is available, the active window is approximate");
+            ToplevelSource::ExtList
+        })
+        .map_err(|e| anyhow!("No foreign toplevel protocol is available: {wlr_error}, {e}"))
+}
+
 pub struct WaylandWindowWatcherInner {
     connection: WlEventConnection<ToplevelState>,
+    toplevel_source: ToplevelSource,
     toplevel_state: ToplevelState,
-    desktop_info_cache: SimpleCache<String, DesktopInfo>,
-    linux_desktop_info: LinuxDesktopInfo,
+    desktop_info: SharedDesktopInfo,
+    /// Executables of the pids reported by the compositor.
+    process_path_cache: SharedCache<u32, Option<Arc<str>>>,
+    reconnect_backoff: ReconnectBackoff,
+}
+
+/// Asks the IPC socket of Hyprland for the pid of the active window. `None` on other
+/// compositors, or if Hyprland's active window isn't `app_id` anymore.
+fn hyprland_active_pid(app_id: &str) -> Option<u32> {
+    let signature = std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE")?;
+    let socket = PathBuf::from(std::env::var_os("XDG_RUNTIME_DIR")?)
+        .join("hypr")
+        .join(signature)
+        .join(".socket.sock");
+    let response = (|| -> std::io::Result<String> {
+        let mut stream = UnixStream::connect(&socket)?;
+        stream.set_read_timeout(Some(Duration::from_millis(500)))?;
+        stream.write_all(b"j/activewindow")?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        Ok(response)
+    })()
+    .inspect_err(|e| trace!("Failed to get the active window from Hyprland: {e}"))
+    .ok()?;
+    let window: serde_json::Value = serde_json::from_str(&response).ok()?;
+    if window["class"].as_str() != Some(app_id) {
+        return None;
+    }
+    window["pid"]
+        .as_u64()
+        .and_then(|pid| u32::try_from(pid).ok())
+}
+
+/// Opens a new connection to the compositor and collects the initial toplevels.
+fn connect_toplevels() -> anyhow::Result<(
+    WlEventConnection<ToplevelState>,
+    ToplevelSource,
+    ToplevelState,
+)> {
+    let mut connection: WlEventConnection<ToplevelState> = WlEventConnection::connect()?;
+    let toplevel_source = bind_toplevel_source(&connection)?;
+
+    // Outputs have to be bound for the compositor to report them on toplevels
+    connection.get_outputs();
+    let mut toplevel_state = ToplevelState::new();
+
+    connection
+        .event_queue
+        .roundtrip(&mut toplevel_state)
+        .map_err(|e| anyhow!("Event queue is not processed: {e}"))?;
+    Ok((connection, toplevel_source, toplevel_state))
 }
 
 impl WaylandWindowWatcherInner {
-    pub fn new(config: WatcherConfig) -> anyhow::Result<Self> {
-        let mut connection: WlEventConnection<ToplevelState> = WlEventConnection::connect()?;
-        connection.get_foreign_toplevel_manager()?;
+    pub fn from_config(config: WatcherConfig) -> anyhow::Result<Self> {
+        let desktop_info = DesktopInfoResolver::shared(&config);
+        Self::with_desktop_info(config, desktop_info)
+    }
 
-        let mut toplevel_state = ToplevelState::new();
+    #[deprecated(note = "use `from_config`, which every backend has")]
+    pub fn new(config: WatcherConfig) -> anyhow::Result<Self> {
+        Self::from_config(config)
+    }
 
-        connection
-            .event_queue
-            .roundtrip(&mut toplevel_state)
-            .unwrap();
+    /// Like `from_config`, but looks the apps up in `desktop_info`, which other watchers may share.
+    pub fn with_desktop_info(
+        config: WatcherConfig,
+        desktop_info: SharedDesktopInfo,
+    ) -> anyhow::Result<Self> {
+        let (connection, toplevel_source, toplevel_state) = connect_toplevels()?;
 
         Ok(Self {
             connection,
+            toplevel_source,
             toplevel_state,
-            desktop_info_cache: SimpleCache::new(config.cache_config),
-            linux_desktop_info: LinuxDesktopInfo::new(),
+            reconnect_backoff: ReconnectBackoff::new(),
+            desktop_info,
+            process_path_cache: SharedCache::new(config.cache_config.clone()),
+        })
+    }
+
+    /// Binds a new toplevel manager and rebuilds the window list from its events.
+    /// Fails if the compositor no longer advertises any of the supported protocols.
+    pub fn reconnect(&mut self) -> anyhow::Result<()> {
+        let connection = &mut self.connection;
+        let toplevel_source = &mut self.toplevel_source;
+        self.toplevel_state.rebuild(|toplevel_state| {
+            *toplevel_source = bind_toplevel_source(connection)?;
+            debug!("Toplevels are tracked through {toplevel_source:?}");
+            connection
+                .event_queue
+                .roundtrip(toplevel_state)
+                .map_err(|e| anyhow!("Event queue is not processed: {e}"))?;
+            Ok(())
         })
     }
 
     pub fn run_iteration(&mut self) -> anyhow::Result<ActiveWindowData> {
+        if !self.connection.is_connected() {
+            let reason = match self.connection.connection.protocol_error() {
+                Some(e) => e.to_string(),
+                None => "The socket is closed".into(),
+            };
+            let Some((connection, toplevel_source, toplevel_state)) =
+                self.reconnect_backoff.attempt(connect_toplevels)
+            else {
+                return Err(WatcherError::ProtocolError(reason).into());
+            };
+            info!("Reconnected to the compositor after: {reason}");
+            self.connection = connection;
+            self.toplevel_source = toplevel_source;
+            self.toplevel_state = toplevel_state;
+        }
         self.connection
             .event_queue
             .roundtrip(&mut self.toplevel_state)
             .map_err(|e| anyhow!("Event queue is not processed: {e}"))?;
 
-        let active_window_id = self
-            .toplevel_state
-            .current_window_id
-            .as_ref()
-            .ok_or(anyhow!("Current window is unknown"))?;
+        if self.toplevel_state.is_finished {
+            debug!("Rebinding the toplevel manager");
+            self.reconnect()?;
+        }
+
+        let Some(active_window_id) = self.toplevel_state.current_window_id.as_ref() else {
+            trace!("No window is active");
+            return Err(NoActiveWindow.into());
+        };
         let active_window = self
             .toplevel_state
             .windows
-            .get(active_window_id)
+            .get_mut(active_window_id)
             .ok_or(anyhow!(
                 "Current window is not found by ID {active_window_id}"
             ))?;
 
-        let (process_path, app_name) = match self.desktop_info_cache.get(&active_window.app_id) {
-            Some(extra_info) => (Some(extra_info.process_path), Some(extra_info.app_name)),
-            None => {
-                if let Some(extra_info) = self
-                    .linux_desktop_info
-                    .get_extra_info(&active_window.app_id)
-                {
-                    self.desktop_info_cache
-                        .set(active_window_id.clone(), extra_info.clone());
-                    (Some(extra_info.process_path), Some(extra_info.app_name))
-                } else {
-                    (None, None)
-                }
-            }
+        let desktop_info = lock_desktop_info(&self.desktop_info).get(&active_window.app_id);
+        let icon_name = desktop_info.as_ref().and_then(DesktopInfo::icon_name);
+        let (process_path, app_name) = match desktop_info {
+            Some(info) => (Some(info.process_path), Some(info.app_name)),
+            None => (None, None),
         };
+        // The socket is only asked once per activation, the pid of a window doesn't change
+        if active_window.pid.is_none() && !active_window.is_pid_queried {
+            active_window.pid = hyprland_active_pid(&active_window.app_id);
+            active_window.is_pid_queried = true;
+        }
+        let pid = active_window.pid;
+        let process_path = process_path
+            .or_else(|| pid.and_then(|pid| get_process_path(&self.process_path_cache, pid)));
 
         Ok(ActiveWindowData {
             window_title: active_window.title.clone().into(),
             app_identifier: Some(active_window.app_id.clone().into()),
+            resource_class: None,
             process_path,
             app_name,
+            workspace: None,
+            geometry: None,
+            is_fullscreen: active_window.state.map(|state| state.fullscreen),
+            window_type: None,
+            virtual_desktop: None,
+            icon_name,
+            publisher: None,
+            focused_at: None,
+            pid,
+            monitor: active_window
+                .outputs
+                .last()
+                .and_then(|output_id| self.toplevel_state.monitors.get(output_id))
+                .cloned(),
         })
     }
+
+    /// Every toplevel, the active one first. Ext-foreign-toplevel-list reports the most recently
+    /// updated one as active.
+    pub fn window_list(&mut self) -> anyhow::Result<Vec<ActiveWindowData>> {
+        self.connection
+            .event_queue
+            .roundtrip(&mut self.toplevel_state)
+            .map_err(|e| anyhow!("Event queue is not processed: {e}"))?;
+
+        let mut desktop_info = lock_desktop_info(&self.desktop_info);
+        let mut windows = Vec::with_capacity(self.toplevel_state.windows.len());
+        for (id, window) in &self.toplevel_state.windows {
+            let info = desktop_info.get(&window.app_id);
+            let icon_name = info.as_ref().and_then(DesktopInfo::icon_name);
+            let (process_path, app_name) = match info {
+                Some(info) => (Some(info.process_path), Some(info.app_name)),
+                None => (None, None),
+            };
+            let data = ActiveWindowData {
+                window_title: window.title.clone().into(),
+                app_identifier: Some(window.app_id.clone().into()),
+                resource_class: None,
+                process_path,
+                app_name,
+                workspace: None,
+                geometry: None,
+                is_fullscreen: window.state.map(|state| state.fullscreen),
+                window_type: None,
+                virtual_desktop: None,
+                icon_name,
+                publisher: None,
+                focused_at: None,
+                pid: None,
+                monitor: window
+                    .outputs
+                    .last()
+                    .and_then(|output_id| self.toplevel_state.monitors.get(output_id))
+                    .cloned(),
+            };
+            if self.toplevel_state.current_window_id.as_ref() == Some(id) {
+                windows.insert(0, data);
+            } else {
+                windows.push(data);
+            }
+        }
+        Ok(windows)
+    }
 }
 
 pub struct WaylandWindowWatcher {
     inner: WaylandWindowWatcherInner,
-    pub idle_watcher: IdleWatcherRunner,
+    /// `None` when the compositor has no idle protocol and logind is unreachable.
+    pub idle_watcher: Option<IdleWatcherRunner>,
 }
 
 impl WaylandWindowWatcher {
+    /// The window that was active when the user last went idle. `None` if idle isn't tracked.
+    pub fn last_active_window_before_idle(&self) -> Option<ActiveWindowData> {
+        self.idle_watcher
+            .as_ref()
+            .and_then(IdleWatcherRunner::last_active_window_before_idle)
+    }
+
+    pub fn window_list(&mut self) -> anyhow::Result<Vec<ActiveWindowData>> {
+        self.inner.window_list()
+    }
+
+    fn idle_state(&self) -> anyhow::Result<(bool, Duration)> {
+        let Some(idle_watcher) = &self.idle_watcher else {
+            return Err(WatcherError::ProtocolUnsupported("no idle protocol".into()).into());
+        };
+        Ok(idle_watcher.idle_state())
+    }
+
+    pub fn from_config(config: WatcherConfig) -> anyhow::Result<Self> {
+        let desktop_info = DesktopInfoResolver::shared(&config);
+        Self::with_desktop_info(config, desktop_info)
+    }
+
+    #[deprecated(note = "use `from_config`, which every backend has")]
     pub fn new(config: WatcherConfig) -> anyhow::Result<Self> {
-        let window_watcher = WaylandWindowWatcherInner::new(config.clone())?;
+        Self::from_config(config)
+    }
+
+    /// Like `from_config`, but looks the apps up in `desktop_info`, which other watchers may share.
+    pub fn with_desktop_info(
+        config: WatcherConfig,
+        desktop_info: SharedDesktopInfo,
+    ) -> anyhow::Result<Self> {
+        let window_watcher =
+            WaylandWindowWatcherInner::with_desktop_info(config.clone(), desktop_info)?;
         Ok(Self {
             inner: window_watcher,
-            idle_watcher: IdleWatcherRunner::new(config.idle_timeout.as_millis() as u32)?,
+            idle_watcher: IdleWatcherRunner::new(&config)
+                .inspect_err(|e| error!("Idle is not tracked: {e}"))
+                .ok(),
         })
     }
 }
@@ -213,16 +694,249 @@ impl Drop for WaylandWindowWatcher {
 }
 
 impl WindowManager for WaylandWindowWatcher {
+    #[tracing::instrument(name = "wayland::get_active", skip(self))]
     fn get_active_window_data(&mut self) -> anyhow::Result<ActiveWindowData> {
-        self.inner.run_iteration()
+        let data = self.inner.run_iteration()?;
+        if let Some(idle_watcher) = &self.idle_watcher {
+            idle_watcher.record_window(&data);
+        }
+        Ok(data)
     }
 
+    #[tracing::instrument(name = "wayland::is_idle", skip(self))]
     fn is_idle(&mut self) -> anyhow::Result<bool> {
-        let status_guard = self.idle_watcher.current_idle_status.lock().unwrap();
-        match *status_guard {
-            Some(Status::Active { .. }) => Ok(false),
-            Some(Status::Idle { .. }) => Ok(true),
-            None => Ok(false),
+        Ok(self.idle_state()?.0)
+    }
+
+    fn idle_duration(&mut self) -> anyhow::Result<Duration> {
+        Ok(self.idle_state()?.1)
+    }
+
+    fn get_window_list(&mut self) -> anyhow::Result<Vec<ActiveWindowData>> {
+        self.window_list()
+    }
+
+    fn set_idle_timeout(&mut self, timeout: Duration) -> anyhow::Result<()> {
+        let Some(idle_watcher) = &mut self.idle_watcher else {
+            return Err(WatcherError::ProtocolUnsupported("no idle protocol".into()).into());
+        };
+        idle_watcher.set_timeout(timeout)
+    }
+
+    fn get_app_icon(&mut self, app_id: &str) -> anyhow::Result<Option<IconRef>> {
+        Ok(lock_desktop_info(&self.inner.desktop_info).app_icon(app_id))
+    }
+
+    fn sample(&mut self) -> anyhow::Result<Sample> {
+        let timestamp = Utc::now();
+        let window = self.get_active_window_data()?;
+        let (idle, idle_duration) = self.idle_state()?;
+        Ok(Sample {
+            window,
+            idle,
+            idle_duration,
+            timestamp,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state_bytes(states: &[HandleState]) -> Vec<u8> {
+        states
+            .iter()
+            .flat_map(|state| (*state as u32).to_ne_bytes())
+            .collect()
+    }
+
+    fn activated(is_activated: bool) -> HandleEvent {
+        let states: &[HandleState] = if is_activated {
+            &[HandleState::Activated]
+        } else {
+            &[]
+        };
+        HandleEvent::State {
+            state: state_bytes(states),
         }
     }
+
+    fn with_windows(ids: &[&str]) -> ToplevelState {
+        let mut state = ToplevelState::new();
+        for id in ids {
+            state.windows.insert(id.to_string(), WindowData::new());
+        }
+        state
+    }
+
+    #[test]
+    fn activation_tracks_the_current_window() {
+        let mut state = with_windows(&["a", "b"]);
+        state.handle_wlr_event("a".into(), activated(true));
+        assert_eq!(state.current_window_id.as_deref(), Some("a"));
+
+        // Compositors send the new window's activation before the old one's deactivation
+        state.handle_wlr_event("b".into(), activated(true));
+        state.handle_wlr_event("a".into(), activated(false));
+        assert_eq!(state.current_window_id.as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn deactivation_clears_the_current_window() {
+        let mut state = with_windows(&["a"]);
+        state.handle_wlr_event("a".into(), activated(true));
+        state.handle_wlr_event("a".into(), activated(false));
+        assert_eq!(state.current_window_id, None);
+        assert_eq!(state.windows["a"].state, Some(WindowState::default()));
+    }
+
+    #[test]
+    fn closing_the_active_window_clears_it() {
+        let mut state = with_windows(&["a", "b"]);
+        state.handle_wlr_event("a".into(), activated(true));
+        state.handle_wlr_event("b".into(), HandleEvent::Closed);
+        assert_eq!(state.current_window_id.as_deref(), Some("a"));
+
+        state.handle_wlr_event("a".into(), HandleEvent::Closed);
+        assert_eq!(state.current_window_id, None);
+        assert!(state.windows.is_empty());
+    }
+
+    #[test]
+    fn events_of_unknown_windows_are_ignored() {
+        let mut state = with_windows(&["a"]);
+        state.handle_wlr_event("b".into(), activated(true));
+        state.handle_wlr_event("b".into(), HandleEvent::Closed);
+        assert_eq!(state.current_window_id, None);
+        assert_eq!(state.windows.len(), 1);
+    }
+
+    #[test]
+    fn reactivation_asks_for_the_pid_again() {
+        let mut state = with_windows(&["a", "b"]);
+        state.handle_wlr_event("a".into(), activated(true));
+        state.windows.get_mut("a").unwrap().is_pid_queried = true;
+
+        // A repeated state event of the same window keeps the answer
+        state.handle_wlr_event(
+            "a".into(),
+            HandleEvent::State {
+                state: state_bytes(&[HandleState::Activated, HandleState::Maximized]),
+            },
+        );
+        assert!(state.windows["a"].is_pid_queried);
+
+        state.handle_wlr_event("b".into(), activated(true));
+        state.handle_wlr_event("a".into(), activated(true));
+        assert!(!state.windows["a"].is_pid_queried);
+    }
+
+    fn title(title: &str) -> HandleEvent {
+        HandleEvent::Title {
+            title: title.into(),
+        }
+    }
+
+    fn app_id(app_id: &str) -> HandleEvent {
+        HandleEvent::AppId {
+            app_id: app_id.into(),
+        }
+    }
+
+    #[test]
+    fn changes_are_applied_on_done() {
+        let mut state = with_windows(&["a", "b"]);
+        let events = [
+            ("a", title("Draft")),
+            ("b", app_id("org.gnome.Nautilus")),
+            ("a", app_id("org.gnome.TextEditor")),
+            ("a", title("Draft (saved)")),
+            ("b", title("Home")),
+        ];
+        for (id, event) in events {
+            state.handle_wlr_event(id.into(), event);
+        }
+        assert_eq!(state.windows["a"].title, "unknown");
+        assert_eq!(state.windows["a"].app_id, "unknown");
+
+        state.handle_wlr_event("a".into(), HandleEvent::Done);
+        let a = &state.windows["a"];
+        assert_eq!(
+            (a.title.as_str(), a.app_id.as_str()),
+            ("Draft (saved)", "org.gnome.TextEditor")
+        );
+        // The other window's changes wait for its own Done
+        assert_eq!(state.windows["b"].title, "unknown");
+
+        state.handle_wlr_event("b".into(), HandleEvent::Done);
+        let b = &state.windows["b"];
+        assert_eq!(
+            (b.title.as_str(), b.app_id.as_str()),
+            ("Home", "org.gnome.Nautilus")
+        );
+    }
+
+    #[test]
+    fn done_keeps_unchanged_fields() {
+        let mut state = with_windows(&["a"]);
+        state.handle_wlr_event("a".into(), app_id("firefox"));
+        state.handle_wlr_event("a".into(), title("Start page"));
+        state.handle_wlr_event("a".into(), HandleEvent::Done);
+
+        state.handle_wlr_event("a".into(), title("Docs"));
+        state.handle_wlr_event("a".into(), HandleEvent::Done);
+        let a = &state.windows["a"];
+        assert_eq!((a.title.as_str(), a.app_id.as_str()), ("Docs", "firefox"));
+
+        // A Done without changes, e.g. after a state event, changes nothing
+        state.handle_wlr_event("a".into(), HandleEvent::Done);
+        assert_eq!(state.windows["a"].title, "Docs");
+        assert!(state.windows["a"].pending_title.is_none());
+    }
+
+    #[test]
+    fn finished_manager_is_rebuilt_from_the_new_one() {
+        let mut state = with_windows(&["a", "b"]);
+        state.handle_wlr_event("a".into(), activated(true));
+        state.monitors.insert(
+            "wl_output@3".into(),
+            MonitorInfo {
+                name: "DP-1".into(),
+                index: 0,
+                resolution: (2560, 1440),
+            },
+        );
+        state.is_finished = true;
+
+        let mut rebind_count = 0;
+        state
+            .rebuild(|state| {
+                rebind_count += 1;
+                // Stands in for the roundtrip that announces the toplevels of the new manager
+                assert!(state.windows.is_empty());
+                assert!(!state.is_finished);
+                state.windows.insert("c".into(), WindowData::new());
+                state.handle_wlr_event("c".into(), activated(true));
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(rebind_count, 1);
+        assert_eq!(state.windows.keys().collect::<Vec<_>>(), ["c"]);
+        assert_eq!(state.current_window_id.as_deref(), Some("c"));
+        assert_eq!(state.monitors["wl_output@3"].name.as_ref(), "DP-1");
+    }
+
+    #[test]
+    fn failed_rebind_leaves_no_stale_windows() {
+        let mut state = with_windows(&["a"]);
+        state.handle_wlr_event("a".into(), activated(true));
+        state.is_finished = true;
+
+        let result = state.rebuild(|_| Err(anyhow!("No foreign toplevel protocol is available")));
+        assert!(result.is_err());
+        assert!(state.windows.is_empty());
+        assert_eq!(state.current_window_id, None);
+    }
 }
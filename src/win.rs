@@ -3,17 +3,26 @@
 
 use crate::{
     config::WatcherConfig,
-    windows_desktop::{WindowsAppInfo, WindowsDesktopInfo},
+    windows_desktop::{WindowsAppInfo, WindowsDesktopInfo, extract_icon},
+    windows_idle::WindowsIdleNotifier,
 };
 use anyhow::{Result, anyhow};
-use tracing::error;
+use tracing::{debug, error};
 use windows::{
     Win32::{
-        Foundation::{CloseHandle, GetLastError, HANDLE, HWND},
+        Foundation::{CloseHandle, GetLastError, HANDLE, HWND, LPARAM, RECT},
+        Graphics::Gdi::{
+            EnumDisplayMonitors, GetMonitorInfoW, HDC, HMONITOR, MONITOR_DEFAULTTONULL,
+            MONITORINFO, MONITORINFOEXW, MonitorFromWindow,
+        },
         System::{
             Diagnostics::Debug::{
                 FORMAT_MESSAGE_FROM_SYSTEM, FORMAT_MESSAGE_IGNORE_INSERTS, FormatMessageW,
             },
+            StationsAndDesktops::{
+                CloseDesktop, DESKTOP_CONTROL_FLAGS, DESKTOP_READOBJECTS,
+                GetUserObjectInformationW, OpenInputDesktop, UOI_NAME,
+            },
             SystemInformation::GetTickCount64,
             SystemServices::{LANG_ENGLISH, SUBLANG_ENGLISH_US},
             Threading::{
@@ -23,13 +32,16 @@ use windows::{
         },
         UI::{
             Input::KeyboardAndMouse::{GetLastInputInfo, LASTINPUTINFO},
-            WindowsAndMessaging::{GetForegroundWindow, GetWindowTextW, GetWindowThreadProcessId},
+            WindowsAndMessaging::{
+                EnumWindows, GetForegroundWindow, GetWindowTextW, GetWindowThreadProcessId,
+                IsWindowVisible,
+            },
         },
     },
-    core::PWSTR,
+    core::{BOOL, PWSTR},
 };
 
-use super::{ActiveWindowData, WindowManager};
+use super::{ActiveWindowData, IconRef, MonitorInfo, WindowManager};
 
 unsafe fn get_window_process_path(window_handle: HANDLE, text: &mut [u16]) -> Result<String> {
     let mut length = text.len() as u32;
@@ -49,18 +61,206 @@ unsafe fn get_window_title(window_handle: HWND, text: &mut [u16]) -> String {
     String::from_utf16_lossy(&text[..len as usize])
 }
 
+unsafe extern "system" fn collect_monitor(
+    monitor: HMONITOR,
+    _: HDC,
+    _: *mut RECT,
+    monitors: LPARAM,
+) -> BOOL {
+    let monitors = unsafe { &mut *(monitors.0 as *mut Vec<HMONITOR>) };
+    monitors.push(monitor);
+    true.into()
+}
+
+fn get_window_monitor(window: HWND) -> Option<MonitorInfo> {
+    let monitor = unsafe { MonitorFromWindow(window, MONITOR_DEFAULTTONULL) };
+    if monitor.is_invalid() {
+        return None;
+    }
+
+    let mut info = MONITORINFOEXW {
+        monitorInfo: MONITORINFO {
+            cbSize: size_of::<MONITORINFOEXW>() as u32,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    if !unsafe { GetMonitorInfoW(monitor, &mut info.monitorInfo) }.as_bool() {
+        error!("Failed to get monitor info");
+        return None;
+    }
+
+    // The index follows the order EnumDisplayMonitors reports the monitors in
+    let mut monitors: Vec<HMONITOR> = Vec::new();
+    let _ = unsafe {
+        EnumDisplayMonitors(
+            None,
+            None,
+            Some(collect_monitor),
+            LPARAM(&mut monitors as *mut Vec<HMONITOR> as isize),
+        )
+    };
+    let index = monitors.iter().position(|m| *m == monitor)?;
+
+    let name_len = info
+        .szDevice
+        .iter()
+        .position(|c| *c == 0)
+        .unwrap_or(info.szDevice.len());
+    let rect = info.monitorInfo.rcMonitor;
+    Some(MonitorInfo {
+        name: String::from_utf16_lossy(&info.szDevice[..name_len]).into(),
+        index: index as u32,
+        resolution: (
+            (rect.right - rect.left) as u32,
+            (rect.bottom - rect.top) as u32,
+        ),
+    })
+}
+
+/// Passed to `collect_window` through `EnumWindows`.
+struct WindowCollector {
+    windows: Vec<HWND>,
+    limit: usize,
+}
+
+unsafe extern "system" fn collect_window(window: HWND, collector: LPARAM) -> BOOL {
+    let collector = unsafe { &mut *(collector.0 as *mut WindowCollector) };
+    if unsafe { IsWindowVisible(window) }.as_bool() {
+        collector.windows.push(window);
+    }
+    // Returning false stops the enumeration
+    (collector.windows.len() < collector.limit).into()
+}
+
+/// Resolves the app name and the publisher from the PE version info of the executable, through
+/// the cache.
+fn get_app_info(
+    desktop_info_cache: &mut crate::simple_cache::SimpleCache<String, WindowsAppInfo>,
+    windows_desktop_info: Option<&WindowsDesktopInfo>,
+    process_path: &str,
+) -> Option<WindowsAppInfo> {
+    if let Some(info) = desktop_info_cache.get(&process_path.to_string()) {
+        return Some(info);
+    }
+    let info = windows_desktop_info.and_then(|info| info.get_extra_info(process_path))?;
+    desktop_info_cache.set(process_path.to_string(), info.clone());
+    Some(info)
+}
+
+/// Reads the title and the process of a window. Windows of processes that can't be opened, like
+/// elevated ones, fail.
+fn get_window_data(
+    window: HWND,
+    desktop_info_cache: &mut crate::simple_cache::SimpleCache<String, WindowsAppInfo>,
+    windows_desktop_info: Option<&WindowsDesktopInfo>,
+) -> Result<ActiveWindowData> {
+    let mut id = 0u32;
+    unsafe { GetWindowThreadProcessId(window, Some(&mut id)) };
+    if id == 0 {
+        return Err(anyhow!("Failed to get the process of the window"));
+    }
+    let process_handle =
+        unsafe { OpenProcess(PROCESS_QUERY_INFORMATION | PROCESS_VM_READ, false, id) }?;
+    let mut text: [u16; 4096] = [0; 4096];
+    let process_path = unsafe { get_window_process_path(process_handle, &mut text) };
+    let title = unsafe { get_window_title(window, &mut text) };
+    unsafe { CloseHandle(process_handle) }?;
+    let process_path = process_path?;
+    let app_info = get_app_info(desktop_info_cache, windows_desktop_info, &process_path);
+
+    Ok(ActiveWindowData {
+        window_title: title.into(),
+        app_identifier: Some(process_path.as_str().into()),
+        resource_class: None,
+        publisher: app_info.as_ref().and_then(|info| info.company_name.clone()),
+        focused_at: None,
+        app_name: app_info.map(|info| info.app_name),
+        process_path: Some(process_path.into()),
+        workspace: None,
+        geometry: None,
+        monitor: get_window_monitor(window),
+        is_fullscreen: None,
+        window_type: None,
+        virtual_desktop: None,
+        icon_name: None,
+        pid: Some(id),
+    })
+}
+
 pub struct WindowsWindowManager {
     idle_timeout: std::time::Duration,
+    window_list_limit: usize,
     desktop_info_cache: crate::simple_cache::SimpleCache<String, WindowsAppInfo>,
-    windows_desktop_info: WindowsDesktopInfo,
+    windows_desktop_info: Option<WindowsDesktopInfo>,
+    /// Icons extracted from the executables, by process path. `None` for executables without one.
+    icon_cache: crate::simple_cache::SimpleCache<String, Option<IconRef>>,
+    idle_notifier: Option<WindowsIdleNotifier>,
 }
 
 impl WindowsWindowManager {
+    #[deprecated(note = "use `from_config`, which returns a `Result` like the other backends")]
     pub fn new(config: WatcherConfig) -> Self {
+        Self::create(config)
+    }
+
+    /// Creating the manager can't fail, the `Result` keeps the signature the same as on the other
+    /// backends.
+    pub fn from_config(config: WatcherConfig) -> Result<Self> {
+        Ok(Self::create(config))
+    }
+
+    /// The visible top-level windows in Z-order, the foreground one first, up to
+    /// `WindowsConfig::window_list_limit`. Windows whose process can't be read are left out.
+    pub fn enumerate_visible_windows(&mut self) -> Result<Vec<ActiveWindowData>> {
+        let mut collector = WindowCollector {
+            windows: Vec::new(),
+            limit: self.window_list_limit,
+        };
+        if collector.limit == 0 {
+            return Ok(Vec::new());
+        }
+        // Fails when the enumeration is stopped at the limit, the collected windows are still valid
+        let _ = unsafe {
+            EnumWindows(
+                Some(collect_window),
+                LPARAM(&mut collector as *mut WindowCollector as isize),
+            )
+        };
+
+        let mut windows = collector.windows;
+        let foreground = unsafe { GetForegroundWindow() };
+        if let Some(position) = windows.iter().position(|window| *window == foreground) {
+            windows[..=position].rotate_right(1);
+        }
+        Ok(windows
+            .into_iter()
+            .filter_map(|window| {
+                get_window_data(
+                    window,
+                    &mut self.desktop_info_cache,
+                    self.windows_desktop_info.as_ref(),
+                )
+                .inspect_err(|e| debug!("Skipping window {window:?}: {e:?}"))
+                .ok()
+            })
+            .collect())
+    }
+
+    fn create(config: WatcherConfig) -> Self {
         Self {
             idle_timeout: config.idle_timeout,
-            desktop_info_cache: crate::simple_cache::SimpleCache::new(config.cache_config),
-            windows_desktop_info: WindowsDesktopInfo::new(),
+            window_list_limit: config.windows_config.window_list_limit,
+            desktop_info_cache: crate::simple_cache::SimpleCache::new(config.cache_config.clone()),
+            icon_cache: crate::simple_cache::SimpleCache::new(config.cache_config),
+            windows_desktop_info: config.resolve_app_metadata.then(WindowsDesktopInfo::new),
+            idle_notifier: if config.windows_event_idle {
+                WindowsIdleNotifier::new()
+                    .inspect_err(|e| error!("Failed to start WinEvent idle notifier {e:?}"))
+                    .ok()
+            } else {
+                None
+            },
         }
     }
 }
@@ -68,9 +268,9 @@ impl WindowsWindowManager {
 #[tracing::instrument]
 fn get_active_windows_data(
     desktop_info_cache: &mut crate::simple_cache::SimpleCache<String, WindowsAppInfo>,
-    windows_desktop_info: &WindowsDesktopInfo,
+    windows_desktop_info: Option<&WindowsDesktopInfo>,
 ) -> Result<ActiveWindowData> {
-    let (process_path, title) = {
+    let (process_path, title, monitor, pid) = {
         let window = unsafe { GetForegroundWindow() };
 
         if window.is_invalid() {
@@ -113,26 +313,26 @@ fn get_active_windows_data(
 
         unsafe { CloseHandle(process_handle) }
             .inspect_err(|e| error!("Failed to close handle {e:?}"))?;
-        (process_path, title)
-    };
-    // Resolve app_name via cache and PE version info
-    let app_name = match desktop_info_cache.get(&process_path) {
-        Some(info) => Some(info.app_name),
-        None => {
-            if let Some(info) = windows_desktop_info.get_extra_info(&process_path) {
-                desktop_info_cache.set(process_path.clone(), info.clone());
-                Some(info.app_name)
-            } else {
-                None
-            }
-        }
+        (process_path, title, get_window_monitor(window), id)
     };
+    let app_info = get_app_info(desktop_info_cache, windows_desktop_info, &process_path);
 
     Ok(ActiveWindowData {
         window_title: title.into(),
         app_identifier: Some(process_path.clone().into()),
+        resource_class: None,
         process_path: Some(process_path.into()),
-        app_name,
+        publisher: app_info.as_ref().and_then(|info| info.company_name.clone()),
+        focused_at: None,
+        app_name: app_info.map(|info| info.app_name),
+        workspace: None,
+        geometry: None,
+        monitor,
+        is_fullscreen: None,
+        window_type: None,
+        virtual_desktop: None,
+        icon_name: None,
+        pid: Some(pid),
     })
 }
 
@@ -148,22 +348,119 @@ pub fn get_idle_time() -> Result<u64> {
     }
 
     let tick_count = unsafe { GetTickCount64() };
-    let duration = tick_count - last.dwTime as u64;
-    if duration > u32::MAX as u64 {
-        Ok(u32::MAX as u64)
-    } else {
-        Ok(duration)
+    Ok(ticks_since(tick_count, last.dwTime))
+}
+
+/// `dwTime` only holds the low 32 bits of the tick count, which wrap every ~49.7 days. The full
+/// tick of the last input is the latest one with these low bits that isn't after `tick_count`.
+fn ticks_since(tick_count: u64, last_input_low: u32) -> u64 {
+    let mut last_input = (tick_count & !u64::from(u32::MAX)) | u64::from(last_input_low);
+    if last_input > tick_count {
+        last_input = last_input.saturating_sub(1 << 32);
     }
+    tick_count.saturating_sub(last_input)
+}
+
+/// The input desktop is switched away from "Default" (to "Winlogon") while the session is locked.
+pub fn is_session_locked() -> Result<bool> {
+    let Ok(desktop) =
+        (unsafe { OpenInputDesktop(DESKTOP_CONTROL_FLAGS(0), false, DESKTOP_READOBJECTS) })
+    else {
+        // The secure desktop can't be opened by a regular process
+        return Ok(true);
+    };
+
+    let mut name = [0u16; 256];
+    let result = unsafe {
+        GetUserObjectInformationW(
+            HANDLE(desktop.0),
+            UOI_NAME,
+            Some(name.as_mut_ptr().cast()),
+            (name.len() * size_of::<u16>()) as u32,
+            None,
+        )
+    };
+    unsafe { CloseDesktop(desktop) }.inspect_err(|e| error!("Failed to close desktop {e:?}"))?;
+    result.inspect_err(|e| error!("Failed to get desktop name {e:?}"))?;
+
+    let len = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+    Ok(!String::from_utf16_lossy(&name[..len]).eq_ignore_ascii_case("Default"))
 }
 
 impl WindowManager for WindowsWindowManager {
+    #[tracing::instrument(name = "windows::get_active", skip(self))]
     fn get_active_window_data(&mut self) -> Result<ActiveWindowData> {
-        get_active_windows_data(&mut self.desktop_info_cache, &self.windows_desktop_info)
-            .inspect_err(|e| error!("Failed to get active window {e:?}"))
+        get_active_windows_data(
+            &mut self.desktop_info_cache,
+            self.windows_desktop_info.as_ref(),
+        )
+        .inspect_err(|e| error!("Failed to get active window {e:?}"))
     }
 
+    #[tracing::instrument(name = "windows::is_idle", skip(self))]
     fn is_idle(&mut self) -> Result<bool> {
-        let idle_time = get_idle_time().inspect_err(|e| error!("Failed to get idle time {e:?}"))?;
+        let idle_time = match &self.idle_notifier {
+            Some(idle_notifier) => idle_notifier.idle_time(),
+            None => get_idle_time().inspect_err(|e| error!("Failed to get idle time {e:?}"))?,
+        };
         Ok(idle_time > self.idle_timeout.as_millis() as u64)
     }
+
+    fn is_screen_locked(&mut self) -> Result<bool> {
+        is_session_locked().inspect_err(|e| error!("Failed to get lock state {e:?}"))
+    }
+
+    fn set_idle_timeout(&mut self, timeout: std::time::Duration) -> Result<()> {
+        self.idle_timeout = timeout;
+        Ok(())
+    }
+
+    fn get_window_list(&mut self) -> Result<Vec<ActiveWindowData>> {
+        self.enumerate_visible_windows()
+    }
+
+    /// The app id is the process path, whose executable the icon is extracted from.
+    fn get_app_icon(&mut self, app_id: &str) -> Result<Option<IconRef>> {
+        if let Some(icon) = self.icon_cache.get(&app_id.to_string()) {
+            return Ok(icon);
+        }
+        let icon = extract_icon(app_id);
+        match &icon {
+            Some(_) => self.icon_cache.set(app_id.to_string(), icon.clone()),
+            None => self.icon_cache.set_negative(app_id.to_string()),
+        }
+        Ok(icon)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WRAP: u64 = 1 << 32;
+
+    #[test]
+    fn ticks_since_input_without_wraparound() {
+        assert_eq!(ticks_since(10_000, 4_000), 6_000);
+        assert_eq!(ticks_since(10_000, 10_000), 0);
+    }
+
+    #[test]
+    fn ticks_since_input_before_the_low_bits_wrapped() {
+        // The input came 100 ticks before the wrap, the tick count is 50 past it
+        let last_input_low = u32::MAX - 99;
+        assert_eq!(ticks_since(WRAP + 50, last_input_low), 150);
+        assert_eq!(ticks_since(3 * WRAP + 50, last_input_low), 150);
+    }
+
+    #[test]
+    fn ticks_since_input_after_several_wraps() {
+        assert_eq!(ticks_since(5 * WRAP + 1_000, 200), 800);
+    }
+
+    #[test]
+    fn ticks_since_input_in_the_first_period_never_underflows() {
+        // Low bits ahead of the tick count with nothing to borrow from
+        assert_eq!(ticks_since(100, 200), 100);
+    }
 }
@@ -1,10 +1,26 @@
 use std::{path::PathBuf, str::FromStr, sync::Arc};
 
 use tracing::warn;
+use windows::{
+    Win32::{
+        Graphics::Gdi::{
+            BI_RGB, BITMAP, BITMAPINFO, BITMAPINFOHEADER, DIB_RGB_COLORS, DeleteObject, GetDC,
+            GetDIBits, GetObjectW, HGDIOBJ, ReleaseDC,
+        },
+        UI::{
+            Shell::ExtractIconExW,
+            WindowsAndMessaging::{DestroyIcon, GetIconInfo, HICON, ICONINFO},
+        },
+    },
+    core::HSTRING,
+};
+
+use crate::IconRef;
 
 #[derive(Clone, Debug)]
 pub struct WindowsAppInfo {
     pub app_name: Arc<str>,
+    pub company_name: Option<Arc<str>>,
 }
 
 #[derive(Debug)]
@@ -50,15 +66,124 @@ impl WindowsDesktopInfo {
         };
 
         let mut product_name: Option<Arc<str>> = None;
+        let mut file_description: Option<Arc<str>> = None;
+        let mut company_name: Option<Arc<str>> = None;
         for lang in info.translation() {
             info.strings(*lang, |key, value| {
-                println!("key: {}, value: {}", key, value);
-                if key == "ProductName" && product_name.is_none() {
-                    product_name = Some(Arc::from(value));
+                // The first translation that has a string wins
+                let field = match key {
+                    "ProductName" => &mut product_name,
+                    "FileDescription" => &mut file_description,
+                    "CompanyName" => &mut company_name,
+                    _ => return,
+                };
+                if field.is_none() && !value.trim().is_empty() {
+                    *field = Some(Arc::from(value.trim()));
                 }
             });
         }
 
-        product_name.map(|app_name| WindowsAppInfo { app_name })
+        // Some executables only describe the file
+        product_name
+            .or(file_description)
+            .map(|app_name| WindowsAppInfo {
+                app_name,
+                company_name,
+            })
+    }
+}
+
+/// Reads the first large icon of the executable as RGBA pixels.
+pub fn extract_icon(process_path: &str) -> Option<IconRef> {
+    let mut icon = HICON::default();
+    let count = unsafe {
+        ExtractIconExW(
+            &HSTRING::from(process_path),
+            0,
+            Some(&raw mut icon),
+            None,
+            1,
+        )
+    };
+    if count == 0 || icon.is_invalid() {
+        return None;
+    }
+    let pixels = unsafe { icon_pixels(icon) };
+    if let Err(e) = unsafe { DestroyIcon(icon) } {
+        warn!("Failed to destroy the icon of {process_path}: {e}");
+    }
+    pixels
+}
+
+unsafe fn icon_pixels(icon: HICON) -> Option<IconRef> {
+    let mut info = ICONINFO::default();
+    unsafe { GetIconInfo(icon, &mut info) }.ok()?;
+    let pixels = unsafe { color_pixels(&info) };
+    unsafe {
+        let _ = DeleteObject(HGDIOBJ(info.hbmColor.0));
+        let _ = DeleteObject(HGDIOBJ(info.hbmMask.0));
+    }
+    pixels
+}
+
+/// The pixels of the color bitmap, converted from BGRA. Icons without an alpha channel are made
+/// opaque.
+unsafe fn color_pixels(info: &ICONINFO) -> Option<IconRef> {
+    if info.hbmColor.is_invalid() {
+        return None;
+    }
+    let mut bitmap = BITMAP::default();
+    let size = unsafe {
+        GetObjectW(
+            HGDIOBJ(info.hbmColor.0),
+            size_of::<BITMAP>() as i32,
+            Some((&mut bitmap as *mut BITMAP).cast()),
+        )
+    };
+    if size == 0 || bitmap.bmWidth <= 0 || bitmap.bmHeight <= 0 {
+        return None;
+    }
+    let (width, height) = (bitmap.bmWidth as u32, bitmap.bmHeight as u32);
+    let mut bitmap_info = BITMAPINFO {
+        bmiHeader: BITMAPINFOHEADER {
+            biSize: size_of::<BITMAPINFOHEADER>() as u32,
+            biWidth: bitmap.bmWidth,
+            // Negative for rows from the top
+            biHeight: -bitmap.bmHeight,
+            biPlanes: 1,
+            biBitCount: 32,
+            biCompression: BI_RGB.0,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut pixels = vec![0u8; width as usize * height as usize * 4];
+    let dc = unsafe { GetDC(None) };
+    let lines = unsafe {
+        GetDIBits(
+            dc,
+            info.hbmColor,
+            0,
+            height,
+            Some(pixels.as_mut_ptr().cast()),
+            &mut bitmap_info,
+            DIB_RGB_COLORS,
+        )
+    };
+    unsafe { ReleaseDC(None, dc) };
+    if lines == 0 {
+        return None;
+    }
+    let has_alpha = pixels.chunks_exact(4).any(|pixel| pixel[3] != 0);
+    for pixel in pixels.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+        if !has_alpha {
+            pixel[3] = u8::MAX;
+        }
     }
+    Some(IconRef::Rgba {
+        width,
+        height,
+        pixels: pixels.into(),
+    })
 }
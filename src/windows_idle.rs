@@ -0,0 +1,164 @@
+//! Push-based idle detection for Windows. WinEvent notifications caused by user input update the
+//! last input tick, so idle checks don't have to poll `GetLastInputInfo`.
+//!
+//! Typing is only noticed through the caret moving. Keyboard input to windows without a system
+//! caret, like games and many terminals, doesn't update the tick.
+
+use std::{
+    cell::RefCell,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+        mpsc,
+    },
+    thread::{self, JoinHandle},
+};
+
+use anyhow::{Result, anyhow};
+use tracing::error;
+use windows::Win32::{
+    Foundation::{HWND, LPARAM, WPARAM},
+    System::{SystemInformation::GetTickCount64, Threading::GetCurrentThreadId},
+    UI::{
+        Accessibility::{HWINEVENTHOOK, SetWinEventHook, UnhookWinEvent},
+        WindowsAndMessaging::{
+            DispatchMessageW, EVENT_OBJECT_LOCATIONCHANGE, EVENT_SYSTEM_FOREGROUND,
+            EVENT_SYSTEM_MINIMIZEEND, GetMessageW, MSG, OBJID_CARET, OBJID_CURSOR, PM_NOREMOVE,
+            PeekMessageW, PostThreadMessageW, TranslateMessage, WINEVENT_OUTOFCONTEXT, WM_QUIT,
+        },
+    },
+};
+
+thread_local! {
+    // WinEvent callbacks don't receive user data, they are called on the thread that set the hook.
+    static LAST_INPUT_TICK: RefCell<Option<Arc<AtomicU64>>> = const { RefCell::new(None) };
+}
+
+unsafe extern "system" fn handle_win_event(
+    _: HWINEVENTHOOK,
+    event: u32,
+    _: HWND,
+    id_object: i32,
+    _: i32,
+    _: u32,
+    _: u32,
+) {
+    // Location changes are only caused by input when the cursor or the caret moves
+    if event == EVENT_OBJECT_LOCATIONCHANGE
+        && id_object != OBJID_CURSOR.0
+        && id_object != OBJID_CARET.0
+    {
+        return;
+    }
+
+    let tick = unsafe { GetTickCount64() };
+    LAST_INPUT_TICK.with_borrow(|last_input_tick| {
+        if let Some(last_input_tick) = last_input_tick {
+            last_input_tick.store(tick, Ordering::Relaxed);
+        }
+    });
+}
+
+fn set_hook(event_min: u32, event_max: u32) -> Result<HWINEVENTHOOK> {
+    let hook = unsafe {
+        SetWinEventHook(
+            event_min,
+            event_max,
+            None,
+            Some(handle_win_event),
+            0,
+            0,
+            WINEVENT_OUTOFCONTEXT,
+        )
+    };
+    if hook.is_invalid() {
+        return Err(anyhow!(
+            "Failed to set WinEvent hook for {event_min}..={event_max}"
+        ));
+    }
+    Ok(hook)
+}
+
+/// Tracks the last input through WinEvent hooks on a separate thread with a message loop.
+pub struct WindowsIdleNotifier {
+    pub last_input_tick: Arc<AtomicU64>,
+    thread_id: u32,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl WindowsIdleNotifier {
+    pub fn new() -> Result<Self> {
+        let last_input_tick = Arc::new(AtomicU64::new(unsafe { GetTickCount64() }));
+        let (thread_id_sender, thread_id_receiver) = mpsc::channel();
+
+        let handle = {
+            let last_input_tick = last_input_tick.clone();
+            thread::spawn(move || {
+                LAST_INPUT_TICK.set(Some(last_input_tick));
+
+                let hooks = match (
+                    set_hook(EVENT_SYSTEM_FOREGROUND, EVENT_SYSTEM_MINIMIZEEND),
+                    set_hook(EVENT_OBJECT_LOCATIONCHANGE, EVENT_OBJECT_LOCATIONCHANGE),
+                ) {
+                    (Ok(system_hook), Ok(location_hook)) => [system_hook, location_hook],
+                    (system_hook, location_hook) => {
+                        for hook in [&system_hook, &location_hook].into_iter().flatten() {
+                            let _ = unsafe { UnhookWinEvent(*hook) };
+                        }
+                        let _ = thread_id_sender.send(system_hook.and(location_hook).map(|_| 0));
+                        return;
+                    }
+                };
+                // The thread only gets a message queue once it calls a message function.
+                // Posting WM_QUIT to a thread without one fails, so `drop` couldn't stop it.
+                let mut message = MSG::default();
+                let _ = unsafe { PeekMessageW(&mut message, None, 0, 0, PM_NOREMOVE) };
+                let _ = thread_id_sender.send(Ok(unsafe { GetCurrentThreadId() }));
+
+                // Out of context hooks are delivered through the message loop of this thread.
+                // GetMessageW returns 0 on WM_QUIT and -1 on errors.
+                while unsafe { GetMessageW(&mut message, None, 0, 0) }.0 > 0 {
+                    unsafe {
+                        let _ = TranslateMessage(&message);
+                        DispatchMessageW(&message);
+                    }
+                }
+
+                for hook in hooks {
+                    if !unsafe { UnhookWinEvent(hook) }.as_bool() {
+                        error!("Failed to remove WinEvent hook");
+                    }
+                }
+            })
+        };
+
+        let thread_id = thread_id_receiver
+            .recv()
+            .map_err(|_| anyhow!("WinEvent thread stopped unexpectedly"))??;
+
+        Ok(Self {
+            last_input_tick,
+            thread_id,
+            handle: Some(handle),
+        })
+    }
+
+    /// Milliseconds since the last input noticed through WinEvents.
+    pub fn idle_time(&self) -> u64 {
+        let tick_count = unsafe { GetTickCount64() };
+        tick_count.saturating_sub(self.last_input_tick.load(Ordering::Relaxed))
+    }
+}
+
+impl Drop for WindowsIdleNotifier {
+    fn drop(&mut self) {
+        if let Err(e) = unsafe { PostThreadMessageW(self.thread_id, WM_QUIT, WPARAM(0), LPARAM(0)) }
+        {
+            error!("Failed to stop WinEvent thread {e:?}");
+            return;
+        }
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
@@ -1,16 +1,21 @@
+use std::time::{Duration, Instant};
+
 use anyhow::Context;
+use tracing::warn;
 use wayland_client::{
     Connection, Dispatch, EventQueue, Proxy, QueueHandle,
     globals::{GlobalList, GlobalListContents, registry_queue_init},
-    protocol::{wl_registry, wl_seat::WlSeat},
+    protocol::{wl_output::WlOutput, wl_registry, wl_seat::WlSeat},
 };
 
+use wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_list_v1::ExtForeignToplevelListV1;
 use wayland_protocols::ext::idle_notify::v1::client::{
     ext_idle_notification_v1::ExtIdleNotificationV1, ext_idle_notifier_v1::ExtIdleNotifierV1,
 };
 use wayland_protocols_plasma::idle::client::{
     org_kde_kwin_idle::OrgKdeKwinIdle, org_kde_kwin_idle_timeout::OrgKdeKwinIdleTimeout,
 };
+use wayland_protocols_plasma::plasma_window_management::client::org_kde_plasma_window_management::OrgKdePlasmaWindowManagement;
 use wayland_protocols_wlr::foreign_toplevel::v1::client::zwlr_foreign_toplevel_manager_v1::ZwlrForeignToplevelManagerV1;
 
 macro_rules! subscribe_state {
@@ -30,7 +35,59 @@ macro_rules! subscribe_state {
 }
 pub(crate) use subscribe_state;
 
+const RECONNECT_INITIAL_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Spaces out attempts to reconnect to the compositor, doubling the delay after each failure.
+pub struct ReconnectBackoff {
+    delay: Duration,
+    next_attempt: Instant,
+}
+
+impl ReconnectBackoff {
+    pub fn new() -> Self {
+        Self {
+            delay: RECONNECT_INITIAL_DELAY,
+            next_attempt: Instant::now(),
+        }
+    }
+
+    /// Time left until the next attempt is allowed.
+    pub fn remaining(&self) -> Duration {
+        self.next_attempt.saturating_duration_since(Instant::now())
+    }
+
+    /// Runs `connect` if the delay has passed. Returns `None` when it's too early or it failed.
+    pub fn attempt<T>(&mut self, connect: impl FnOnce() -> anyhow::Result<T>) -> Option<T> {
+        if !self.remaining().is_zero() {
+            return None;
+        }
+        match connect() {
+            Ok(value) => {
+                self.delay = RECONNECT_INITIAL_DELAY;
+                Some(value)
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to reconnect to the compositor, retrying in {:?}: {e}",
+                    self.delay
+                );
+                self.next_attempt = Instant::now() + self.delay;
+                self.delay = (self.delay * 2).min(RECONNECT_MAX_DELAY);
+                None
+            }
+        }
+    }
+}
+
+impl Default for ReconnectBackoff {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct WlEventConnection<T> {
+    pub connection: Connection,
     pub globals: GlobalList,
     pub event_queue: EventQueue<T>,
     pub queue_handle: QueueHandle<T>,
@@ -53,12 +110,18 @@ where
         let _registry = display.get_registry(&queue_handle, ());
 
         Ok(Self {
+            connection,
             globals,
             event_queue,
             queue_handle,
         })
     }
 
+    /// Whether the compositor is still reachable, checked without dispatching events.
+    pub fn is_connected(&self) -> bool {
+        self.connection.protocol_error().is_none() && self.connection.flush().is_ok()
+    }
+
     pub fn get_foreign_toplevel_manager(&self) -> anyhow::Result<ZwlrForeignToplevelManagerV1>
     where
         T: Dispatch<ZwlrForeignToplevelManagerV1, ()>,
@@ -72,6 +135,76 @@ where
             .map_err(std::convert::Into::into)
     }
 
+    pub fn get_ext_foreign_toplevel_list(&self) -> anyhow::Result<ExtForeignToplevelListV1>
+    where
+        T: Dispatch<ExtForeignToplevelListV1, ()>,
+    {
+        self.globals
+            .bind::<ExtForeignToplevelListV1, T, ()>(
+                &self.queue_handle,
+                1..=ExtForeignToplevelListV1::interface().version,
+                (),
+            )
+            .map_err(std::convert::Into::into)
+    }
+
+    pub fn get_plasma_window_management(&self) -> anyhow::Result<OrgKdePlasmaWindowManagement>
+    where
+        T: Dispatch<OrgKdePlasmaWindowManagement, ()>,
+    {
+        self.globals
+            .bind::<OrgKdePlasmaWindowManagement, T, ()>(
+                &self.queue_handle,
+                1..=OrgKdePlasmaWindowManagement::interface().version,
+                (),
+            )
+            .map_err(std::convert::Into::into)
+    }
+
+    /// Binds every output advertised so far. Version 4 is needed for the `name` event.
+    pub fn get_outputs(&self) -> Vec<WlOutput>
+    where
+        T: Dispatch<WlOutput, ()>,
+    {
+        let interface = WlOutput::interface();
+        self.globals.contents().with_list(|globals| {
+            globals
+                .iter()
+                .filter(|global| global.interface == interface.name)
+                .map(|global| {
+                    self.globals.registry().bind::<WlOutput, _, T>(
+                        global.name,
+                        global.version.min(interface.version),
+                        &self.queue_handle,
+                        (),
+                    )
+                })
+                .collect()
+        })
+    }
+
+    /// Binds every seat advertised so far. Version 2 is needed for the `name` event.
+    pub fn get_seats(&self) -> Vec<WlSeat>
+    where
+        T: Dispatch<WlSeat, ()>,
+    {
+        let interface = WlSeat::interface();
+        self.globals.contents().with_list(|globals| {
+            globals
+                .iter()
+                .filter(|global| global.interface == interface.name)
+                .map(|global| {
+                    self.globals.registry().bind::<WlSeat, _, T>(
+                        global.name,
+                        global.version.min(interface.version),
+                        &self.queue_handle,
+                        (),
+                    )
+                })
+                .collect()
+        })
+    }
+
     pub fn get_kwin_idle(&self) -> anyhow::Result<OrgKdeKwinIdle>
     where
         T: Dispatch<OrgKdeKwinIdle, ()>,
@@ -98,31 +231,27 @@ where
             .map_err(std::convert::Into::into)
     }
 
-    pub fn get_ext_idle_notification(&self, timeout: u32) -> anyhow::Result<ExtIdleNotificationV1>
+    pub fn get_ext_idle_notification(
+        &self,
+        timeout: u32,
+        seat: &WlSeat,
+    ) -> anyhow::Result<ExtIdleNotificationV1>
     where
-        T: Dispatch<ExtIdleNotifierV1, ()>
-            + Dispatch<WlSeat, ()>
-            + Dispatch<ExtIdleNotificationV1, ()>,
+        T: Dispatch<ExtIdleNotifierV1, ()> + Dispatch<ExtIdleNotificationV1, ()>,
     {
-        let seat: WlSeat =
-            self.globals
-                .bind(&self.queue_handle, 1..=WlSeat::interface().version, ())?;
-
         let idle = self.get_ext_idle()?;
-        Ok(idle.get_idle_notification(timeout, &seat, &self.queue_handle, ()))
+        Ok(idle.get_idle_notification(timeout, seat, &self.queue_handle, ()))
     }
 
-    pub fn get_kwin_idle_timeout(&self, timeout: u32) -> anyhow::Result<OrgKdeKwinIdleTimeout>
+    pub fn get_kwin_idle_timeout(
+        &self,
+        timeout: u32,
+        seat: &WlSeat,
+    ) -> anyhow::Result<OrgKdeKwinIdleTimeout>
     where
-        T: Dispatch<OrgKdeKwinIdle, ()>
-            + Dispatch<OrgKdeKwinIdleTimeout, ()>
-            + Dispatch<WlSeat, ()>,
+        T: Dispatch<OrgKdeKwinIdle, ()> + Dispatch<OrgKdeKwinIdleTimeout, ()>,
     {
-        let seat: WlSeat =
-            self.globals
-                .bind(&self.queue_handle, 1..=WlSeat::interface().version, ())?;
-
         let idle = self.get_kwin_idle()?;
-        Ok(idle.get_idle_timeout(&seat, timeout, &self.queue_handle, ()))
+        Ok(idle.get_idle_timeout(seat, timeout, &self.queue_handle, ()))
     }
 }
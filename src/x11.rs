@@ -1,18 +1,41 @@
 //! Contains logic for extracting records through x11. The implementation uses xcb for communication
 //! with the server.
+//!
+//! The app is identified by the instance name in WM_CLASS, which is matched against desktop entries.
+//! The monitor of the active window is found through RandR 1.5, and is `None` without it.
+//! Idle time requires the MIT-SCREEN-SAVER extension, or the SYNC extension with the `IDLETIME`
+//! system counter when `X11IdleSource::Sync` is selected, the screensaver readings look stuck or
+//! MIT-SCREEN-SAVER is missing. With SYNC, `is_idle` is answered by an alarm on the counter.
+//! Requests of a missing extension would close the connection, so every extension is optional
+//! and checked before use.
 
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::{Result, anyhow};
+use chrono::Utc;
 use sysinfo::Pid;
-use tracing::{error, instrument};
+use tracing::{error, instrument, warn};
 use xcb::{
-    Connection,
-    screensaver::{QueryInfo, QueryInfoReply},
+    Connection, Extension, Xid, randr,
+    screensaver::{QueryInfo, QueryInfoReply, State},
+    sync::{self, Counter},
     x::{self, ATOM_ANY, Atom, Drawable, GetProperty, InternAtom, Window},
 };
 
-use super::{ActiveWindowData, WindowManager, config::WatcherConfig};
+use super::{
+    ActiveWindowData, IconRef, MonitorInfo, NoActiveWindow, Sample, WatcherError, WindowManager,
+    config::{IdleSensitivity, WatcherConfig, X11IdleSource},
+    linux_desktop::{DesktopInfo, DesktopInfoResolver, SharedDesktopInfo, lock_desktop_info},
+};
+
+/// Amount of failed reconnections in a row after which the manager stops reconnecting.
+const MAX_RECONNECT_FAILURES: u32 = 10;
+/// Delay after the first failed reconnection. Doubles with every further failure up to
+/// `RECONNECT_MAX_DELAY`.
+const RECONNECT_INITIAL_DELAY: Duration = Duration::from_millis(500);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+/// Amount of identical XScreenSaver readings in a row after which XSync is used instead.
+const CONSTANT_IDLE_READINGS_LIMIT: u32 = 5;
 
 fn get_pid_atom(conn: &Connection) -> Result<Atom> {
     let reply = conn.wait_for_reply(conn.send_request(&InternAtom {
@@ -58,7 +81,13 @@ fn get_active_window_atom(conn: &Connection) -> Result<Atom> {
     Ok(active_window_atom.atom())
 }
 
-fn get_active_window(conn: &Connection, root: &Window, active_window_atom: Atom) -> Result<Window> {
+/// `None` if the window manager doesn't set `_NET_ACTIVE_WINDOW` on the root or no window is
+/// focused.
+fn get_active_window(
+    conn: &Connection,
+    root: &Window,
+    active_window_atom: Atom,
+) -> Result<Option<Window>> {
     let result = conn.wait_for_reply(conn.send_request(&GetProperty {
         delete: false,
         window: *root,
@@ -67,7 +96,11 @@ fn get_active_window(conn: &Connection, root: &Window, active_window_atom: Atom)
         long_offset: 0,
         long_length: 1,
     }))?;
-    Ok(result.value::<Window>()[0])
+    Ok(result
+        .value::<Window>()
+        .first()
+        .copied()
+        .filter(|window| !window.is_none()))
 }
 
 fn get_net_wm_name_atom(conn: &Connection) -> Result<Atom> {
@@ -78,18 +111,192 @@ fn get_net_wm_name_atom(conn: &Connection) -> Result<Atom> {
     Ok(response.atom())
 }
 
-pub fn get_name(conn: &Connection, window: Window, wm_name_atom: Atom) -> Result<String> {
-    let wm_name = conn.wait_for_reply(conn.send_request(&x::GetProperty {
+fn get_net_wm_icon_name_atom(conn: &Connection) -> Result<Atom> {
+    let response = conn.wait_for_reply(conn.send_request(&InternAtom {
+        only_if_exists: false,
+        name: b"_NET_WM_ICON_NAME",
+    }))?;
+    Ok(response.atom())
+}
+
+/// The `_NET_WM_NAME` of the window. Legacy apps that leave it empty are named by `WM_NAME`, or
+/// by `_NET_WM_ICON_NAME` as a last resort.
+pub fn get_name(
+    conn: &Connection,
+    window: Window,
+    wm_name_atom: Atom,
+    icon_name_atom: Atom,
+) -> Result<String> {
+    let title = get_utf8_property(conn, window, wm_name_atom)?;
+    if !title.is_empty() {
+        return Ok(title);
+    }
+    if let Some(title) = get_legacy_name(conn, window)?.filter(|title| !title.is_empty()) {
+        return Ok(title);
+    }
+    get_utf8_property(conn, window, icon_name_atom)
+}
+
+fn get_utf8_property(conn: &Connection, window: Window, property: Atom) -> Result<String> {
+    let reply = conn.wait_for_reply(conn.send_request(&x::GetProperty {
         delete: false,
         window,
-        property: wm_name_atom,
+        property,
         r#type: x::ATOM_ANY,
         long_offset: 0,
         long_length: 1024,
     }))?;
-    let title = String::from_utf8(wm_name.value().to_vec())
-        .expect("The WM_NAME property is not valid UTF-8");
-    Ok(title)
+    // Clients don't always set valid UTF-8, a broken title shouldn't take the watcher down
+    Ok(String::from_utf8_lossy(reply.value()).into_owned())
+}
+
+/// Reads `WM_NAME` as a Latin-1 `STRING`. `None` if it's missing or has another type, like
+/// `COMPOUND_TEXT`.
+pub fn get_legacy_name(conn: &Connection, window: Window) -> Result<Option<String>> {
+    let reply = conn.wait_for_reply(conn.send_request(&GetProperty {
+        delete: false,
+        window,
+        property: x::ATOM_WM_NAME,
+        r#type: x::ATOM_STRING,
+        long_offset: 0,
+        long_length: 1024,
+    }))?;
+    if reply.r#type() != x::ATOM_STRING {
+        return Ok(None);
+    }
+    Ok(Some(
+        reply
+            .value::<u8>()
+            .iter()
+            .map(|&byte| byte as char)
+            .collect(),
+    ))
+}
+
+fn get_window_type_atom(conn: &Connection) -> Result<Atom> {
+    let response = conn.wait_for_reply(conn.send_request(&InternAtom {
+        only_if_exists: false,
+        name: b"_NET_WM_WINDOW_TYPE",
+    }))?;
+    Ok(response.atom())
+}
+
+/// The name of the first, most preferred `_NET_WM_WINDOW_TYPE` of the window, like
+/// `_NET_WM_WINDOW_TYPE_NORMAL`. `None` if the window has none.
+fn get_window_type(
+    conn: &Connection,
+    window: Window,
+    window_type_atom: Atom,
+) -> Result<Option<String>> {
+    let reply = conn.wait_for_reply(conn.send_request(&GetProperty {
+        delete: false,
+        window,
+        property: window_type_atom,
+        r#type: x::ATOM_ATOM,
+        long_offset: 0,
+        long_length: 1,
+    }))?;
+    let Some(&window_type) = reply.value::<Atom>().first() else {
+        return Ok(None);
+    };
+    let name = conn.wait_for_reply(conn.send_request(&x::GetAtomName { atom: window_type }))?;
+    Ok(Some(name.name().to_utf8().into_owned()))
+}
+
+fn get_current_desktop_atom(conn: &Connection) -> Result<Atom> {
+    let response = conn.wait_for_reply(conn.send_request(&InternAtom {
+        only_if_exists: false,
+        name: b"_NET_CURRENT_DESKTOP",
+    }))?;
+    Ok(response.atom())
+}
+
+/// The `_NET_CURRENT_DESKTOP` of the root. `None` if the window manager doesn't set it.
+fn get_current_desktop(
+    conn: &Connection,
+    root: Window,
+    current_desktop_atom: Atom,
+) -> Result<Option<u32>> {
+    let reply = conn.wait_for_reply(conn.send_request(&GetProperty {
+        delete: false,
+        window: root,
+        property: current_desktop_atom,
+        r#type: x::ATOM_CARDINAL,
+        long_offset: 0,
+        long_length: 1,
+    }))?;
+    Ok(reply.value::<u32>().first().copied())
+}
+
+/// Reads WM_CLASS, which holds the instance and the class names separated by a null byte.
+fn get_wm_class(conn: &Connection, window: Window) -> Result<Option<(String, String)>> {
+    let reply = conn.wait_for_reply(conn.send_request(&GetProperty {
+        delete: false,
+        window,
+        property: x::ATOM_WM_CLASS,
+        r#type: x::ATOM_STRING,
+        long_offset: 0,
+        long_length: 1024,
+    }))?;
+    let mut parts = reply
+        .value::<u8>()
+        .split(|c| *c == 0)
+        .map(|part| String::from_utf8_lossy(part).into_owned());
+    match (parts.next(), parts.next()) {
+        (Some(instance), Some(class)) => Ok(Some((instance, class))),
+        _ => Ok(None),
+    }
+}
+
+fn has_extension(conn: &Connection, extension: Extension) -> bool {
+    conn.active_extensions().any(|active| active == extension)
+}
+
+/// Whether the server implements RandR 1.5, which added monitors.
+fn has_randr_monitors(conn: &Connection) -> Result<bool> {
+    if !has_extension(conn, Extension::RandR) {
+        return Ok(false);
+    }
+    let version = conn.wait_for_reply(conn.send_request(&randr::QueryVersion {
+        major_version: 1,
+        minor_version: 5,
+    }))?;
+    Ok((version.major_version(), version.minor_version()) >= (1, 5))
+}
+
+fn get_idle_counter(conn: &Connection) -> Result<Counter> {
+    if !has_extension(conn, Extension::Sync) {
+        return Err(anyhow!("SYNC extension is not available"));
+    }
+    conn.wait_for_reply(conn.send_request(&sync::Initialize {
+        desired_major_version: 3,
+        desired_minor_version: 1,
+    }))?;
+    let reply = conn.wait_for_reply(conn.send_request(&sync::ListSystemCounters {}))?;
+    reply
+        .counters()
+        .find(|counter| counter.name().to_utf8() == "IDLETIME")
+        .map(|counter| counter.counter())
+        .ok_or_else(|| anyhow!("IDLETIME counter is not found"))
+}
+
+fn int64_value(value: sync::Int64) -> i64 {
+    (i64::from(value.hi) << 32) | i64::from(value.lo)
+}
+
+fn to_int64(value: i64) -> sync::Int64 {
+    sync::Int64 {
+        hi: (value >> 32) as i32,
+        lo: value as u32,
+    }
+}
+
+/// XSync alarm on `IDLETIME` that fires when the idle time crosses `threshold` either way.
+struct IdleAlarm {
+    alarm: sync::Alarm,
+    threshold: Duration,
+    /// Whether the user was idle when the alarm last fired, so it now waits for input.
+    is_idle: bool,
 }
 
 struct WindowData {
@@ -97,50 +304,324 @@ struct WindowData {
     preferred_screen: usize,
     active_window_atom: Atom,
     window_name_atom: Atom,
+    icon_name_atom: Atom,
+    window_type_atom: Atom,
+    current_desktop_atom: Atom,
     pid_atom: Atom,
+    has_screensaver: bool,
+    randr_monitors: bool,
+    idle_counter: Option<Counter>,
+    /// Created on the first `alarm_idle`.
+    idle_alarm: Option<IdleAlarm>,
 }
 
 impl WindowData {
-    #[instrument(skip(self))]
-    fn get_active_inner(&self) -> Result<ActiveWindowData> {
+    #[instrument(skip_all)]
+    fn get_active_inner(&self, desktop_info: &SharedDesktopInfo) -> Result<ActiveWindowData> {
         let setup = self.connection.get_setup();
-
-        // Currently the application only supports 1 x11 screen.
         let default_window = setup.roots().nth(self.preferred_screen).unwrap().root();
 
-        let active_window =
-            get_active_window(&self.connection, &default_window, self.active_window_atom)?;
-        let window_name = get_name(&self.connection, active_window, self.window_name_atom)?;
-        let process = get_pid(&self.connection, active_window, self.pid_atom)?
-            .ok_or_else(|| anyhow!("Failed to get pid: pid is None"))?;
-        let process_name = get_process_name(process)?
-            .ok_or_else(|| anyhow!("Failed to get process name: process name is None"))?;
+        self.get_active_on_root(default_window, desktop_info)?
+            .ok_or_else(|| NoActiveWindow.into())
+    }
+
+    /// The focused window of every screen that has one, with the index of the screen.
+    fn get_active_per_screen(
+        &self,
+        desktop_info: &SharedDesktopInfo,
+    ) -> Result<Vec<(usize, ActiveWindowData)>> {
+        let mut windows = Vec::new();
+        for (index, screen) in self.connection.get_setup().roots().enumerate() {
+            if let Some(data) = self.get_active_on_root(screen.root(), desktop_info)? {
+                windows.push((index, data));
+            }
+        }
+        Ok(windows)
+    }
 
-        Ok(ActiveWindowData {
+    fn get_active_on_root(
+        &self,
+        default_window: Window,
+        desktop_info: &SharedDesktopInfo,
+    ) -> Result<Option<ActiveWindowData>> {
+        let Some(active_window) =
+            get_active_window(&self.connection, &default_window, self.active_window_atom)?
+        else {
+            return Ok(None);
+        };
+        let window_name = get_name(
+            &self.connection,
+            active_window,
+            self.window_name_atom,
+            self.icon_name_atom,
+        )?;
+        let wm_class = get_wm_class(&self.connection, active_window)
+            .inspect_err(|e| warn!("Failed to get WM_CLASS of the active window {e:?}"))
+            .ok()
+            .flatten();
+        // The instance name is tried first, like the resource name KWin reports for the window
+        let desktop_info = wm_class.as_ref().and_then(|(instance, class)| {
+            let mut desktop_info = lock_desktop_info(desktop_info);
+            desktop_info
+                .get(instance)
+                .or_else(|| desktop_info.get(class))
+        });
+        let pid = get_pid(&self.connection, active_window, self.pid_atom)
+            .inspect_err(|e| warn!("Failed to get _NET_WM_PID of the active window {e:?}"))
+            .ok()
+            .flatten();
+        let process_name = match &desktop_info {
+            Some(info) => info.process_path.clone(),
+            None => {
+                let process = pid.ok_or_else(|| anyhow!("Failed to get pid: pid is None"))?;
+                get_process_name(process)?
+                    .ok_or_else(|| anyhow!("Failed to get process name: process name is None"))?
+                    .into()
+            }
+        };
+        let monitor = self
+            .get_monitor(default_window, active_window)
+            .inspect_err(|e| warn!("Failed to get the monitor of the active window {e:?}"))
+            .ok()
+            .flatten();
+        let window_type = get_window_type(&self.connection, active_window, self.window_type_atom)
+            .inspect_err(|e| warn!("Failed to get the type of the active window {e:?}"))
+            .ok()
+            .flatten();
+        let virtual_desktop =
+            get_current_desktop(&self.connection, default_window, self.current_desktop_atom)
+                .inspect_err(|e| warn!("Failed to get the current desktop {e:?}"))
+                .ok()
+                .flatten();
+
+        Ok(Some(ActiveWindowData {
             window_title: window_name.into(),
-            process_path: Some(process_name.into()),
-            app_identifier: None,
-            app_name: None,
-        })
+            process_path: Some(process_name),
+            resource_class: wm_class.as_ref().map(|(_, class)| class.as_str().into()),
+            app_identifier: wm_class.map(|(instance, _)| instance.into()),
+            icon_name: desktop_info.as_ref().and_then(DesktopInfo::icon_name),
+            publisher: None,
+            focused_at: None,
+            app_name: desktop_info.map(|info| info.app_name),
+            workspace: None,
+            geometry: None,
+            monitor,
+            is_fullscreen: None,
+            pid,
+            window_type: window_type.map(Into::into),
+            virtual_desktop,
+        }))
+    }
+
+    /// Finds the RandR monitor containing the center of the window. `None` without RandR 1.5.
+    fn get_monitor(&self, root: Window, window: Window) -> Result<Option<MonitorInfo>> {
+        if !self.randr_monitors {
+            return Ok(None);
+        }
+        let geometry = self.connection.send_request(&x::GetGeometry {
+            drawable: Drawable::Window(window),
+        });
+        let position = self.connection.send_request(&x::TranslateCoordinates {
+            src_window: window,
+            dst_window: root,
+            src_x: 0,
+            src_y: 0,
+        });
+        let monitors = self.connection.send_request(&randr::GetMonitors {
+            window: root,
+            get_active: true,
+        });
+        let geometry = self.connection.wait_for_reply(geometry)?;
+        let position = self.connection.wait_for_reply(position)?;
+        let monitors = self.connection.wait_for_reply(monitors)?;
+
+        let center_x = i32::from(position.dst_x()) + i32::from(geometry.width()) / 2;
+        let center_y = i32::from(position.dst_y()) + i32::from(geometry.height()) / 2;
+        let Some((index, monitor)) = monitors.monitors().enumerate().find(|(_, monitor)| {
+            let x = i32::from(monitor.x());
+            let y = i32::from(monitor.y());
+            (x..x + i32::from(monitor.width())).contains(&center_x)
+                && (y..y + i32::from(monitor.height())).contains(&center_y)
+        }) else {
+            return Ok(None);
+        };
+
+        let name = self
+            .connection
+            .wait_for_reply(self.connection.send_request(&x::GetAtomName {
+                atom: monitor.name(),
+            }))?;
+        Ok(Some(MonitorInfo {
+            name: name.name().to_utf8().as_ref().into(),
+            index: index as u32,
+            resolution: (u32::from(monitor.width()), u32::from(monitor.height())),
+        }))
+    }
+
+    fn get_screensaver_info(&self) -> Result<QueryInfoReply> {
+        if !self.has_screensaver {
+            return Err(anyhow!("MIT-SCREEN-SAVER extension is not available"));
+        }
+        let setup = self.connection.get_setup();
+        let root = setup.roots().nth(self.preferred_screen).unwrap().root();
+        let info = self.connection.send_request(&QueryInfo {
+            drawable: Drawable::Window(root),
+        });
+        Ok(self.connection.wait_for_reply(info)?)
+    }
+
+    fn get_sync_idle_time(&self) -> Result<u64> {
+        let counter = self
+            .idle_counter
+            .ok_or_else(|| anyhow!("IDLETIME counter is not available"))?;
+        let reply = self.connection.wait_for_reply(
+            self.connection
+                .send_request(&sync::QueryCounter { counter }),
+        )?;
+        Ok(int64_value(reply.counter_value()).max(0) as u64)
+    }
+
+    /// Whether the user has been idle for `threshold`, as last reported by the idle alarm. The
+    /// alarm is rearmed every time it fires, so the counter is only queried when it's created or
+    /// the threshold changes.
+    fn alarm_idle(&mut self, threshold: Duration) -> Result<bool> {
+        let counter = self
+            .idle_counter
+            .ok_or_else(|| anyhow!("IDLETIME counter is not available"))?;
+        if self
+            .idle_alarm
+            .as_ref()
+            .is_none_or(|alarm| alarm.threshold != threshold)
+        {
+            let alarm = match self.idle_alarm.take() {
+                Some(idle_alarm) => idle_alarm.alarm,
+                None => {
+                    let alarm = self.connection.generate_id();
+                    self.connection.send_and_check_request(&sync::CreateAlarm {
+                        id: alarm,
+                        value_list: &[sync::Ca::Counter(counter), sync::Ca::Events(1)],
+                    })?;
+                    alarm
+                }
+            };
+            let is_idle = Duration::from_millis(self.get_sync_idle_time()?) >= threshold;
+            self.arm_idle_alarm(alarm, threshold, is_idle)?;
+            self.idle_alarm = Some(IdleAlarm {
+                alarm,
+                threshold,
+                is_idle,
+            });
+        }
+
+        let threshold_ms = threshold.as_millis() as i64;
+        while let Some(event) = self.connection.poll_for_event()? {
+            let xcb::Event::Sync(sync::Event::AlarmNotify(event)) = event else {
+                continue;
+            };
+            let Some(idle_alarm) = &self.idle_alarm else {
+                continue;
+            };
+            if event.alarm() != idle_alarm.alarm {
+                continue;
+            }
+            let (alarm, is_idle) = (
+                idle_alarm.alarm,
+                int64_value(event.counter_value()) >= threshold_ms,
+            );
+            self.arm_idle_alarm(alarm, threshold, is_idle)?;
+            if let Some(idle_alarm) = &mut self.idle_alarm {
+                idle_alarm.is_idle = is_idle;
+            }
+        }
+        Ok(self
+            .idle_alarm
+            .as_ref()
+            .is_some_and(|idle_alarm| idle_alarm.is_idle))
+    }
+
+    /// While the user is active, the alarm waits for the idle time to reach `threshold`.
+    /// While they're idle, it waits for input to drop the idle time below it.
+    fn arm_idle_alarm(&self, alarm: sync::Alarm, threshold: Duration, is_idle: bool) -> Result<()> {
+        let threshold_ms = threshold.as_millis() as i64;
+        let (value, test) = if is_idle {
+            (threshold_ms - 1, sync::Testtype::NegativeComparison)
+        } else {
+            (threshold_ms, sync::Testtype::PositiveComparison)
+        };
+        self.connection.send_and_check_request(&sync::ChangeAlarm {
+            id: alarm,
+            value_list: &[
+                sync::Ca::ValueType(sync::Valuetype::Absolute),
+                sync::Ca::Value(to_int64(value)),
+                sync::Ca::TestType(test),
+                sync::Ca::Events(1),
+            ],
+        })?;
+        Ok(())
     }
 }
 
 pub struct LinuxWindowManager {
     data: Option<WindowData>,
     idle_timeout: Duration,
+    idle_sensitivity: IdleSensitivity,
+    idle_source: X11IdleSource,
+    last_screensaver_idle: Option<u32>,
+    constant_idle_readings: u32,
+    /// Failed reconnections since the last successful one.
+    reconnect_count: u32,
+    /// Reconnecting isn't attempted before this.
+    next_reconnect: Instant,
+    desktop_info: SharedDesktopInfo,
 }
 
 impl LinuxWindowManager {
+    pub fn from_config(config: WatcherConfig) -> Result<Self> {
+        let desktop_info = DesktopInfoResolver::shared(&config);
+        Self::with_desktop_info(config, desktop_info)
+    }
+
+    #[deprecated(note = "use `from_config`, which every backend has")]
     pub fn new(config: WatcherConfig) -> Result<Self> {
+        Self::from_config(config)
+    }
+
+    /// Like `from_config`, but looks the apps up in `desktop_info`, which other watchers may share.
+    pub fn with_desktop_info(
+        config: WatcherConfig,
+        desktop_info: SharedDesktopInfo,
+    ) -> Result<Self> {
         Ok(Self {
             data: None,
             idle_timeout: config.idle_timeout,
+            idle_sensitivity: config.idle_sensitivity,
+            idle_source: config.x11_idle_source,
+            last_screensaver_idle: None,
+            constant_idle_readings: 0,
+            reconnect_count: 0,
+            next_reconnect: Instant::now(),
+            desktop_info,
         })
     }
 
+    /// The focused window of every X screen, with the index of the screen. Screens without a
+    /// focused window are left out. `get_active_window_data` only reports the preferred screen.
+    pub fn active_windows_per_screen(&mut self) -> Result<Vec<(usize, ActiveWindowData)>> {
+        let data = self
+            .try_get_data()
+            .inspect_err(|e| error!("Failed getting connection {e:?}"))?;
+        let result = data.get_active_per_screen(&self.desktop_info);
+        self.data = Some(data);
+        result
+    }
+
     fn try_reload_manager(&mut self) -> Result<WindowData> {
-        let (connection, preferred_screen) = xcb::Connection::connect(None)
-            .inspect_err(|e| error!("Failed creating connection {e:?}"))?;
+        let (connection, preferred_screen) = xcb::Connection::connect_with_extensions(
+            None,
+            &[],
+            &[Extension::Sync, Extension::RandR, Extension::ScreenSaver],
+        )
+        .inspect_err(|e| error!("Failed creating connection {e:?}"))?;
         if preferred_screen < 0 {
             return Err(anyhow!(
                 "Preferred screen is less than 0 {preferred_screen}"
@@ -151,17 +632,83 @@ impl LinuxWindowManager {
             .inspect_err(|e| error!("Failed getting active window atom {e:?}"))?;
         let name_atom = get_net_wm_name_atom(&connection)
             .inspect_err(|e| error!("Failed getting wm name atom {e:?}"))?;
+        let icon_name_atom = get_net_wm_icon_name_atom(&connection)
+            .inspect_err(|e| error!("Failed getting wm icon name atom {e:?}"))?;
+        let window_type_atom = get_window_type_atom(&connection)
+            .inspect_err(|e| error!("Failed getting window type atom {e:?}"))?;
+        let current_desktop_atom = get_current_desktop_atom(&connection)
+            .inspect_err(|e| error!("Failed getting current desktop atom {e:?}"))?;
         let pid_atom = get_pid_atom(&connection)
             .inspect_err(|e| error!("Failed getting pid of an atom {e:?}"))?;
+        let has_screensaver = has_extension(&connection, Extension::ScreenSaver);
+        if !has_screensaver {
+            warn!("MIT-SCREEN-SAVER extension is not available");
+        }
+        let randr_monitors = has_randr_monitors(&connection)
+            .inspect_err(|e| warn!("Failed to query the RandR version {e:?}"))
+            .unwrap_or(false);
+        let idle_counter = get_idle_counter(&connection)
+            .inspect_err(|e| warn!("XSync idle time is unavailable {e:?}"))
+            .ok();
         Ok(WindowData {
             connection,
             preferred_screen,
             active_window_atom,
             window_name_atom: name_atom,
+            icon_name_atom,
+            window_type_atom,
+            current_desktop_atom,
             pid_atom,
+            has_screensaver,
+            randr_monitors,
+            idle_counter,
+            idle_alarm: None,
         })
     }
 
+    /// Whether XSync is the idle source. Switches to it for good when MIT-SCREEN-SAVER is
+    /// missing.
+    fn uses_sync(&mut self, data: &WindowData) -> bool {
+        if !data.has_screensaver && data.idle_counter.is_some() {
+            self.idle_source = X11IdleSource::Sync;
+        }
+        self.idle_source == X11IdleSource::Sync
+    }
+
+    fn get_idle_time(&mut self, data: &WindowData) -> Result<u64> {
+        if self.uses_sync(data) {
+            return data.get_sync_idle_time();
+        }
+
+        let idle = data.get_screensaver_info()?.ms_since_user_input();
+        if self.last_screensaver_idle.replace(idle) == Some(idle) {
+            self.constant_idle_readings += 1;
+        } else {
+            self.constant_idle_readings = 0;
+        }
+        if self.constant_idle_readings >= CONSTANT_IDLE_READINGS_LIMIT
+            && data.idle_counter.is_some()
+        {
+            warn!("XScreenSaver keeps reporting {idle}ms of idle time, switching to XSync");
+            self.idle_source = X11IdleSource::Sync;
+            return data.get_sync_idle_time();
+        }
+        Ok(u64::from(idle))
+    }
+
+    /// Amount of failed reconnections in a row. Once it reaches 10, the manager returns
+    /// `WatcherError::Fatal` without trying to reconnect until the count is reset.
+    /// Between failures the manager waits from half a second up to 30 seconds, doubling each
+    /// time, and returns `WatcherError::Transient` meanwhile.
+    pub fn reconnect_count(&self) -> u32 {
+        self.reconnect_count
+    }
+
+    pub fn reset_reconnect_count(&mut self) {
+        self.reconnect_count = 0;
+        self.next_reconnect = Instant::now();
+    }
+
     fn try_get_data(&mut self) -> Result<WindowData> {
         match self
             .data
@@ -169,42 +716,133 @@ impl LinuxWindowManager {
             .filter(|v| v.connection.has_error().is_ok())
         {
             Some(data) => Ok(data),
-            None => match self.try_reload_manager() {
-                Ok(data) => Ok(data),
-                Err(e) => {
-                    error!("Failed to get xcb connection {e:?}");
-                    Err(e)
+            None => {
+                if self.reconnect_count >= MAX_RECONNECT_FAILURES {
+                    return Err(WatcherError::Fatal(format!(
+                        "Failed to connect to the X server {} times in a row",
+                        self.reconnect_count
+                    ))
+                    .into());
+                }
+                let now = Instant::now();
+                if now < self.next_reconnect {
+                    return Err(WatcherError::Transient(format!(
+                        "Waiting {:?} before reconnecting to the X server",
+                        self.next_reconnect - now
+                    ))
+                    .into());
                 }
-            },
+                match self.try_reload_manager() {
+                    Ok(data) => {
+                        self.reconnect_count = 0;
+                        Ok(data)
+                    }
+                    Err(e) => {
+                        self.reconnect_count += 1;
+                        let delay = RECONNECT_INITIAL_DELAY
+                            .saturating_mul(1 << (self.reconnect_count - 1).min(16));
+                        self.next_reconnect = now + delay.min(RECONNECT_MAX_DELAY);
+                        error!("Failed to get xcb connection {e:?}");
+                        Err(e)
+                    }
+                }
+            }
         }
     }
 }
 
 impl WindowManager for LinuxWindowManager {
-    #[instrument(skip(self))]
+    #[instrument(name = "x11::get_active", skip(self))]
     fn get_active_window_data(&mut self) -> Result<ActiveWindowData> {
         let data = self
             .try_get_data()
             .inspect_err(|e| error!("Failed getting connection {e:?}"))?;
-        let result = data.get_active_inner();
+        let result = data.get_active_inner(&self.desktop_info);
         self.data = Some(data);
         result
     }
 
-    #[instrument(skip(self))]
+    /// With XSync as the source, the state comes from the idle alarm.
+    #[instrument(name = "x11::is_idle", skip(self))]
     fn is_idle(&mut self) -> Result<bool> {
+        let mut data = self
+            .try_get_data()
+            .inspect_err(|e| error!("Failed getting connection {e:?}"))?;
+        let idle = if self.uses_sync(&data) {
+            data.alarm_idle(self.idle_sensitivity.threshold(self.idle_timeout))
+        } else {
+            self.get_idle_time(&data).map(|since_input| {
+                self.idle_sensitivity
+                    .is_idle(Duration::from_millis(since_input), self.idle_timeout)
+            })
+        };
+        self.data = Some(data);
+        idle.inspect_err(|e| error!("Failed getting idle {e}"))
+    }
+
+    fn idle_duration(&mut self) -> Result<Duration> {
         let data = self
             .try_get_data()
             .inspect_err(|e| error!("Failed getting connection {e:?}"))?;
-        let w = data.connection.get_setup();
-        let wnd = w.roots().nth(data.preferred_screen).unwrap().root();
-        let idle = data.connection.send_request(&QueryInfo {
-            drawable: Drawable::Window(wnd),
-        });
-        let reply: QueryInfoReply = data
-            .connection
-            .wait_for_reply(idle)
-            .inspect_err(|e| error!("Failed getting idle {e}"))?;
-        Ok(reply.ms_since_user_input() as u128 > self.idle_timeout.as_millis())
+        let since_input = self.get_idle_time(&data);
+        self.data = Some(data);
+        let since_input =
+            Duration::from_millis(since_input.inspect_err(|e| error!("Failed getting idle {e}"))?);
+        let idle = self
+            .idle_sensitivity
+            .is_idle(since_input, self.idle_timeout);
+        Ok(if idle { since_input } else { Duration::ZERO })
+    }
+
+    /// X11 has no notion of a lock screen, so an active screensaver is reported instead.
+    /// Without MIT-SCREEN-SAVER the screen is reported as unlocked.
+    #[instrument(skip(self))]
+    fn is_screen_locked(&mut self) -> Result<bool> {
+        let data = self
+            .try_get_data()
+            .inspect_err(|e| error!("Failed getting connection {e:?}"))?;
+        if !data.has_screensaver {
+            self.data = Some(data);
+            return Ok(false);
+        }
+        let info = data
+            .get_screensaver_info()
+            .inspect_err(|e| error!("Failed getting screensaver state {e}"));
+        self.data = Some(data);
+        Ok(info?.state() == State::On as u8)
+    }
+
+    fn set_idle_timeout(&mut self, timeout: Duration) -> Result<()> {
+        self.idle_timeout = timeout;
+        Ok(())
+    }
+
+    fn get_app_icon(&mut self, app_id: &str) -> Result<Option<IconRef>> {
+        Ok(lock_desktop_info(&self.desktop_info).app_icon(app_id))
+    }
+
+    /// The window and the idle time are read over the same connection.
+    #[instrument(name = "x11::sample", skip(self))]
+    fn sample(&mut self) -> Result<Sample> {
+        let timestamp = Utc::now();
+        let data = self
+            .try_get_data()
+            .inspect_err(|e| error!("Failed getting connection {e:?}"))?;
+        let window = data.get_active_inner(&self.desktop_info);
+        let since_input = self
+            .get_idle_time(&data)
+            .inspect_err(|e| error!("Failed getting idle {e}"));
+        self.data = Some(data);
+        let window = window?;
+        let since_input = Duration::from_millis(since_input?);
+        let idle = self
+            .idle_sensitivity
+            .is_idle(since_input, self.idle_timeout);
+        Ok(Sample {
+            window,
+            idle,
+            idle_duration: if idle { since_input } else { Duration::ZERO },
+            timestamp,
+        })
     }
 }